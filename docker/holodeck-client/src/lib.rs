@@ -0,0 +1,583 @@
+//! Typed async client for the holodeck API.
+
+pub use holodeck_models::{backup, characters, lifecycle, models, rooms, safety, validation};
+
+use safety::{Violation, Violations};
+use validation::{FieldError, ValidationErrors};
+
+use backup::Archive;
+use characters::{Character, NewCharacter};
+use lifecycle::Action;
+use models::{ApiError, NewName, Simulation};
+use rooms::{NewReservation, Reservation, Room};
+use reqwest::header::{HeaderMap, HeaderValue, ACCEPT};
+use reqwest::{Method, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+
+/// What went wrong talking to the server. The status variants mirror the codes the server answers with.
+#[derive(Debug)]
+pub enum Error {
+    /// 400: the body was rejected, or the simulation or room already exists.
+    BadRequest(String),
+    /// 403: the request breaks the server's safety rules, with every unmet requirement.
+    Forbidden(Vec<Violation>),
+    /// 404: there is no such simulation, character, room or reservation.
+    NotFound(String),
+    /// 409: the simulation's state does not allow the change, or the room is already booked.
+    Conflict(String),
+    /// 413: the body is over the server's size limit.
+    PayloadTooLarge(String),
+    /// 415: the body was not sent as JSON.
+    UnsupportedMediaType(String),
+    /// 422: the payload failed validation, with every problem found.
+    Unprocessable(Vec<FieldError>),
+    /// 5xx, still failing after every retry.
+    Server(StatusCode, String),
+    /// Any other status.
+    Unexpected(StatusCode, String),
+    /// No answer within the configured timeout, still failing after every retry.
+    Timeout,
+    /// The request could not be sent or the answer could not be read.
+    Transport(reqwest::Error),
+}
+
+impl Error {
+    /// The HTTP status the server answered with, if it answered at all.
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            Error::BadRequest(_) => Some(StatusCode::BAD_REQUEST),
+            Error::Forbidden(_) => Some(StatusCode::FORBIDDEN),
+            Error::NotFound(_) => Some(StatusCode::NOT_FOUND),
+            Error::Conflict(_) => Some(StatusCode::CONFLICT),
+            Error::PayloadTooLarge(_) => Some(StatusCode::PAYLOAD_TOO_LARGE),
+            Error::UnsupportedMediaType(_) => Some(StatusCode::UNSUPPORTED_MEDIA_TYPE),
+            Error::Unprocessable(_) => Some(StatusCode::UNPROCESSABLE_ENTITY),
+            Error::Server(status, _) | Error::Unexpected(status, _) => Some(*status),
+            Error::Timeout | Error::Transport(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::BadRequest(message)
+            | Error::NotFound(message)
+            | Error::Conflict(message)
+            | Error::PayloadTooLarge(message)
+            | Error::UnsupportedMediaType(message)
+            | Error::Server(_, message)
+            | Error::Unexpected(_, message) => {
+                write!(f, "{}: {}", self.status().unwrap(), message)
+            }
+            Error::Unprocessable(errors) => {
+                write!(f, "{}:", self.status().unwrap())?;
+                for error in errors {
+                    write!(f, " {} {};", error.field, error.message)?;
+                }
+                Ok(())
+            }
+            Error::Forbidden(violations) => {
+                write!(f, "{}:", self.status().unwrap())?;
+                for violation in violations {
+                    write!(f, " {} {};", violation.rule, violation.message)?;
+                }
+                Ok(())
+            }
+            Error::Timeout => write!(f, "the server did not answer in time"),
+            Error::Transport(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Transport(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            Error::Timeout
+        } else {
+            Error::Transport(e)
+        }
+    }
+}
+
+/// Configures a `Client`.
+#[derive(Clone, Debug)]
+pub struct Builder {
+    base_url: String,
+    timeout: Duration,
+    retries: u32,
+    backoff: Duration,
+    headers: HeaderMap,
+}
+
+impl Builder {
+    /// Time allowed for each attempt, including reading the answer. Defaults to 10 seconds.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// How many times a failed request is tried again. Defaults to 2.
+    /// Only timeouts, connection failures and 5xx answers are retried, and never for `create`.
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Wait before the first retry; it doubles for every one after. Defaults to 100ms.
+    pub fn backoff(mut self, backoff: Duration) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Who the requests are made by, sent as `X-Holodeck-User` for the server's safety rules.
+    pub fn user(self, user: &str) -> Self {
+        self.header("x-holodeck-user", &[user])
+    }
+
+    /// Roles of the user, sent as `X-Holodeck-Roles`.
+    pub fn roles(self, roles: &[&str]) -> Self {
+        self.header("x-holodeck-roles", roles)
+    }
+
+    /// People who approved the requests, sent as `X-Holodeck-Approved-By`.
+    pub fn approved_by(self, approvers: &[&str]) -> Self {
+        self.header("x-holodeck-approved-by", approvers)
+    }
+
+    // Values that are not valid in a header are left out, and the server then sees no such value
+    fn header(mut self, name: &'static str, values: &[&str]) -> Self {
+        match HeaderValue::from_str(&values.join(",")) {
+            Ok(value) => self.headers.insert(name, value),
+            Err(_) => self.headers.remove(name),
+        };
+        self
+    }
+
+    pub fn build(mut self) -> Result<Client, Error> {
+        // Everything is decoded as JSON; some answers are plain text unless JSON is asked for
+        self.headers.insert(ACCEPT, HeaderValue::from_static("application/json"));
+        let http = reqwest::Client::builder()
+            .timeout(self.timeout)
+            .default_headers(self.headers)
+            .build()?;
+        Ok(Client {
+            base_url: self.base_url.trim_end_matches('/').to_string(),
+            http,
+            retries: self.retries,
+            backoff: self.backoff,
+        })
+    }
+}
+
+/// Client for a holodeck server, e.g. `Client::new("http://localhost:3030")`.
+#[derive(Clone, Debug)]
+pub struct Client {
+    base_url: String,
+    http: reqwest::Client,
+    retries: u32,
+    backoff: Duration,
+}
+
+impl Client {
+    pub fn new(base_url: impl Into<String>) -> Result<Client, Error> {
+        Client::builder(base_url).build()
+    }
+
+    pub fn builder(base_url: impl Into<String>) -> Builder {
+        Builder {
+            base_url: base_url.into(),
+            timeout: Duration::from_secs(10),
+            retries: 2,
+            backoff: Duration::from_millis(100),
+            headers: HeaderMap::new(),
+        }
+    }
+
+    pub async fn list(&self) -> Result<Vec<Simulation>, Error> {
+        let response = self.send(Method::GET, "/holodeck", None::<&()>).await?;
+        decode(response).await
+    }
+
+    /// Like `list`, with every simulation's characters.
+    pub async fn list_with_characters(&self) -> Result<Vec<Simulation>, Error> {
+        let response = self.send(Method::GET, "/holodeck?expand=characters", None::<&()>).await?;
+        decode(response).await
+    }
+
+    /// Simulations carrying every one of `tags`; a tag starting with `!` must be missing instead,
+    /// e.g. `list_tagged(&["noir", "!combat"])`.
+    pub async fn list_tagged(&self, tags: &[&str]) -> Result<Vec<Simulation>, Error> {
+        // Only used to encode the query string
+        let mut url = reqwest::Url::parse("http://holodeck/holodeck").unwrap();
+        url.query_pairs_mut().extend_pairs(tags.iter().map(|tag| ("tag", tag)));
+        let path = format!("{}?{}", url.path(), url.query().unwrap_or_default());
+
+        let response = self.send(Method::GET, &path, None::<&()>).await?;
+        decode(response).await
+    }
+
+    /// Every tag in use, with how many simulations carry it.
+    pub async fn tags(&self) -> Result<BTreeMap<String, usize>, Error> {
+        let response = self.send(Method::GET, "/tags", None::<&()>).await?;
+        decode(response).await
+    }
+
+    pub async fn get(&self, id: u64) -> Result<Simulation, Error> {
+        let response = self.send(Method::GET, &format!("/holodeck/{}", id), None::<&()>).await?;
+        decode(response).await
+    }
+
+    /// Returns the simulation as stored, with the timestamps the server gave it.
+    pub async fn create(&self, sim: &Simulation) -> Result<Simulation, Error> {
+        let response = self.send(Method::POST, "/holodeck", Some(sim)).await?;
+        decode(response).await
+    }
+
+    /// Renames the simulation, creating it when it does not exist yet.
+    pub async fn update(&self, id: u64, name: &str) -> Result<Simulation, Error> {
+        self.update_with(id, &NewName::new(name)).await
+    }
+
+    /// Like `update`, also replacing whatever metadata `new` carries.
+    pub async fn update_with(&self, id: u64, new: &NewName) -> Result<Simulation, Error> {
+        let response = self.send(Method::PUT, &format!("/holodeck/{}", id), Some(new)).await?;
+        decode(response).await
+    }
+
+    pub async fn delete(&self, id: u64) -> Result<(), Error> {
+        self.send(Method::DELETE, &format!("/holodeck/{}", id), None::<&()>).await?;
+        Ok(())
+    }
+
+    /// Runs, pauses, resumes or ends the simulation; answers with it in its new state.
+    pub async fn transition(&self, id: u64, action: Action) -> Result<Simulation, Error> {
+        let path = format!("/holodeck/{}/{}", id, action);
+        let response = self.send(Method::POST, &path, None::<&()>).await?;
+        decode(response).await
+    }
+
+    pub async fn characters(&self, id: u64) -> Result<Vec<Character>, Error> {
+        let response = self.send(Method::GET, &format!("/holodeck/{}/characters", id), None::<&()>).await?;
+        decode(response).await
+    }
+
+    /// Answers with the character as stored, with the id the server gave it.
+    pub async fn add_character(&self, id: u64, new: &NewCharacter) -> Result<Character, Error> {
+        let response = self.send(Method::POST, &format!("/holodeck/{}/characters", id), Some(new)).await?;
+        decode(response).await
+    }
+
+    pub async fn update_character(&self, id: u64, character: u64, new: &NewCharacter) -> Result<Character, Error> {
+        let path = format!("/holodeck/{}/characters/{}", id, character);
+        let response = self.send(Method::PUT, &path, Some(new)).await?;
+        decode(response).await
+    }
+
+    pub async fn delete_character(&self, id: u64, character: u64) -> Result<(), Error> {
+        let path = format!("/holodeck/{}/characters/{}", id, character);
+        self.send(Method::DELETE, &path, None::<&()>).await?;
+        Ok(())
+    }
+
+    /// The catalogue as it is at this instant; takes the admin role.
+    pub async fn backup(&self) -> Result<Archive, Error> {
+        let response = self.send(Method::POST, "/admin/backup", None::<&()>).await?;
+        decode(response).await
+    }
+
+    /// Swaps the whole catalogue for the one archived, in one write; takes the admin role.
+    pub async fn restore(&self, archive: &Archive) -> Result<(), Error> {
+        self.send(Method::POST, "/admin/restore", Some(archive)).await?;
+        Ok(())
+    }
+
+    pub async fn rooms(&self) -> Result<Vec<Room>, Error> {
+        let response = self.send(Method::GET, "/rooms", None::<&()>).await?;
+        decode(response).await
+    }
+
+    pub async fn create_room(&self, room: &Room) -> Result<(), Error> {
+        self.send(Method::POST, "/rooms", Some(room)).await?;
+        Ok(())
+    }
+
+    pub async fn delete_room(&self, id: u64) -> Result<(), Error> {
+        self.send(Method::DELETE, &format!("/rooms/{}", id), None::<&()>).await?;
+        Ok(())
+    }
+
+    /// The room's reservations that have not ended yet, earliest first.
+    pub async fn schedule(&self, room: u64) -> Result<Vec<Reservation>, Error> {
+        let response = self.send(Method::GET, &format!("/rooms/{}/reservations", room), None::<&()>).await?;
+        decode(response).await
+    }
+
+    /// Answers with the reservation made, or `Error::Conflict` when the window is taken.
+    pub async fn book(&self, room: u64, new: &NewReservation) -> Result<Reservation, Error> {
+        let response = self.send(Method::POST, &format!("/rooms/{}/reservations", room), Some(new)).await?;
+        decode(response).await
+    }
+
+    pub async fn cancel(&self, room: u64, reservation: u64) -> Result<(), Error> {
+        let path = format!("/rooms/{}/reservations/{}", room, reservation);
+        self.send(Method::DELETE, &path, None::<&()>).await?;
+        Ok(())
+    }
+
+    async fn send<T: Serialize>(&self, method: Method, path: &str, body: Option<&T>) -> Result<Response, Error> {
+        // A POST that timed out may still have created the simulation
+        let retries = if method == Method::POST { 0 } else { self.retries };
+        let url = format!("{}{}", self.base_url, path);
+
+        let mut attempt = 0;
+        loop {
+            let mut request = self.http.request(method.clone(), &url);
+            if let Some(body) = body {
+                request = request.json(body);
+            }
+
+            let result = request.send().await;
+            let retry = match &result {
+                Ok(response) => response.status().is_server_error(),
+                Err(e) => e.is_timeout() || e.is_connect(),
+            };
+            if retry && attempt < retries {
+                tokio::time::sleep(self.backoff * 2u32.pow(attempt)).await;
+                attempt += 1;
+                continue;
+            }
+
+            return check(result?).await;
+        }
+    }
+}
+
+async fn check(response: Response) -> Result<Response, Error> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let mut message = response.text().await.unwrap_or_default().trim_end().to_string();
+    // Most errors come as plain text, some as an `ApiError`
+    if let Ok(error) = serde_json::from_str::<ApiError>(&message) {
+        message = error.message;
+    }
+    if status == StatusCode::UNPROCESSABLE_ENTITY {
+        if let Ok(invalid) = serde_json::from_str::<ValidationErrors>(&message) {
+            return Err(Error::Unprocessable(invalid.errors));
+        }
+    }
+    if status == StatusCode::FORBIDDEN {
+        if let Ok(forbidden) = serde_json::from_str::<Violations>(&message) {
+            return Err(Error::Forbidden(forbidden.violations));
+        }
+    }
+
+    Err(match status {
+        StatusCode::BAD_REQUEST => Error::BadRequest(message),
+        StatusCode::NOT_FOUND => Error::NotFound(message),
+        StatusCode::CONFLICT => Error::Conflict(message),
+        StatusCode::PAYLOAD_TOO_LARGE => Error::PayloadTooLarge(message),
+        StatusCode::UNSUPPORTED_MEDIA_TYPE => Error::UnsupportedMediaType(message),
+        status if status.is_server_error() => Error::Server(status, message),
+        status => Error::Unexpected(status, message),
+    })
+}
+
+async fn decode<T: DeserializeOwned>(response: Response) -> Result<T, Error> {
+    Ok(response.json().await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::Infallible;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use warp::Filter;
+
+    fn serve<F>(routes: F) -> String
+    where
+        F: Filter + Clone + Send + Sync + 'static,
+        F::Extract: warp::Reply,
+    {
+        let (addr, server) = warp::serve(routes).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        format!("http://{}", addr)
+    }
+
+    fn sim(id: u64, name: &str) -> Simulation {
+        Simulation::new(id, name)
+    }
+
+    #[tokio::test]
+    async fn try_crud() {
+        let client = Client::new(serve(holodeck::api())).unwrap();
+
+        client.create(&sim(1, "The Big Goodbye")).await.unwrap();
+        client.create(&sim(2, "Bride Of Chaotica!")).await.unwrap();
+
+        let mut sims = client.list().await.unwrap();
+        sims.sort_by_key(|sim| sim.id);
+        assert_eq!(sims.len(), 2);
+        assert_eq!(sims[1].name, "Bride Of Chaotica!");
+
+        for (id, name, tags) in [(1, "The Big Goodbye", vec!["noir", "combat"]), (2, "Bride Of Chaotica!", vec!["noir"])] {
+            let mut tagged = NewName::new(name);
+            tagged.tags = Some(tags.into_iter().map(String::from).collect());
+            client.update_with(id, &tagged).await.unwrap();
+        }
+        assert_eq!(client.tags().await.unwrap()["noir"], 2);
+        let found = client.list_tagged(&["noir", "!combat"]).await.unwrap();
+        assert_eq!(found.iter().map(|sim| sim.id).collect::<Vec<_>>(), [2]);
+
+        let renamed = client.update(1, "The Short Hello").await.unwrap();
+        assert_eq!(renamed.name, "The Short Hello");
+        assert!(renamed.updated_at.is_some());
+
+        let mut details = NewName::new("The Short Hello");
+        details.author = Some("Jean-Luc Picard".to_string());
+        let described = client.update_with(1, &details).await.unwrap();
+        assert_eq!(described.author.as_deref(), Some("Jean-Luc Picard"));
+        assert_eq!(described.created_at, renamed.created_at);
+
+        let dixon = client.add_character(1, &NewCharacter::new("Dixon Hill", "Detective")).await.unwrap();
+        let dixon = client.update_character(1, dixon.id, &NewCharacter::new("Dixon Hill", "Private eye")).await.unwrap();
+        assert_eq!(client.characters(1).await.unwrap(), std::slice::from_ref(&dixon));
+        assert!(client.get(1).await.unwrap().characters.is_empty());
+        let expanded = client.list_with_characters().await.unwrap();
+        assert_eq!(expanded.iter().find(|sim| sim.id == 1).unwrap().characters, std::slice::from_ref(&dixon));
+        client.delete_character(1, dixon.id).await.unwrap();
+        assert!(matches!(client.delete_character(1, dixon.id).await, Err(Error::NotFound(_))));
+
+        let running = client.transition(1, Action::Run).await.unwrap();
+        assert_eq!(running.state, lifecycle::State::Running);
+        assert!(matches!(client.transition(1, Action::Resume).await, Err(Error::Conflict(_))));
+
+        client.delete(1).await.unwrap();
+        match client.get(1).await {
+            Err(Error::NotFound(_)) => {}
+            other => panic!("expected NotFound, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn try_status_errors() {
+        let client = Client::new(serve(holodeck::api())).unwrap();
+
+        client.create(&sim(1, "The Big Goodbye")).await.unwrap();
+        let error = client.create(&sim(1, "The Big Goodbye")).await.unwrap_err();
+        assert_eq!(error.status(), Some(StatusCode::BAD_REQUEST));
+        assert!(error.to_string().contains("already exists"));
+
+        let error = client.create(&sim(2, &"x".repeat(32 * 1024))).await.unwrap_err();
+        assert!(matches!(error, Error::PayloadTooLarge(_)));
+
+        match client.update(0, "").await.unwrap_err() {
+            Error::Unprocessable(errors) => {
+                let fields: Vec<&str> = errors.iter().map(|e| e.field.as_str()).collect();
+                assert_eq!(fields, ["id", "name"]);
+            }
+            other => panic!("expected Unprocessable, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn try_safety() {
+        let url = serve(holodeck::api());
+        let mut unsafe_sim = sim(1, "The Big Goodbye");
+        unsafe_sim.safety.protocols = false;
+
+        let client = Client::builder(url.as_str()).user("barclay").build().unwrap();
+        match client.create(&unsafe_sim).await.unwrap_err() {
+            Error::Forbidden(violations) => assert_eq!(violations.len(), 2),
+            other => panic!("expected Forbidden, got {:?}", other),
+        }
+
+        let client = Client::builder(url.as_str())
+            .user("picard")
+            .roles(&["admin"])
+            .approved_by(&["riker", "troi"])
+            .build()
+            .unwrap();
+        assert!(!client.create(&unsafe_sim).await.unwrap().safety.protocols);
+        client.transition(1, Action::Run).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn try_rooms() {
+        use chrono::{Duration as Hours, Utc};
+
+        let client = Client::new(serve(holodeck::api())).unwrap();
+        client.create(&sim(1, "The Big Goodbye")).await.unwrap();
+        client.create_room(&Room { id: 2, name: "Holodeck 2".to_string() }).await.unwrap();
+        assert_eq!(client.rooms().await.unwrap().len(), 1);
+
+        let start = Utc::now() + Hours::hours(1);
+        let booking = NewReservation { simulation: 1, start, end: start + Hours::hours(2) };
+        let reservation = client.book(2, &booking).await.unwrap();
+        assert!(matches!(client.book(2, &booking).await, Err(Error::Conflict(_))));
+        assert_eq!(client.schedule(2).await.unwrap(), std::slice::from_ref(&reservation));
+
+        client.cancel(2, reservation.id).await.unwrap();
+        assert!(client.schedule(2).await.unwrap().is_empty());
+        client.delete_room(2).await.unwrap();
+        assert!(matches!(client.schedule(2).await, Err(Error::NotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn try_retry() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let flaky = {
+            let calls = calls.clone();
+            warp::path!("holodeck").map(move || {
+                if calls.fetch_add(1, Ordering::SeqCst) < 2 {
+                    warp::reply::with_status(warp::reply::json(&()), StatusCode::SERVICE_UNAVAILABLE)
+                } else {
+                    warp::reply::with_status(warp::reply::json(&vec![sim(1, "The Big Goodbye")]), StatusCode::OK)
+                }
+            })
+        };
+        let url = serve(flaky);
+
+        let client = Client::builder(url.as_str()).backoff(Duration::from_millis(1)).build().unwrap();
+        assert_eq!(client.list().await.unwrap().len(), 1);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        calls.store(0, Ordering::SeqCst);
+        let client = Client::builder(url.as_str()).retries(0).build().unwrap();
+        let error = client.list().await.unwrap_err();
+        assert_eq!(error.status(), Some(StatusCode::SERVICE_UNAVAILABLE));
+    }
+
+    #[tokio::test]
+    async fn try_timeout() {
+        let slow = warp::path!("holodeck").and_then(|| async {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            Ok::<_, Infallible>(warp::reply::json(&Vec::<Simulation>::new()))
+        });
+
+        let client = Client::builder(serve(slow))
+            .timeout(Duration::from_millis(50))
+            .retries(1)
+            .backoff(Duration::from_millis(1))
+            .build()
+            .unwrap();
+        assert!(matches!(client.list().await, Err(Error::Timeout)));
+    }
+}
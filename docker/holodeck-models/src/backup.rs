@@ -0,0 +1,141 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeSet;
+use std::fmt;
+
+use crate::models::{Simulation, SCHEMA_VERSION};
+use crate::validation::Validate;
+
+/// Layout of the archives written by this version.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// A tenant's catalogue as of one instant, as made by `POST /admin/backup` and taken by `POST /admin/restore`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Archive {
+    pub format_version: u32,
+    /// `SCHEMA_VERSION` of the server that made it; older simulations are brought up to date on restore.
+    pub schema_version: u32,
+    pub created_at: DateTime<Utc>,
+    pub count: usize,
+    /// Hex SHA-256 of `simulations` as compact JSON.
+    pub checksum: String,
+    /// Kept as JSON so the checksum is checked against exactly what was archived.
+    pub simulations: serde_json::Value,
+}
+
+/// Why an archive cannot be restored.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Error {
+    UnsupportedFormat(u32),
+    NewerSchema(u32),
+    Checksum,
+    Count { expected: usize, found: usize },
+    Undecodable(String),
+    Invalid(u64),
+    Duplicate(u64),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::UnsupportedFormat(version) => write!(f, "Archive format {} is not supported; this server reads format {}.", version, FORMAT_VERSION),
+            Error::NewerSchema(version) => write!(f, "The archive holds schema version {}, newer than this server's {}.", version, SCHEMA_VERSION),
+            Error::Checksum => write!(f, "The archive does not match its checksum."),
+            Error::Count { expected, found } => write!(f, "The archive says it holds {} simulation(s) but holds {}.", expected, found),
+            Error::Undecodable(e) => write!(f, "The archived simulations cannot be read: {}", e),
+            Error::Invalid(id) => write!(f, "Archived simulation #{} is invalid.", id),
+            Error::Duplicate(id) => write!(f, "Simulation #{} is archived more than once.", id),
+        }
+    }
+}
+
+fn checksum(simulations: &serde_json::Value) -> String {
+    // Serializing a `Value` cannot fail
+    let bytes = serde_json::to_vec(simulations).unwrap_or_default();
+    format!("{:x}", Sha256::digest(&bytes))
+}
+
+impl Archive {
+    /// Simulations by id, so the same catalogue always makes the same archive, timestamp aside.
+    pub fn of<'a>(sims: impl IntoIterator<Item = &'a Simulation>) -> Archive {
+        let mut sims: Vec<&Simulation> = sims.into_iter().collect();
+        sims.sort_by_key(|sim| sim.id);
+        let simulations = serde_json::to_value(&sims).unwrap_or_default();
+        Archive {
+            format_version: FORMAT_VERSION,
+            schema_version: SCHEMA_VERSION,
+            created_at: Utc::now(),
+            count: sims.len(),
+            checksum: checksum(&simulations),
+            simulations,
+        }
+    }
+
+    /// The simulations, once every check has passed.
+    pub fn open(self) -> Result<Vec<Simulation>, Error> {
+        if self.format_version != FORMAT_VERSION {
+            return Err(Error::UnsupportedFormat(self.format_version));
+        }
+        if self.schema_version > SCHEMA_VERSION {
+            return Err(Error::NewerSchema(self.schema_version));
+        }
+        if checksum(&self.simulations) != self.checksum.to_ascii_lowercase() {
+            return Err(Error::Checksum);
+        }
+
+        let sims: Vec<Simulation> = serde_json::from_value(self.simulations).map_err(|e| Error::Undecodable(e.to_string()))?;
+        if sims.len() != self.count {
+            return Err(Error::Count {
+                expected: self.count,
+                found: sims.len(),
+            });
+        }
+        let mut ids = BTreeSet::new();
+        let mut upgraded = Vec::with_capacity(sims.len());
+        for mut sim in sims {
+            if !ids.insert(sim.id) {
+                return Err(Error::Duplicate(sim.id));
+            }
+            sim.validate().map_err(|_| Error::Invalid(sim.id))?;
+            sim.schema_version = SCHEMA_VERSION;
+            upgraded.push(sim);
+        }
+        Ok(upgraded)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn try_archive() {
+        let sims = [Simulation::new(2, "Fair Haven"), Simulation::new(1, "The Big Goodbye")];
+        let archive = Archive::of(&sims);
+        assert_eq!(archive.count, 2);
+        let sims = archive.clone().open().unwrap();
+        assert_eq!(sims.iter().map(|sim| sim.name.as_str()).collect::<Vec<_>>(), ["The Big Goodbye", "Fair Haven"]);
+
+        let mut tampered = archive.clone();
+        tampered.simulations[0]["name"] = "The Long Hello".into();
+        assert_eq!(tampered.open(), Err(Error::Checksum));
+
+        let mut future = archive.clone();
+        future.schema_version = SCHEMA_VERSION + 1;
+        assert_eq!(future.open(), Err(Error::NewerSchema(SCHEMA_VERSION + 1)));
+
+        let mut miscounted = archive.clone();
+        miscounted.count = 3;
+        assert_eq!(miscounted.open(), Err(Error::Count { expected: 3, found: 2 }));
+
+        // A consistent archive of bad data is still refused
+        let simulations = serde_json::json!([{ "id": 1, "name": "" }, { "id": 1, "name": "Fair Haven" }]);
+        let invalid = Archive {
+            checksum: checksum(&simulations),
+            simulations,
+            ..archive
+        };
+        assert_eq!(invalid.open(), Err(Error::Invalid(1)));
+    }
+}
@@ -0,0 +1,91 @@
+use serde::{Deserialize, Serialize};
+
+use super::models::Simulation;
+
+/// A holographic character of one simulation. Ids are given by the server and unique within the simulation.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Character {
+    pub id: u64,
+    pub name: String,
+    pub role: String,
+}
+
+/// Body of a POST or PUT to `/holodeck/{id}/characters`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct NewCharacter {
+    pub name: String,
+    pub role: String,
+}
+
+impl NewCharacter {
+    pub fn new(name: impl Into<String>, role: impl Into<String>) -> NewCharacter {
+        NewCharacter {
+            name: name.into(),
+            role: role.into(),
+        }
+    }
+}
+
+impl Simulation {
+    pub fn character(&self, id: u64) -> Option<&Character> {
+        self.characters.iter().find(|character| character.id == id)
+    }
+
+    pub fn add_character(&mut self, new: NewCharacter) -> Character {
+        // Ids stay ordered by creation, and one freed by a removal is not reused
+        let highest = self.characters.iter().map(|character| character.id).max().unwrap_or(0);
+        let id = self.next_character_id.max(highest + 1);
+        self.next_character_id = id + 1;
+        let character = Character {
+            id,
+            name: new.name,
+            role: new.role,
+        };
+        self.characters.push(character.clone());
+        character
+    }
+
+    pub fn update_character(&mut self, id: u64, new: NewCharacter) -> Option<Character> {
+        let character = self.characters.iter_mut().find(|character| character.id == id)?;
+        character.name = new.name;
+        character.role = new.role;
+        Some(character.clone())
+    }
+
+    pub fn remove_character(&mut self, id: u64) -> Option<Character> {
+        let index = self.characters.iter().position(|character| character.id == id)?;
+        Some(self.characters.remove(index))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn try_characters() {
+        let mut sim = Simulation::new(1, "The Big Goodbye");
+        let dixon = sim.add_character(NewCharacter::new("Dixon Hill", "Detective"));
+        let redblock = sim.add_character(NewCharacter::new("Cyrus Redblock", "Gangster"));
+        assert_eq!((dixon.id, redblock.id), (1, 2));
+
+        let updated = sim.update_character(2, NewCharacter::new("Cyrus Redblock", "Villain")).unwrap();
+        assert_eq!(sim.character(2), Some(&updated));
+        assert_eq!(sim.update_character(3, NewCharacter::new("Nobody", "Extra")), None);
+
+        assert_eq!(sim.remove_character(1), Some(dixon));
+        assert_eq!(sim.remove_character(1), None);
+        let leech = sim.add_character(NewCharacter::new("Felix Leech", "Henchman"));
+        assert_eq!(leech.id, 3);
+
+        // Deleting the newest character does not free its id either
+        assert_eq!(sim.remove_character(3), Some(leech));
+        assert_eq!(sim.add_character(NewCharacter::new("Jimmy", "Newsboy")).id, 4);
+
+        // The counter stays on the server; simulations read back pick up after their highest id
+        assert!(!serde_json::to_string(&sim).unwrap().contains("next_character_id"));
+        let mut stored: Simulation = serde_json::from_str(r#"{"id": 1, "name": "The Big Goodbye", "next_character_id": 2, "characters": [{"id": 7, "name": "Dixon Hill", "role": "Detective"}]}"#).unwrap();
+        assert_eq!(stored.next_character_id, 0);
+        assert_eq!(stored.add_character(NewCharacter::new("Jimmy", "Newsboy")).id, 8);
+    }
+}
@@ -0,0 +1,10 @@
+//! The payloads the holodeck server and its clients exchange, and the rules they are validated by.
+
+pub mod backup;
+pub mod characters;
+pub mod lifecycle;
+pub mod models;
+pub mod rooms;
+pub mod safety;
+pub mod tenants;
+pub mod validation;
@@ -0,0 +1,122 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// Where a simulation is in its lifecycle. New simulations are `Loaded`; `Ended` is final.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum State {
+    #[default]
+    Loaded,
+    Running,
+    Paused,
+    Ended,
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            State::Loaded => "loaded",
+            State::Running => "running",
+            State::Paused => "paused",
+            State::Ended => "ended",
+        };
+        f.write_str(name)
+    }
+}
+
+/// What can be done to a simulation, one endpoint each: `POST /holodeck/{id}/{action}`.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Run,
+    Pause,
+    Resume,
+    End,
+}
+
+pub const ACTIONS: [Action; 4] = [Action::Run, Action::Pause, Action::Resume, Action::End];
+
+impl Action {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Action::Run => "run",
+            Action::Pause => "pause",
+            Action::Resume => "resume",
+            Action::End => "end",
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Action {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ACTIONS.iter().copied().find(|action| action.as_str() == s).ok_or(())
+    }
+}
+
+/// A state change, kept in the simulation's history.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Transition {
+    pub action: Action,
+    pub from: State,
+    pub to: State,
+    pub at: DateTime<Utc>,
+}
+
+impl State {
+    /// The state `action` leads to, or `None` when it is not allowed from here.
+    pub fn next(self, action: Action) -> Option<State> {
+        match (self, action) {
+            (State::Loaded, Action::Run) => Some(State::Running),
+            (State::Running, Action::Pause) => Some(State::Paused),
+            (State::Paused, Action::Resume) => Some(State::Running),
+            (State::Running, Action::End) | (State::Paused, Action::End) => Some(State::Ended),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn try_transitions() {
+        let mut state = State::default();
+        for (action, expected) in [
+            (Action::Run, State::Running),
+            (Action::Pause, State::Paused),
+            (Action::Resume, State::Running),
+            (Action::Pause, State::Paused),
+            (Action::End, State::Ended),
+        ] {
+            state = state.next(action).unwrap();
+            assert_eq!(state, expected);
+        }
+
+        assert_eq!(State::Loaded.next(Action::Pause), None);
+        assert_eq!(State::Loaded.next(Action::End), None);
+        assert_eq!(State::Running.next(Action::Run), None);
+        assert_eq!(State::Running.next(Action::Resume), None);
+        for action in ACTIONS.iter() {
+            assert_eq!(State::Ended.next(*action), None);
+        }
+    }
+
+    #[test]
+    fn try_parse_action() {
+        for action in ACTIONS.iter() {
+            assert_eq!(action.as_str().parse(), Ok(*action));
+        }
+        assert_eq!("stop".parse::<Action>(), Err(()));
+    }
+}
@@ -0,0 +1,160 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::hash::{Hash, Hasher};
+
+use super::characters::Character;
+use super::lifecycle::{Action, State, Transition};
+use super::safety::Safety;
+
+/// Shape of the `Simulation` written by this version; payloads without `schema_version` are version 1 (`id` and `name` only).
+pub const SCHEMA_VERSION: u32 = 5;
+
+// Everything but `id` and `name` is optional so version 1 payloads still deserialize
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Simulation {
+    pub id: u64,
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub genre: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    #[serde(default)]
+    pub tags: BTreeSet<String>,
+    #[serde(default)]
+    pub safety: Safety,
+    // Maintained by the server; whatever the client sends is ignored
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<DateTime<Utc>>,
+    #[serde(default = "first_version")]
+    pub schema_version: u32,
+    // Also maintained by the server, through the lifecycle endpoints
+    #[serde(default)]
+    pub state: State,
+    #[serde(default)]
+    pub transitions: Vec<Transition>,
+    // Managed through `/holodeck/{id}/characters`, and only listed with `?expand=characters`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub characters: Vec<Character>,
+    // Server-side only: never handed out twice while the server runs, even after the character
+    // holding it is removed. Neither sent nor taken, so simulations loaded or restored start from
+    // their highest character id
+    #[serde(skip)]
+    pub next_character_id: u64,
+}
+
+fn first_version() -> u32 {
+    1
+}
+
+impl Simulation {
+    pub fn new(id: u64, name: impl Into<String>) -> Simulation {
+        Simulation {
+            id,
+            name: name.into(),
+            schema_version: SCHEMA_VERSION,
+            ..Default::default()
+        }
+    }
+
+    // Called on every write, so stored simulations are always in the current shape
+    pub fn touch(&mut self, now: DateTime<Utc>) {
+        self.created_at.get_or_insert(now);
+        self.updated_at = Some(now);
+        self.schema_version = SCHEMA_VERSION;
+    }
+
+    // Hands back the current state when `action` is not allowed from it
+    pub fn transition(&mut self, action: Action, now: DateTime<Utc>) -> Result<(), State> {
+        let to = self.state.next(action).ok_or(self.state)?;
+        self.transitions.push(Transition { action, from: self.state, to, at: now });
+        self.state = to;
+        self.touch(now);
+        Ok(())
+    }
+}
+
+/// Body of the errors answered in JSON, such as the 404 of `GET /holodeck/{id}`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ApiError {
+    /// The HTTP status code.
+    pub code: u16,
+    pub message: String,
+}
+
+/// Body of a PUT. Only `name` is required; metadata left out keeps its current value.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct NewName{
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub genre: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tags: Option<BTreeSet<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub safety: Option<Safety>,
+}
+
+impl NewName {
+    pub fn new(name: impl Into<String>) -> NewName {
+        NewName {
+            name: name.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn apply(self, sim: &mut Simulation) {
+        sim.name = self.name;
+        if self.description.is_some() {
+            sim.description = self.description;
+        }
+        if self.genre.is_some() {
+            sim.genre = self.genre;
+        }
+        if self.author.is_some() {
+            sim.author = self.author;
+        }
+        if let Some(tags) = self.tags {
+            sim.tags = tags;
+        }
+        if let Some(safety) = self.safety {
+            sim.safety = safety;
+        }
+    }
+}
+
+// Carries all of a simulation's metadata, e.g. to restore it from an export
+impl From<&Simulation> for NewName {
+    fn from(sim: &Simulation) -> Self {
+        NewName {
+            name: sim.name.clone(),
+            description: sim.description.clone(),
+            genre: sim.genre.clone(),
+            author: sim.author.clone(),
+            tags: Some(sim.tags.clone()),
+            safety: Some(sim.safety.clone()),
+        }
+    }
+}
+
+impl PartialEq for Simulation{
+    //https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for Simulation {}
+
+impl Hash for Simulation{
+    fn hash<H: Hasher>(&self, state: &mut H){
+            self.id.hash(state);
+    }
+}
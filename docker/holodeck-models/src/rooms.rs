@@ -0,0 +1,36 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// A physical holodeck simulations are booked into.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Room {
+    pub id: u64,
+    pub name: String,
+}
+
+/// A simulation from the catalogue booked into a room for `[start, end)`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Reservation {
+    pub id: u64,
+    pub room: u64,
+    pub simulation: u64,
+    /// Tenant whose catalogue `simulation` is in; left out for the default tenant.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+/// Body of `POST /rooms/{id}/reservations`; the id is given by the server.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct NewReservation {
+    pub simulation: u64,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+impl Reservation {
+    pub fn overlaps(&self, start: DateTime<Utc>, end: DateTime<Utc>) -> bool {
+        self.start < end && start < self.end
+    }
+}
@@ -0,0 +1,37 @@
+use serde::{Deserialize, Serialize};
+
+/// How a simulation's safety protocols are set. Protocols are on unless a client turns them off.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Safety {
+    #[serde(default = "enabled")]
+    pub protocols: bool,
+    /// Why the settings differ from the defaults, for whoever reviews them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+impl Default for Safety {
+    fn default() -> Self {
+        Safety {
+            protocols: true,
+            reason: None,
+        }
+    }
+}
+
+/// One unmet requirement of one of the server's safety rules.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Violation {
+    pub rule: String,
+    pub message: String,
+}
+
+/// Body of a 403 answer.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Violations {
+    pub violations: Vec<Violation>,
+}
@@ -0,0 +1,19 @@
+use serde::{Deserialize, Serialize};
+
+/// Body of a POST to `/admin/tenants`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct NewTenant {
+    pub name: String,
+    /// Most simulations the tenant may hold; no limit when left out.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quota: Option<usize>,
+}
+
+/// One of the tenants `GET /admin/tenants` lists.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Tenant {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quota: Option<usize>,
+    pub simulations: usize,
+}
@@ -0,0 +1,330 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+use super::characters::NewCharacter;
+use super::models::{NewName, Simulation};
+use super::rooms::{NewReservation, Room};
+use super::safety::Safety;
+use super::tenants::NewTenant;
+
+/// Longest name, genre or author accepted, in characters.
+pub const NAME_MAX_LEN: usize = 100;
+
+/// Longest description accepted, in characters.
+pub const DESCRIPTION_MAX_LEN: usize = 2000;
+
+/// Most tags a simulation can carry, and the longest tag accepted.
+pub const TAGS_MAX: usize = 20;
+pub const TAG_MAX_LEN: usize = 32;
+
+/// Longest tenant name accepted.
+pub const TENANT_MAX_LEN: usize = 32;
+
+/// Ids that can never be given to a simulation.
+pub const RESERVED_IDS: [u64; 1] = [0];
+
+/// One thing wrong with one field of a payload.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+/// Body of a 422 answer.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ValidationErrors {
+    pub errors: Vec<FieldError>,
+}
+
+pub trait Validate {
+    /// Reports every problem at once rather than stopping at the first.
+    fn validate(&self) -> Result<(), ValidationErrors>;
+}
+
+impl Validate for Simulation {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = Vec::new();
+        check_id(self.id, &mut errors);
+        check_name("name", &self.name, &mut errors);
+        check_metadata(&self.description, &self.genre, &self.author, Some(&self.tags), &mut errors);
+        check_safety(&self.safety, &mut errors);
+        finish(errors)
+    }
+}
+
+impl Validate for NewName {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = Vec::new();
+        check_name("name", &self.name, &mut errors);
+        check_metadata(&self.description, &self.genre, &self.author, self.tags.as_ref(), &mut errors);
+        if let Some(safety) = &self.safety {
+            check_safety(safety, &mut errors);
+        }
+        finish(errors)
+    }
+}
+
+/// A PUT to `/holodeck/{id}`: the id from the path and the body are reported together.
+pub fn validate_update(id: u64, new: &NewName) -> Result<(), ValidationErrors> {
+    let mut errors = Vec::new();
+    check_id(id, &mut errors);
+    if let Err(invalid) = new.validate() {
+        errors.extend(invalid.errors);
+    }
+    finish(errors)
+}
+
+impl Validate for NewCharacter {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = Vec::new();
+        check_name("name", &self.name, &mut errors);
+        check_name("role", &self.role, &mut errors);
+        finish(errors)
+    }
+}
+
+impl Validate for Room {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = Vec::new();
+        check_id(self.id, &mut errors);
+        check_name("name", &self.name, &mut errors);
+        finish(errors)
+    }
+}
+
+impl Validate for NewReservation {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = Vec::new();
+        check_id(self.simulation, &mut errors);
+        if self.end <= self.start {
+            errors.push(error("end", "must be after start".to_string()));
+        }
+        finish(errors)
+    }
+}
+
+// Tenant names go in a header and in paths, so they follow the same rules as tags
+impl Validate for NewTenant {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = Vec::new();
+        if !is_slug(&self.name, TENANT_MAX_LEN) {
+            errors.push(error(
+                "name",
+                format!("{:?} must be 1 to {} lowercase letters, digits or dashes", self.name, TENANT_MAX_LEN),
+            ));
+        }
+        finish(errors)
+    }
+}
+
+/// A booking is also checked against the clock and the catalogue, which `Validate` cannot see.
+pub fn validate_booking(new: &NewReservation, now: DateTime<Utc>, in_catalogue: bool) -> Result<(), ValidationErrors> {
+    let mut errors = match new.validate() {
+        Ok(()) => Vec::new(),
+        Err(invalid) => invalid.errors,
+    };
+    if !in_catalogue {
+        errors.push(error("simulation", format!("#{} is not in the catalogue", new.simulation)));
+    }
+    if new.end <= now {
+        errors.push(error("end", "must be in the future".to_string()));
+    }
+    finish(errors)
+}
+
+fn check_id(id: u64, errors: &mut Vec<FieldError>) {
+    if RESERVED_IDS.contains(&id) {
+        errors.push(error("id", format!("{} is reserved", id)));
+    }
+}
+
+fn check_metadata(
+    description: &Option<String>,
+    genre: &Option<String>,
+    author: &Option<String>,
+    tags: Option<&BTreeSet<String>>,
+    errors: &mut Vec<FieldError>,
+) {
+    if let Some(description) = description {
+        check_text("description", description, errors);
+    }
+    if let Some(genre) = genre {
+        check_name("genre", genre, errors);
+    }
+    if let Some(author) = author {
+        check_name("author", author, errors);
+    }
+    if let Some(tags) = tags {
+        check_tags(tags, errors);
+    }
+}
+
+fn check_name(field: &str, name: &str, errors: &mut Vec<FieldError>) {
+    if name.trim().is_empty() {
+        errors.push(error(field, "must not be blank".to_string()));
+        return;
+    }
+    if name.trim() != name {
+        errors.push(error(field, "must not start or end with whitespace".to_string()));
+    }
+    check_len(field, name, NAME_MAX_LEN, errors);
+
+    // Letters and digits of any script, plain spaces and ASCII punctuation; no control or invisible characters
+    if let Some(c) = name
+        .chars()
+        .find(|c| !(c.is_alphanumeric() || *c == ' ' || c.is_ascii_punctuation()))
+    {
+        errors.push(error(field, format!("must not contain {:?}", c)));
+    }
+}
+
+fn check_safety(safety: &Safety, errors: &mut Vec<FieldError>) {
+    if let Some(reason) = &safety.reason {
+        check_text("safety.reason", reason, errors);
+    }
+}
+
+// Free text, so line breaks are fine; other control characters are not
+fn check_text(field: &str, text: &str, errors: &mut Vec<FieldError>) {
+    check_len(field, text, DESCRIPTION_MAX_LEN, errors);
+    if let Some(c) = text.chars().find(|c| c.is_control() && *c != '\n') {
+        errors.push(error(field, format!("must not contain {:?}", c)));
+    }
+}
+
+// Tags end up in query strings, so they stay short, lowercase and URL safe
+fn check_tags(tags: &BTreeSet<String>, errors: &mut Vec<FieldError>) {
+    if tags.len() > TAGS_MAX {
+        errors.push(error("tags", format!("must hold at most {} tags, got {}", TAGS_MAX, tags.len())));
+    }
+    for tag in tags {
+        if !is_slug(tag, TAG_MAX_LEN) {
+            errors.push(error(
+                "tags",
+                format!("{:?} must be 1 to {} lowercase letters, digits or dashes", tag, TAG_MAX_LEN),
+            ));
+        }
+    }
+}
+
+fn is_slug(value: &str, max: usize) -> bool {
+    !value.is_empty() && value.len() <= max && value.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+}
+
+fn check_len(field: &str, value: &str, max: usize, errors: &mut Vec<FieldError>) {
+    let len = value.chars().count();
+    if len > max {
+        errors.push(error(field, format!("must be at most {} characters long, got {}", max, len)));
+    }
+}
+
+fn error(field: &str, message: String) -> FieldError {
+    FieldError {
+        field: field.to_string(),
+        message,
+    }
+}
+
+fn finish(errors: Vec<FieldError>) -> Result<(), ValidationErrors> {
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(ValidationErrors { errors })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(sim: Simulation) -> Vec<String> {
+        match sim.validate() {
+            Ok(()) => Vec::new(),
+            Err(invalid) => invalid.errors.into_iter().map(|e| format!("{}: {}", e.field, e.message)).collect(),
+        }
+    }
+
+    fn sim(id: u64, name: &str) -> Simulation {
+        Simulation::new(id, name)
+    }
+
+    #[test]
+    fn try_validate() {
+        assert!(messages(sim(1, "The Big Goodbye!")).is_empty());
+        assert!(messages(sim(2, "Ça, c'est Québec (1759)")).is_empty());
+
+        assert_eq!(messages(sim(0, "")), ["id: 0 is reserved", "name: must not be blank"]);
+        assert_eq!(messages(sim(1, " \t ")), ["name: must not be blank"]);
+        assert_eq!(messages(sim(1, " Padded")), ["name: must not start or end with whitespace"]);
+        assert_eq!(messages(sim(1, "Bell\u{7}")), ["name: must not contain '\\u{7}'"]);
+        assert_eq!(messages(sim(1, "Tab\tbed")), ["name: must not contain '\\t'"]);
+        assert_eq!(
+            messages(sim(1, &"x".repeat(NAME_MAX_LEN + 1))),
+            ["name: must be at most 100 characters long, got 101"]
+        );
+
+        assert!(NewName::new("Fair Haven").validate().is_ok());
+        assert!(NewName::new("").validate().is_err());
+        assert_eq!(validate_update(0, &NewName::new("")).unwrap_err().errors.len(), 2);
+    }
+
+    #[test]
+    fn try_validate_metadata() {
+        let mut valid = sim(1, "The Big Goodbye");
+        valid.description = Some("A hard-boiled detective story.\nSan Francisco, 1941.".to_string());
+        valid.genre = Some("Noir".to_string());
+        valid.author = Some("Jean-Luc Picard".to_string());
+        valid.tags = ["noir", "dixon-hill", "1941"].iter().map(|t| t.to_string()).collect();
+        assert!(messages(valid.clone()).is_empty());
+
+        let mut invalid = valid;
+        invalid.description = Some("Bell\u{7}".to_string());
+        invalid.genre = Some(" ".to_string());
+        invalid.tags.insert("Film Noir".to_string());
+        invalid.safety.reason = Some("Bell\u{7}".to_string());
+        assert_eq!(
+            messages(invalid),
+            [
+                "description: must not contain '\\u{7}'",
+                "genre: must not be blank",
+                "tags: \"Film Noir\" must be 1 to 32 lowercase letters, digits or dashes",
+                "safety.reason: must not contain '\\u{7}'",
+            ]
+        );
+
+        let mut new = NewName::new("Fair Haven");
+        new.tags = Some((0..=TAGS_MAX).map(|i| i.to_string()).collect());
+        assert_eq!(new.validate().unwrap_err().errors[0].message, "must hold at most 20 tags, got 21");
+    }
+
+    #[test]
+    fn try_validate_booking() {
+        let now = Utc::now();
+        let hour = chrono::Duration::hours(1);
+        let booking = NewReservation {
+            simulation: 1,
+            start: now + hour,
+            end: now + hour * 2,
+        };
+        assert!(validate_booking(&booking, now, true).is_ok());
+
+        let backwards = NewReservation {
+            end: booking.start,
+            ..booking.clone()
+        };
+        let fields: Vec<String> = validate_booking(&backwards, now + hour * 3, false)
+            .unwrap_err()
+            .errors
+            .into_iter()
+            .map(|e| format!("{}: {}", e.field, e.message))
+            .collect();
+        assert_eq!(
+            fields,
+            ["end: must be after start", "simulation: #1 is not in the catalogue", "end: must be in the future"]
+        );
+
+        assert!(Room { id: 1, name: "Holodeck 1".to_string() }.validate().is_ok());
+        assert_eq!(Room { id: 0, name: String::new() }.validate().unwrap_err().errors.len(), 2);
+    }
+}
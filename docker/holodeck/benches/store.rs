@@ -0,0 +1,136 @@
+//! Throughput of the catalogue under mixed load: readers listing and getting simulations while
+//! writers update them, against a single `Mutex` around the store (how `Db` used to be)
+//! and against `Db` as it is now.
+//!
+//! Run with `cargo bench --bench store`.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+use holodeck::{get_simulation, Db, Store};
+use holodeck_models::models::Simulation;
+
+const SIMULATIONS: u64 = 1_000;
+const READERS: usize = 8;
+const WRITERS: usize = 2;
+const DURATION: Duration = Duration::from_secs(3);
+
+#[derive(Clone)]
+enum Design {
+    Mutex(Arc<Mutex<Store>>),
+    Snapshot(Db),
+}
+
+impl Design {
+    fn name(&self) -> &'static str {
+        match self {
+            Design::Mutex(_) => "single mutex",
+            Design::Snapshot(_) => "snapshots",
+        }
+    }
+
+    // Clones every simulation, as the list route does
+    async fn list(&self) -> usize {
+        let sims: Vec<Simulation> = match self {
+            Design::Mutex(store) => store.lock().await.iter().cloned().collect(),
+            Design::Snapshot(db) => db.snapshot().iter().cloned().collect(),
+        };
+        sims.len()
+    }
+
+    async fn get(&self, id: u64) -> Option<Simulation> {
+        match self {
+            Design::Mutex(store) => get_simulation(&*store.lock().await, id).cloned(),
+            Design::Snapshot(db) => get_simulation(&db.snapshot(), id).cloned(),
+        }
+    }
+
+    async fn rename(&self, id: u64, name: String) {
+        let sim = Simulation::new(id, name);
+        match self {
+            Design::Mutex(store) => store.lock().await.replace(sim),
+            Design::Snapshot(db) => db.lock().await.replace(sim),
+        };
+    }
+}
+
+struct Throughput {
+    lists: u64,
+    gets: u64,
+    writes: u64,
+}
+
+async fn run(design: Design) -> Throughput {
+    let stop = Arc::new(AtomicBool::new(false));
+    let lists = Arc::new(AtomicU64::new(0));
+    let gets = Arc::new(AtomicU64::new(0));
+    let writes = Arc::new(AtomicU64::new(0));
+    let mut tasks = Vec::new();
+
+    for reader in 0..READERS {
+        let (design, stop, lists, gets) = (design.clone(), stop.clone(), lists.clone(), gets.clone());
+        tasks.push(tokio::spawn(async move {
+            let mut id = reader as u64;
+            while !stop.load(Ordering::Relaxed) {
+                // One list for every hundred gets, roughly how a catalogue is browsed
+                for _ in 0..100 {
+                    id = (id + 7) % SIMULATIONS;
+                    assert!(design.get(id).await.is_some());
+                }
+                gets.fetch_add(100, Ordering::Relaxed);
+                assert_eq!(design.list().await, SIMULATIONS as usize);
+                lists.fetch_add(1, Ordering::Relaxed);
+                // Snapshot reads never wait, so without this the readers could keep every worker to themselves
+                tokio::task::yield_now().await;
+            }
+        }));
+    }
+    for writer in 0..WRITERS {
+        let (design, stop, writes) = (design.clone(), stop.clone(), writes.clone());
+        tasks.push(tokio::spawn(async move {
+            let mut id = writer as u64;
+            while !stop.load(Ordering::Relaxed) {
+                id = (id + 13) % SIMULATIONS;
+                design.rename(id, format!("Simulation {}", id)).await;
+                writes.fetch_add(1, Ordering::Relaxed);
+                tokio::task::yield_now().await;
+            }
+        }));
+    }
+
+    tokio::time::sleep(DURATION).await;
+    stop.store(true, Ordering::Relaxed);
+    for task in tasks {
+        task.await.unwrap();
+    }
+    Throughput {
+        lists: lists.load(Ordering::Relaxed),
+        gets: gets.load(Ordering::Relaxed),
+        writes: writes.load(Ordering::Relaxed),
+    }
+}
+
+fn main() {
+    let runtime = tokio::runtime::Builder::new_multi_thread().enable_all().build().unwrap();
+    let store = || (0..SIMULATIONS).map(|id| Simulation::new(id, format!("Simulation {}", id))).collect::<Store>();
+
+    println!(
+        "{} simulations, {} readers and {} writers for {:?} each",
+        SIMULATIONS, READERS, WRITERS, DURATION
+    );
+    for design in [Design::Mutex(Arc::new(Mutex::new(store()))), Design::Snapshot(Db::from(store()))] {
+        let name = design.name();
+        let started = Instant::now();
+        let throughput = runtime.block_on(run(design));
+        let secs = started.elapsed().as_secs_f64();
+        println!(
+            "{:>12}: {:>10.0} lists/s {:>12.0} gets/s {:>10.0} writes/s",
+            name,
+            throughput.lists as f64 / secs,
+            throughput.gets as f64 / secs,
+            throughput.writes as f64 / secs,
+        );
+    }
+}
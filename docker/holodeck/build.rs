@@ -0,0 +1,15 @@
+use std::env;
+
+// Generates the `Holodeck` messages, server and client from `proto/holodeck.proto`, with the protoc
+// bundled in `protoc-bin-vendored` so that nothing has to be installed to build
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+
+    // `HolodeckClient::connect` needs the 2021 prelude and this crate is on 2018, so clients connect
+    // a `Channel` and hand it to `HolodeckClient::new` instead
+    tonic_build::configure()
+        .build_transport(false)
+        .compile(&["proto/holodeck.proto"], &["proto"])?;
+    println!("cargo:rerun-if-changed=proto/holodeck.proto");
+    Ok(())
+}
@@ -0,0 +1,193 @@
+//! The holodeck server. The payloads it shares with clients live in `holodeck-models`; everything
+//! else is private, and reached through `run` or, from tests, `api`.
+
+mod libs;
+
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tonic::transport::server::TcpIncoming;
+use tokio::sync::oneshot;
+use warp::{Filter, Rejection, Reply};
+
+use libs::{audit, compression, cors, filters, grpc, idempotency, models, replication, rooms, safety, shutdown, tenants, tls};
+
+pub use libs::config::{Compat, Config, Cors, TlsFiles};
+
+/// The catalogue store, for `benches/store.rs`; not part of the API.
+#[doc(hidden)]
+pub use libs::models::{get_simulation, Db, Store};
+
+/// Every route, over an empty in-memory catalogue with the built-in safety rules and default settings.
+/// Client crates serve it in their tests.
+pub fn api() -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    filters::routes(
+        models::new_db().into(),
+        rooms::new_db(),
+        Arc::new(safety::Policy::default()),
+        Default::default(),
+        Default::default(),
+    )
+}
+
+/// Serves as `config` says until SIGTERM or Ctrl-C, then drains in-flight requests and flushes the
+/// simulations. Fails when a file it is configured with cannot be read or an address cannot be bound.
+pub async fn run(config: Config) -> Result<(), String> {
+    let db = match &config.data_file {
+        Some(path) => match models::load_db(path) {
+            Ok(db) => db,
+            Err(e) => return Err(format!("Failed to load simulations from {}: {}", path.display(), e)),
+        },
+        None => models::new_db(),
+    };
+    if let Some(path) = &config.audit_file {
+        let log = match audit::Log::open(path) {
+            Ok(log) => log,
+            Err(e) => return Err(format!("Failed to open the audit file {}: {}", path.display(), e)),
+        };
+        db.lock().await.set_audit(log);
+    }
+
+    let policy = match &config.safety_rules {
+        Some(path) => match safety::Policy::load(path) {
+            Ok(policy) => policy,
+            Err(e) => return Err(format!("Failed to load safety rules from {}: {}", path.display(), e)),
+        },
+        None => safety::Policy::default(),
+    };
+
+    let policy = Arc::new(policy);
+
+    let rooms = rooms::new_db();
+    tokio::spawn(rooms::release_expired_every(rooms.clone(), config.reservation_sweep));
+
+    let follower = config
+        .follow
+        .as_ref()
+        .map(|leader| replication::Follower::new(leader, config.replication_secret.clone().unwrap_or_default()));
+    if let Some(follower) = &follower {
+        tokio::spawn(follower.clone().run(db.clone()));
+    }
+
+    let tracker = shutdown::Tracker::new();
+
+    let api = filters::routes_for(filters::Context {
+        // Only the default tenant is flushed, so none other may be created when it would be lost
+        tenants: match config.data_file {
+            Some(_) => tenants::Tenants::single(db.clone()),
+            None => tenants::Tenants::from(db.clone()),
+        },
+        rooms: rooms.clone(),
+        policy: policy.clone(),
+        idempotency: idempotency::Cache::new(config.idempotency_ttl),
+        compat: config.compat,
+        replication_secret: config.replication_secret.clone(),
+        leader: follower.as_ref().map(|follower| follower.leader().to_string()),
+    });
+    // A follower answers reads itself and sends writes to its leader
+    let api = match &follower {
+        Some(follower) => follower.routes().or(api).map(Reply::into_response).boxed(),
+        None => api.map(Reply::into_response).boxed(),
+    };
+    let routes = compression::compress(api);
+    // CORS goes outside the API, so preflight requests are answered before anything else runs
+    let routes = match &config.cors {
+        Some(policy) => routes.with(cors::policy(policy)).map(Reply::into_response).boxed(),
+        None => routes.boxed(),
+    };
+    // A request is in flight until its reply, compressed or not, is handed to the connection
+    let routes = shutdown::track(tracker.clone())
+        .and(routes)
+        .map(shutdown::hold);
+
+    let (stop, stopped) = oneshot::channel::<()>();
+    let stopped = async { stopped.await.ok(); };
+
+    let server = match &config.tls {
+        Some(files) => {
+            let acceptor = match tls::Acceptor::new(files.clone()) {
+                Ok(acceptor) => acceptor,
+                Err(e) => return Err(format!("Failed to load TLS certificates from {}: {}", files.cert.display(), e)),
+            };
+            let listener = match TcpListener::bind(config.addr).await {
+                Ok(listener) => listener,
+                Err(e) => return Err(format!("Failed to bind {}: {}", config.addr, e)),
+            };
+            tokio::spawn(tls::reload_on_hangup(acceptor.clone()));
+            tokio::spawn(
+                warp::serve(routes)
+                    .serve_incoming_with_graceful_shutdown(tls::incoming(listener, acceptor, tls::HANDSHAKE_TIMEOUT), stopped),
+            )
+        }
+        None => {
+            let server = match warp::serve(routes).try_bind_with_graceful_shutdown(config.addr, stopped) {
+                Ok((_, server)) => server,
+                Err(e) => return Err(format!("Failed to bind {}: {}", config.addr, e)),
+            };
+            tokio::spawn(server)
+        }
+    };
+
+    // Same process and store as the REST API, on a port of its own
+    let (stop_grpc, grpc_stopped) = oneshot::channel::<()>();
+    // gRPC writes would go straight into the store, so followers do not serve it
+    let grpc = match config.grpc_addr.filter(|_| follower.is_none()) {
+        Some(addr) => {
+            // Never in plaintext next to an HTTPS API
+            let server = match &config.tls {
+                Some(files) => match tls::grpc_server(files) {
+                    Ok(server) => server,
+                    Err(e) => return Err(format!("Failed to load gRPC TLS certificates from {}: {}", files.cert.display(), e)),
+                },
+                None => tonic::transport::Server::builder(),
+            };
+            let listener = match TcpListener::bind(addr).await {
+                Ok(listener) => listener,
+                Err(e) => return Err(format!("Failed to bind the gRPC address {}: {}", addr, e)),
+            };
+            let incoming = match TcpIncoming::from_listener(listener, true, None) {
+                Ok(incoming) => incoming,
+                Err(e) => return Err(format!("Failed to listen on the gRPC address {}: {}", addr, e)),
+            };
+            let stopped = async { grpc_stopped.await.ok(); };
+            // Only the default tenant: the service has no way to name another
+            Some(tokio::spawn(grpc::serve(server, incoming, db.clone(), rooms.clone(), policy, stopped)))
+        }
+        None => None,
+    };
+
+    println!("Warp 6, Engage!");
+    shutdown::signal().await;
+
+    println!("Disengaging...");
+    let _ = stop.send(());
+    let _ = stop_grpc.send(());
+    shutdown::drain(server, &tracker, config.shutdown_timeout).await;
+    if let Some(mut grpc) = grpc {
+        match tokio::time::timeout(config.shutdown_timeout, &mut grpc).await {
+            Ok(Ok(Err(e))) => eprintln!("The gRPC server failed: {}", e),
+            Ok(_) => {}
+            Err(_) => {
+                grpc.abort();
+                println!("Deadline reached: gRPC calls abandoned.");
+            }
+        }
+    }
+
+    if let Some(path) = &config.data_file {
+        // Requests abandoned at the deadline may still try to write; they wait here until the process exits
+        let _writes = db.lock().await;
+        match models::save_db(&db, path).await {
+            Ok(count) => println!("Flushed {} simulation(s) to {}", count, path.display()),
+            Err(e) => eprintln!("Failed to flush simulations to {}: {}", path.display(), e),
+        }
+    }
+    let rooms = rooms.lock().await;
+    if !rooms.rooms().is_empty() {
+        eprintln!(
+            "Not flushed: {} room(s) and {} reservation(s), which are kept in memory only.",
+            rooms.rooms().len(),
+            rooms.reservation_count()
+        );
+    }
+    Ok(())
+}
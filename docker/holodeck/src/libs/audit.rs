@@ -0,0 +1,236 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex, PoisonError};
+
+use super::lifecycle::Action;
+use super::models::Simulation;
+use super::safety::Actor;
+
+/// Media type of the newline-delimited JSON export of `GET /audit`.
+pub const NDJSON: &str = "application/x-ndjson";
+
+/// Most entries a log keeps in memory; older ones are only left in the file, if there is one.
+pub const KEPT: usize = 10_000;
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Operation {
+    Create,
+    Update,
+    Delete,
+    Run,
+    Pause,
+    Resume,
+    End,
+    CreateCharacter,
+    UpdateCharacter,
+    DeleteCharacter,
+    /// Replaced, or removed, by `POST /admin/restore`.
+    Restore,
+}
+
+impl From<Action> for Operation {
+    fn from(action: Action) -> Self {
+        match action {
+            Action::Run => Operation::Run,
+            Action::Pause => Operation::Pause,
+            Action::Resume => Operation::Resume,
+            Action::End => Operation::End,
+        }
+    }
+}
+
+/// One change to a simulation: who made it and when, and the simulation on either side of it.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Entry {
+    pub at: DateTime<Utc>,
+    /// `X-Holodeck-User`; `None` when the request did not say.
+    pub actor: Option<String>,
+    pub operation: Operation,
+    pub id: u64,
+    /// `None` for creates.
+    pub before: Option<Simulation>,
+    /// `None` for deletes.
+    pub after: Option<Simulation>,
+}
+
+impl Entry {
+    pub fn new(actor: &Actor, operation: Operation, id: u64, before: Option<Simulation>, after: Option<Simulation>) -> Entry {
+        Entry {
+            at: Utc::now(),
+            actor: actor.user.clone(),
+            operation,
+            id,
+            before,
+            after,
+        }
+    }
+}
+
+/// Query string of `GET /audit`; every field left out matches everything.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct Query {
+    pub actor: Option<String>,
+    /// Entries recorded at or after this instant, e.g. `2024-05-01T00:00:00Z`.
+    pub since: Option<DateTime<Utc>>,
+    pub id: Option<u64>,
+}
+
+impl Query {
+    pub fn matches(&self, entry: &Entry) -> bool {
+        self.actor.as_ref().is_none_or(|actor| entry.actor.as_ref() == Some(actor))
+            && self.since.is_none_or(|since| entry.at >= since)
+            && self.id.is_none_or(|id| entry.id == id)
+    }
+}
+
+#[derive(Debug)]
+struct Inner {
+    entries: VecDeque<Entry>,
+    kept: usize,
+    file: Option<File>,
+}
+
+impl Inner {
+    fn push(&mut self, entry: Entry) {
+        if self.entries.len() == self.kept {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+}
+
+/// Append-only record of the changes to one tenant's catalogue, oldest first. Entries can be added
+/// and read, never changed; only the latest `KEPT` are kept in memory, and the default tenant's are
+/// also appended to `HOLODECK_AUDIT_FILE`, which keeps them all.
+#[derive(Clone, Debug)]
+pub struct Log {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl Default for Log {
+    fn default() -> Self {
+        Log::keeping(KEPT)
+    }
+}
+
+impl Log {
+    fn keeping(kept: usize) -> Log {
+        Log {
+            inner: Arc::new(Mutex::new(Inner {
+                entries: VecDeque::new(),
+                kept: kept.max(1),
+                file: None,
+            })),
+        }
+    }
+
+    /// Picks up the entries already in the file, which is created when it does not exist yet.
+    pub fn open(path: &Path) -> io::Result<Log> {
+        let entries = match fs::read_to_string(path) {
+            Ok(text) => text
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(serde_json::from_str)
+                .collect::<Result<Vec<Entry>, _>>()?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let log = Log::default();
+        {
+            let mut inner = log.inner.lock().unwrap_or_else(PoisonError::into_inner);
+            for entry in entries {
+                inner.push(entry);
+            }
+            inner.file = Some(file);
+        }
+        Ok(log)
+    }
+
+    pub fn record(&self, entry: Entry) {
+        let mut inner = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(file) = &mut inner.file {
+            // The change has been made by now, so failing to write it down must not undo it
+            let written = serde_json::to_vec(&entry).map_err(io::Error::from).and_then(|mut line| {
+                line.push(b'\n');
+                file.write_all(&line)
+            });
+            if let Err(e) = written {
+                eprintln!("Failed to append to the audit file: {}", e);
+            }
+        }
+        inner.push(entry);
+    }
+
+    pub fn query(&self, query: &Query) -> Vec<Entry> {
+        let inner = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+        inner.entries.iter().filter(|entry| query.matches(entry)).cloned().collect()
+    }
+}
+
+/// One JSON document per line, for tools that stream large exports.
+pub fn to_ndjson(entries: &[Entry]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for entry in entries {
+        // Entries only hold strings, numbers and timestamps, which always serialize
+        if serde_json::to_writer(&mut bytes, entry).is_ok() {
+            bytes.push(b'\n');
+        }
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn try_log() {
+        let path = std::env::temp_dir().join(format!("holodeck-{}-audit.ndjson", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let picard = Actor::from_headers(Some("picard".to_string()), None, None);
+        let sim = Simulation::new(1, "The Big Goodbye");
+
+        let log = Log::open(&path).unwrap();
+        log.record(Entry::new(&picard, Operation::Create, 1, None, Some(sim.clone())));
+        log.record(Entry::new(&Actor::default(), Operation::Delete, 1, Some(sim), None));
+        let since = Utc::now();
+        log.record(Entry::new(&picard, Operation::Create, 2, None, Some(Simulation::new(2, "Fair Haven"))));
+
+        let by_picard = log.query(&Query {
+            actor: Some("picard".to_string()),
+            ..Query::default()
+        });
+        assert_eq!(by_picard.iter().map(|e| e.id).collect::<Vec<_>>(), [1, 2]);
+        let recent = log.query(&Query {
+            since: Some(since),
+            ..Query::default()
+        });
+        assert_eq!(recent.iter().map(|e| e.id).collect::<Vec<_>>(), [2]);
+        let first = log.query(&Query {
+            id: Some(1),
+            ..Query::default()
+        });
+        assert_eq!(first.iter().map(|e| e.operation).collect::<Vec<_>>(), [Operation::Create, Operation::Delete]);
+
+        // A restart picks up where the file left off
+        let reopened = Log::open(&path).unwrap();
+        assert_eq!(reopened.query(&Query::default()), log.query(&Query::default()));
+        assert_eq!(fs::read(&path).unwrap(), to_ndjson(&log.query(&Query::default())));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn try_kept() {
+        let log = Log::keeping(2);
+        for id in 1..=3 {
+            log.record(Entry::new(&Actor::default(), Operation::Create, id, None, None));
+        }
+        assert_eq!(log.query(&Query::default()).iter().map(|e| e.id).collect::<Vec<_>>(), [2, 3]);
+    }
+}
@@ -0,0 +1,121 @@
+use chrono::Utc;
+use std::fmt;
+
+use super::audit::{self, Operation};
+use super::lifecycle::State;
+use super::models::{self, Db, NewName, Simulation};
+use super::rooms;
+use super::safety::{Actor, Event, Policy, Violations};
+use super::validation::{validate_update, Validate, ValidationErrors};
+
+/// Writes to the catalogue shared by every API, so each one validates and checks the safety rules the same way.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Error {
+    Exists { id: u64, name: String },
+    NotFound(u64),
+    Invalid(ValidationErrors),
+    Forbidden(Violations),
+    /// The tenant already holds as many simulations as its quota allows.
+    QuotaExceeded(usize),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Exists { id, name } => write!(f, "Simulation #{} already exists under the name {}", id, name),
+            Error::NotFound(id) => write!(f, "Simulation #{} does not exist.", id),
+            Error::Invalid(invalid) => {
+                let fields: Vec<String> = invalid.errors.iter().map(|e| format!("{}: {}", e.field, e.message)).collect();
+                write!(f, "Invalid simulation: {}", fields.join("; "))
+            }
+            Error::Forbidden(forbidden) => {
+                let rules: Vec<String> = forbidden.violations.iter().map(|v| format!("{}: {}", v.rule, v.message)).collect();
+                write!(f, "Forbidden by the safety rules: {}", rules.join("; "))
+            }
+            Error::QuotaExceeded(quota) => write!(f, "The quota of {} simulation(s) is reached.", quota),
+        }
+    }
+}
+
+/// Adds `sim` and hands back what was stored.
+pub async fn create(db: &Db, policy: &Policy, actor: &Actor, sim: Simulation) -> Result<Simulation, Error> {
+    sim.validate().map_err(Error::Invalid)?;
+
+    let mut map = db.lock().await;
+    if let Some(existing) = models::get_simulation(&map, sim.id) {
+        return Err(Error::Exists {
+            id: existing.id,
+            name: existing.name.clone(),
+        });
+    }
+    check_quota(&map)?;
+    policy.check(Event::Create, &sim, actor).map_err(Error::Forbidden)?;
+
+    // Timestamps, lifecycle and characters belong to the server, whatever the client sent
+    let mut sim = Simulation {
+        created_at: None,
+        state: State::default(),
+        transitions: Vec::new(),
+        characters: Vec::new(),
+        ..sim
+    };
+    sim.touch(Utc::now());
+    map.insert(sim.clone());
+    map.record(audit::Entry::new(actor, Operation::Create, sim.id, None, Some(sim.clone())));
+    Ok(sim)
+}
+
+/// Applies `new` to simulation `id`. When it does not exist, it is created if `upsert` is set; the flag
+/// that comes back with the simulation tells whether it was.
+pub async fn update(
+    db: &Db,
+    policy: &Policy,
+    actor: &Actor,
+    id: u64,
+    new: NewName,
+    upsert: bool,
+) -> Result<(Simulation, bool), Error> {
+    validate_update(id, &new).map_err(Error::Invalid)?;
+
+    let mut map = db.lock().await;
+    let existing = models::get_simulation(&map, id).cloned();
+    let created = existing.is_none();
+    if created && !upsert {
+        return Err(Error::NotFound(id));
+    }
+    if created {
+        check_quota(&map)?;
+    }
+
+    let mut sim = existing.clone().unwrap_or_else(|| Simulation::new(id, ""));
+    new.apply(&mut sim);
+    // Checked on the simulation as it would be stored
+    policy.check(Event::Update, &sim, actor).map_err(Error::Forbidden)?;
+    sim.touch(Utc::now());
+    map.replace(sim.clone());
+    let operation = if created { Operation::Create } else { Operation::Update };
+    map.record(audit::Entry::new(actor, operation, id, existing, Some(sim.clone())));
+    Ok((sim, created))
+}
+
+/// Removes simulation `id` of `tenant`, characters and room reservations included, and hands it back.
+pub async fn delete(db: &Db, rooms: &rooms::Db, tenant: Option<&str>, actor: &Actor, id: u64) -> Result<Simulation, Error> {
+    let mut map = db.lock().await;
+    let sim = models::get_simulation(&map, id).cloned().ok_or(Error::NotFound(id))?;
+    map.remove(&sim);
+    map.record(audit::Entry::new(actor, Operation::Delete, id, Some(sim.clone()), None));
+
+    // Bookings check the snapshot under the rooms lock, so holding it until the removal is published
+    // keeps a booking from slipping in for a simulation that is already gone
+    let mut rooms = rooms.lock().await;
+    drop(map);
+    rooms.cancel_simulations(tenant, |booked| booked == id);
+    Ok(sim)
+}
+
+fn check_quota(store: &models::Store) -> Result<(), Error> {
+    match store.quota() {
+        Some(quota) if store.len() >= quota => Err(Error::QuotaExceeded(quota)),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,42 @@
+use serde::{Deserialize, Serialize};
+
+use super::models::Simulation;
+
+/// How many changes a subscriber may fall behind before it starts missing some.
+pub const BUFFER: usize = 256;
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    Created,
+    Updated,
+    Deleted,
+}
+
+/// One write to the catalogue, as published by `models::Store` to everyone subscribed.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Change {
+    pub kind: ChangeKind,
+    pub id: u64,
+    /// The simulation as stored after the write; `None` once deleted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub simulation: Option<Simulation>,
+}
+
+impl Change {
+    pub fn written(kind: ChangeKind, sim: &Simulation) -> Change {
+        Change {
+            kind,
+            id: sim.id,
+            simulation: Some(sim.clone()),
+        }
+    }
+
+    pub fn deleted(id: u64) -> Change {
+        Change {
+            kind: ChangeKind::Deleted,
+            id,
+            simulation: None,
+        }
+    }
+}
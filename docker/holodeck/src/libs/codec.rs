@@ -0,0 +1,163 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::str::FromStr;
+use warp::http::header::{CONTENT_TYPE, VARY};
+use warp::http::{HeaderValue, StatusCode};
+use warp::reply::Response;
+use warp::Reply;
+
+/// Encodings the catalogue can be read and written in. JSON is the default.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Format {
+    Json,
+    MsgPack,
+    Cbor,
+}
+
+pub const FORMATS: [Format; 3] = [Format::Json, Format::MsgPack, Format::Cbor];
+
+impl Format {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Format::Json => "application/json",
+            Format::MsgPack => "application/msgpack",
+            Format::Cbor => "application/cbor",
+        }
+    }
+
+    /// The format of a media type such as `application/cbor; charset=binary`; parameters are ignored.
+    pub fn from_media_type(media_type: &str) -> Option<Format> {
+        let essence = media_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+        match essence.as_str() {
+            "application/json" => Some(Format::Json),
+            // MessagePack has no registered type, so the common spellings are all accepted
+            "application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack" => Some(Format::MsgPack),
+            "application/cbor" => Some(Format::Cbor),
+            _ => None,
+        }
+    }
+
+    /// Picks the format an `Accept` header likes best, JSON when there is no header or it takes anything.
+    /// `None` means none of the formats is acceptable.
+    pub fn negotiate(accept: Option<&str>) -> Option<Format> {
+        let accept = match accept {
+            Some(accept) if !accept.trim().is_empty() => accept,
+            _ => return Some(Format::Json),
+        };
+
+        let mut best: Option<(Format, f32)> = None;
+        for range in accept.split(',') {
+            let mut params = range.split(';');
+            let media_type = params.next().unwrap_or_default().trim().to_ascii_lowercase();
+            let quality = params
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .find_map(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+
+            let format = match media_type.as_str() {
+                "*/*" | "application/*" => Some(Format::Json),
+                other => Format::from_media_type(other),
+            };
+            if let Some(format) = format {
+                // Ties go to the range listed first
+                if quality > 0.0 && best.is_none_or(|(_, q)| quality > q) {
+                    best = Some((format, quality));
+                }
+            }
+        }
+        best.map(|(format, _)| format)
+    }
+
+    pub fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, String> {
+        match self {
+            Format::Json => serde_json::to_vec(value).map_err(|e| e.to_string()),
+            // Structs as maps rather than arrays, so fields left out or added later do not shift the others
+            Format::MsgPack => rmp_serde::to_vec_named(value).map_err(|e| e.to_string()),
+            Format::Cbor => {
+                let mut bytes = Vec::new();
+                ciborium::ser::into_writer(value, &mut bytes).map_err(|e| e.to_string())?;
+                Ok(bytes)
+            }
+        }
+    }
+
+    pub fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, String> {
+        match self {
+            Format::Json => serde_json::from_slice(bytes).map_err(|e| e.to_string()),
+            Format::MsgPack => rmp_serde::from_slice(bytes).map_err(|e| e.to_string()),
+            Format::Cbor => ciborium::de::from_reader(bytes).map_err(|e| e.to_string()),
+        }
+    }
+
+    /// `value` encoded in this format, with the matching `Content-Type`.
+    pub fn reply<T: Serialize>(&self, value: &T) -> Response {
+        let mut response = match self.encode(value) {
+            Ok(bytes) => Response::new(bytes.into()),
+            Err(e) => return warp::reply::with_status(e, StatusCode::INTERNAL_SERVER_ERROR).into_response(),
+        };
+        let headers = response.headers_mut();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static(self.content_type()));
+        headers.insert(VARY, HeaderValue::from_static("accept"));
+        response
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Format::from_media_type(s).ok_or_else(|| format!("unsupported media type {:?}", s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libs::characters::NewCharacter;
+    use crate::libs::lifecycle::Action;
+    use crate::libs::models::{NewName, Simulation};
+
+    #[test]
+    fn try_round_trip() {
+        let mut sim = Simulation::new(1, "The Big Goodbye");
+        sim.description = Some("San Francisco, 1941.\nA hard-boiled detective story.".to_string());
+        sim.genre = Some("Noir".to_string());
+        sim.tags = ["noir", "detective"].iter().map(|tag| tag.to_string()).collect();
+        sim.safety.protocols = false;
+        sim.add_character(NewCharacter::new("Dixon Hill", "Detective"));
+        sim.transition(Action::Run, chrono::Utc::now()).unwrap();
+
+        for format in FORMATS.iter() {
+            let bytes = format.encode(&sim).unwrap();
+            let decoded: Simulation = format.decode(&bytes).unwrap();
+            // `Simulation` compares by id only, so compare what is on the wire instead
+            assert_eq!(serde_json::to_value(&decoded).unwrap(), serde_json::to_value(&sim).unwrap(), "{:?}", format);
+
+            // A version 1 payload, with every optional field left out
+            let bytes = format.encode(&serde_json::json!({ "id": 2, "name": "The Short Hello" })).unwrap();
+            let minimal: Simulation = format.decode(&bytes).unwrap();
+            assert_eq!((minimal.name.as_str(), minimal.schema_version), ("The Short Hello", 1));
+
+            let new: NewName = format.decode(&format.encode(&NewName::new("Fair Haven")).unwrap()).unwrap();
+            assert_eq!(new.name, "Fair Haven");
+        }
+        assert!(Format::Cbor.decode::<Simulation>(&Format::MsgPack.encode(&sim).unwrap()).is_err());
+    }
+
+    #[test]
+    fn try_negotiate() {
+        assert_eq!(Format::negotiate(None), Some(Format::Json));
+        assert_eq!(Format::negotiate(Some("*/*")), Some(Format::Json));
+        assert_eq!(Format::negotiate(Some("application/cbor")), Some(Format::Cbor));
+        assert_eq!(Format::negotiate(Some("application/x-msgpack, application/json")), Some(Format::MsgPack));
+        assert_eq!(
+            Format::negotiate(Some("application/json;q=0.5, application/msgpack;q=0.9, */*;q=0.1")),
+            Some(Format::MsgPack)
+        );
+        assert_eq!(Format::negotiate(Some("application/cbor;q=0, application/json")), Some(Format::Json));
+        assert_eq!(Format::negotiate(Some("text/html")), None);
+
+        assert_eq!("application/cbor; charset=binary".parse(), Ok(Format::Cbor));
+        assert!("text/plain".parse::<Format>().is_err());
+    }
+}
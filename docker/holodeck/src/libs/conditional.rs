@@ -0,0 +1,115 @@
+use chrono::{DateTime, TimeZone, Utc};
+use warp::http::header::{HeaderValue, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, VARY};
+use warp::http::StatusCode;
+use warp::reply::Response;
+use warp::{Filter, Rejection};
+
+/// The conditional headers of a GET; both absent means the client has nothing cached.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Preconditions {
+    pub if_none_match: Option<String>,
+    pub if_modified_since: Option<String>,
+}
+
+pub fn preconditions() -> impl Filter<Extract = (Preconditions,), Error = Rejection> + Clone {
+    warp::header::optional::<String>(IF_NONE_MATCH.as_str())
+        .and(warp::header::optional::<String>(IF_MODIFIED_SINCE.as_str()))
+        .map(|if_none_match, if_modified_since| Preconditions {
+            if_none_match,
+            if_modified_since,
+        })
+}
+
+/// What a client can check its cached copy of a reply against.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Validators {
+    /// Weak, since the same simulations can encode to different bytes.
+    pub etag: String,
+    pub last_modified: DateTime<Utc>,
+}
+
+impl Validators {
+    /// `tag` must change whenever the reply would.
+    pub fn new(tag: &str, last_modified: DateTime<Utc>) -> Validators {
+        Validators {
+            etag: format!("W/\"{}\"", tag),
+            last_modified,
+        }
+    }
+
+    /// Whether the client's copy is still good. `If-None-Match` wins when both are sent, as
+    /// `Last-Modified` only has whole seconds and misses a second write within the same one.
+    pub fn fresh(&self, preconditions: &Preconditions) -> bool {
+        if let Some(if_none_match) = &preconditions.if_none_match {
+            let ours = opaque(&self.etag);
+            return if_none_match.split(',').map(str::trim).any(|tag| tag == "*" || opaque(tag) == ours);
+        }
+        match preconditions.if_modified_since.as_deref().and_then(parse_http_date) {
+            Some(since) => self.last_modified.timestamp() <= since.timestamp(),
+            None => false,
+        }
+    }
+
+    /// `response` with the validators added, for the client to send back next time. Replies differ by
+    /// tenant and format, so caches are told to keep those apart.
+    pub fn reply(&self, mut response: Response) -> Response {
+        let headers = response.headers_mut();
+        headers.insert(VARY, HeaderValue::from_static("x-holodeck-tenant, accept"));
+        if let Ok(etag) = HeaderValue::from_str(&self.etag) {
+            headers.insert(ETAG, etag);
+        }
+        if let Ok(last_modified) = HeaderValue::from_str(&http_date(self.last_modified)) {
+            headers.insert(LAST_MODIFIED, last_modified);
+        }
+        response
+    }
+
+    pub fn not_modified(&self) -> Response {
+        let mut response = Response::default();
+        *response.status_mut() = StatusCode::NOT_MODIFIED;
+        self.reply(response)
+    }
+}
+
+// Weak comparison, which is what `If-None-Match` uses
+fn opaque(tag: &str) -> &str {
+    tag.strip_prefix("W/").unwrap_or(tag)
+}
+
+/// The IMF-fixdate HTTP uses, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`.
+pub fn http_date(at: DateTime<Utc>) -> String {
+    at.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+fn parse_http_date(date: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc2822(date.trim()).ok().map(|date| Utc.from_utc_datetime(&date.naive_utc()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn try_fresh() {
+        let modified = Utc.with_ymd_and_hms(1994, 11, 6, 8, 49, 37).unwrap();
+        let validators = Validators::new("7-json", modified);
+        assert_eq!(http_date(modified), "Sun, 06 Nov 1994 08:49:37 GMT");
+        let check = |if_none_match: Option<&str>, if_modified_since: Option<&str>| {
+            validators.fresh(&Preconditions {
+                if_none_match: if_none_match.map(str::to_string),
+                if_modified_since: if_modified_since.map(str::to_string),
+            })
+        };
+
+        assert!(!check(None, None));
+        assert!(check(Some("W/\"7-json\""), None));
+        assert!(check(Some("\"3-json\", \"7-json\""), None));
+        assert!(check(Some("*"), None));
+        assert!(!check(Some("W/\"7-cbor\""), None));
+        assert!(check(None, Some("Sun, 06 Nov 1994 08:49:37 GMT")));
+        assert!(!check(None, Some("Sun, 06 Nov 1994 08:49:36 GMT")));
+        assert!(!check(None, Some("last Sunday")));
+        // A stale ETag is not rescued by a date
+        assert!(!check(Some("W/\"6-json\""), Some("Sun, 06 Nov 1994 08:49:37 GMT")));
+    }
+}
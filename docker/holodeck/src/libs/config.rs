@@ -0,0 +1,241 @@
+use std::env;
+use std::fmt::Display;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+use warp::http::header::HeaderName;
+use warp::http::{Method, Uri};
+
+use super::idempotency;
+
+/// Runtime settings, read from `HOLODECK_*` environment variables.
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// Address the server listens on (`HOLODECK_ADDR`).
+    pub addr: SocketAddr,
+    /// Address the gRPC service listens on (`HOLODECK_GRPC_ADDR`); not served when unset or `off`.
+    /// It speaks TLS, and asks for client certificates, whenever `tls` is set.
+    pub grpc_addr: Option<SocketAddr>,
+    /// How long in-flight requests get to finish after SIGTERM (`HOLODECK_SHUTDOWN_TIMEOUT`, in seconds).
+    pub shutdown_timeout: Duration,
+    /// Where the simulations are loaded from on start and flushed to on exit (`HOLODECK_DATA_FILE`).
+    /// Only the default tenant's are, so no other can be created then; rooms and reservations are lost on exit.
+    pub data_file: Option<PathBuf>,
+    /// File the audit log is appended to, and read back from on start (`HOLODECK_AUDIT_FILE`); kept in memory only when unset.
+    pub audit_file: Option<PathBuf>,
+    /// JSON file with the safety rules every write is checked against (`HOLODECK_SAFETY_RULES`); built-in rules when unset.
+    pub safety_rules: Option<PathBuf>,
+    /// How often ended reservations are released (`HOLODECK_RESERVATION_SWEEP`, in seconds).
+    pub reservation_sweep: Duration,
+    /// How long responses to POSTs with an `Idempotency-Key` are kept for replay (`HOLODECK_IDEMPOTENCY_TTL`, in seconds).
+    pub idempotency_ttl: Duration,
+    /// Serve HTTPS instead of plain HTTP when set.
+    pub tls: Option<TlsFiles>,
+    /// Let browsers call the API from other origins when set.
+    pub cors: Option<Cors>,
+    /// Older behaviours kept for clients that rely on them.
+    pub compat: Compat,
+    /// Base URL of the leader to follow (`HOLODECK_FOLLOW`), e.g. `http://holodeck-1:3030`; the server is read-only then.
+    pub follow: Option<Uri>,
+    /// Shared secret followers present as `Authorization: Bearer` on `GET /replication`
+    /// (`HOLODECK_REPLICATION_SECRET`); the leader refuses to stream when unset.
+    pub replication_secret: Option<String>,
+}
+
+/// Switches for behaviours the API has moved away from.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Compat {
+    /// `GET /holodeck/{id}` answers with an array, empty when there is no such simulation, instead of
+    /// the simulation or a 404 (`HOLODECK_ITEM_ARRAYS`).
+    pub item_arrays: bool,
+    /// `PUT /holodeck/{id}` only creates with `If-None-Match: *`, otherwise answering 404 like `DELETE` does
+    /// for missing simulations, and deletes answer 204 (`HOLODECK_STRICT`). Always on under `/v2`.
+    pub strict: bool,
+}
+
+impl Compat {
+    /// What the routes under `/v2` follow.
+    pub const V2: Compat = Compat {
+        item_arrays: false,
+        strict: true,
+    };
+}
+
+/// PEM files for the HTTPS listener.
+#[derive(Clone, Debug)]
+pub struct TlsFiles {
+    /// Certificate chain (`HOLODECK_TLS_CERT`).
+    pub cert: PathBuf,
+    /// Private key (`HOLODECK_TLS_KEY`).
+    pub key: PathBuf,
+    /// CA bundle clients must present a certificate from (`HOLODECK_TLS_CLIENT_CA`); enables mTLS.
+    pub client_ca: Option<PathBuf>,
+}
+
+/// Cross-origin requests browsers may make.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cors {
+    /// Origins such as `https://bridge.enterprise.example`, or `*` for any (`HOLODECK_CORS_ORIGINS`, comma separated).
+    pub origins: Vec<String>,
+    /// Methods they may use (`HOLODECK_CORS_METHODS`); every method the API has when unset.
+    pub methods: Vec<String>,
+    /// Request headers they may send (`HOLODECK_CORS_HEADERS`); `Content-Type`, `Accept`, `Idempotency-Key`, the conditional ones and the `X-Holodeck-*` ones when unset.
+    pub headers: Vec<String>,
+}
+
+impl Default for Cors {
+    fn default() -> Self {
+        Cors {
+            origins: Vec::new(),
+            methods: list("GET, POST, PUT, DELETE"),
+            headers: list("content-type, accept, idempotency-key, if-none-match, if-modified-since, x-holodeck-user, x-holodeck-roles, x-holodeck-approved-by, x-holodeck-tenant"),
+        }
+    }
+}
+
+impl Cors {
+    // warp panics on anything it cannot parse, so it is caught here instead
+    fn check(&self) -> Result<(), String> {
+        for origin in &self.origins {
+            let uri: Uri = origin.parse().map_err(|e| format!("HOLODECK_CORS_ORIGINS: {:?}: {}", origin, e))?;
+            let bare = uri.scheme().is_some() && uri.host().is_some() && uri.path_and_query().is_none();
+            if origin != "*" && !bare {
+                return Err(format!("HOLODECK_CORS_ORIGINS: {:?} is not an origin like https://host:port", origin));
+            }
+        }
+        for method in &self.methods {
+            Method::from_str(method).map_err(|e| format!("HOLODECK_CORS_METHODS: {:?}: {}", method, e))?;
+        }
+        for header in &self.headers {
+            HeaderName::from_str(header).map_err(|e| format!("HOLODECK_CORS_HEADERS: {:?}: {}", header, e))?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            addr: ([0, 0, 0, 0], 3030).into(),
+            grpc_addr: None,
+            shutdown_timeout: Duration::from_secs(5),
+            data_file: None,
+            audit_file: None,
+            safety_rules: None,
+            reservation_sweep: Duration::from_secs(60),
+            idempotency_ttl: idempotency::DEFAULT_TTL,
+            tls: None,
+            cors: None,
+            compat: Compat::default(),
+            follow: None,
+            replication_secret: None,
+        }
+    }
+}
+
+impl Config {
+    pub fn from_env() -> Result<Config, String> {
+        let mut config = Config::default();
+
+        if let Some(addr) = var("HOLODECK_ADDR") {
+            config.addr = addr;
+        }
+        if env::var("HOLODECK_GRPC_ADDR").is_ok_and(|addr| addr == "off") {
+            config.grpc_addr = None;
+        } else if let Some(addr) = var("HOLODECK_GRPC_ADDR") {
+            config.grpc_addr = Some(addr);
+        }
+        if let Some(secs) = var("HOLODECK_SHUTDOWN_TIMEOUT") {
+            config.shutdown_timeout = Duration::from_secs(secs);
+        }
+        config.data_file = var("HOLODECK_DATA_FILE");
+        config.audit_file = var("HOLODECK_AUDIT_FILE");
+        config.safety_rules = var("HOLODECK_SAFETY_RULES");
+        if let Some(secs) = var::<u64>("HOLODECK_RESERVATION_SWEEP") {
+            // `tokio::time::interval` panics on a zero period
+            config.reservation_sweep = Duration::from_secs(secs.max(1));
+        }
+        if let Some(secs) = var("HOLODECK_IDEMPOTENCY_TTL") {
+            config.idempotency_ttl = Duration::from_secs(secs);
+        }
+
+        if let Some(item_arrays) = var("HOLODECK_ITEM_ARRAYS") {
+            config.compat.item_arrays = item_arrays;
+        }
+        if let Some(strict) = var("HOLODECK_STRICT") {
+            config.compat.strict = strict;
+        }
+
+        // Half a TLS setup must not quietly fall back to plain HTTP
+        config.tls = match (var("HOLODECK_TLS_CERT"), var("HOLODECK_TLS_KEY")) {
+            (Some(cert), Some(key)) => Some(TlsFiles {
+                cert,
+                key,
+                client_ca: var("HOLODECK_TLS_CLIENT_CA"),
+            }),
+            (None, None) => None,
+            _ => return Err("HOLODECK_TLS_CERT and HOLODECK_TLS_KEY must be set together".to_string()),
+        };
+
+        // CORS stays off until origins are given; the other two only refine it
+        config.cors = match var::<String>("HOLODECK_CORS_ORIGINS") {
+            Some(origins) => {
+                let mut cors = Cors {
+                    origins: list(&origins),
+                    ..Cors::default()
+                };
+                if let Some(methods) = var::<String>("HOLODECK_CORS_METHODS") {
+                    cors.methods = list(&methods);
+                }
+                if let Some(headers) = var::<String>("HOLODECK_CORS_HEADERS") {
+                    cors.headers = list(&headers);
+                }
+                cors.check()?;
+                Some(cors)
+            }
+            None => None,
+        };
+
+        // Followers only speak plain HTTP to their leader, so anything else is refused up front; unlike
+        // the settings above, a bad leader is never ignored, or the server would start as a writable leader
+        config.follow = match env::var("HOLODECK_FOLLOW") {
+            Ok(leader) => match leader.parse::<Uri>() {
+                Ok(uri) if uri.scheme_str() == Some("http") && uri.host().is_some() => Some(uri),
+                _ => return Err(format!("HOLODECK_FOLLOW: {:?} is not a URL like http://host:port", leader)),
+            },
+            Err(env::VarError::NotPresent) => None,
+            Err(e) => return Err(format!("HOLODECK_FOLLOW: {}", e)),
+        };
+        config.replication_secret = env::var("HOLODECK_REPLICATION_SECRET").ok().filter(|secret| !secret.is_empty());
+        if config.follow.is_some() && config.replication_secret.is_none() {
+            return Err("HOLODECK_FOLLOW: the leader only streams to followers that know its HOLODECK_REPLICATION_SECRET, which is unset".to_string());
+        }
+
+        Ok(config)
+    }
+}
+
+fn list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+fn var<T>(key: &str) -> Option<T>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    let value = env::var(key).ok()?;
+    match value.parse() {
+        Ok(parsed) => Some(parsed),
+        Err(e) => {
+            eprintln!("Ignoring {}={:?}: {}", key, value, e);
+            None
+        }
+    }
+}
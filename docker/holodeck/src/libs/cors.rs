@@ -0,0 +1,71 @@
+use warp::cors::Builder;
+
+use super::config::Cors;
+
+/// The warp CORS filter for `cors`; preflight requests are answered by it without reaching the routes.
+pub fn policy(cors: &Cors) -> Builder {
+    let builder = warp::cors()
+        .allow_methods(cors.methods.iter().map(String::as_str))
+        .allow_headers(cors.headers.iter().map(String::as_str))
+        // Scripts cannot read it otherwise, and need it for `If-None-Match`
+        .expose_headers(["etag"]);
+
+    if cors.origins.iter().any(|origin| origin == "*") {
+        builder.allow_any_origin()
+    } else {
+        builder.allow_origins(cors.origins.iter().map(String::as_str))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use warp::Filter;
+
+    #[tokio::test]
+    async fn try_policy() {
+        let cors = Cors {
+            origins: vec!["https://bridge.enterprise.example".to_string()],
+            methods: vec!["GET".to_string()],
+            ..Cors::default()
+        };
+        let route = warp::path("holodeck").map(|| "[]").with(policy(&cors));
+
+        let preflight = |origin: &'static str, method: &'static str| {
+            warp::test::request()
+                .method("OPTIONS")
+                .path("/holodeck")
+                .header("origin", origin)
+                .header("access-control-request-method", method)
+                .header("access-control-request-headers", "x-holodeck-user")
+                .reply(&route)
+        };
+        let res = preflight("https://bridge.enterprise.example", "GET").await;
+        assert_eq!(res.status(), 200);
+        assert_eq!(res.headers()["access-control-allow-origin"], "https://bridge.enterprise.example");
+        assert_eq!(preflight("https://bridge.enterprise.example", "DELETE").await.status(), 403);
+        assert_eq!(preflight("https://romulus.example", "GET").await.status(), 403);
+
+        let res = warp::test::request()
+            .path("/holodeck")
+            .header("origin", "https://bridge.enterprise.example")
+            .reply(&route)
+            .await;
+        assert_eq!(res.headers()["access-control-allow-origin"], "https://bridge.enterprise.example");
+
+        // Requests without an `Origin` do not come from a browser and are left alone
+        assert_eq!(warp::test::request().path("/holodeck").reply(&route).await.body(), "[]");
+
+        let any = Cors {
+            origins: vec!["*".to_string()],
+            ..Cors::default()
+        };
+        let route = warp::path("holodeck").map(|| "[]").with(policy(&any));
+        let res = warp::test::request()
+            .path("/holodeck")
+            .header("origin", "https://romulus.example")
+            .reply(&route)
+            .await;
+        assert_eq!(res.status(), 200);
+    }
+}
@@ -0,0 +1,425 @@
+use async_graphql::futures_util::stream::{self, Stream};
+use async_graphql::parser::types::OperationType;
+use async_graphql::{Context, Enum, ErrorExtensions, InputObject, Object, Schema, SimpleObject, Subscription};
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+
+use super::catalogue;
+use super::changes::{self, Change};
+use super::characters::Character;
+use super::lifecycle;
+use super::models::{self, Db, NewName};
+use super::rooms;
+use super::safety::{Actor, Policy};
+use super::tags::TagFilter;
+
+/// Most simulations one page of `simulations` holds.
+pub const PAGE_MAX: usize = 100;
+
+pub type HolodeckSchema = Schema<Query, Mutation, Subscription>;
+
+/// The schema behind `/graphql`, over the same rooms and safety rules as the REST routes. Requests
+/// carry the `Catalogue` they read and write in their data, and mutations are made on behalf of the
+/// `Actor` there, or of nobody when there is none.
+pub fn schema(rooms: rooms::Db, policy: Arc<Policy>) -> HolodeckSchema {
+    Schema::build(Query, Mutation, Subscription).data(rooms).data(policy).finish()
+}
+
+/// The tenant a request is for, and its catalogue.
+#[derive(Clone)]
+pub struct Catalogue {
+    pub db: Db,
+    /// `None` for the default tenant.
+    pub tenant: Option<String>,
+}
+
+/// Whether `request` runs a mutation, going by its `operationName` when the document has several
+/// operations. Documents that do not parse are not, and are left to the schema to answer.
+pub fn is_mutation(request: &async_graphql::Request) -> bool {
+    let document = match async_graphql::parser::parse_query(&request.query) {
+        Ok(document) => document,
+        Err(_) => return false,
+    };
+    let wanted = request.operation_name.as_deref();
+    document
+        .operations
+        .iter()
+        .find(|(name, _)| wanted.is_none() || name.map(|name| name.as_str()) == wanted)
+        .is_some_and(|(_, operation)| operation.node.ty == OperationType::Mutation)
+}
+
+/// GraphiQL, pointed at `/graphql` and its subscriptions at `/graphql/ws`.
+pub fn graphiql() -> String {
+    async_graphql::http::GraphiQLSource::build()
+        .endpoint("/graphql")
+        .subscription_endpoint("/graphql/ws")
+        .finish()
+}
+
+#[derive(Clone, Copy, Debug, Enum, Eq, PartialEq)]
+#[graphql(remote = "lifecycle::State")]
+pub enum State {
+    Loaded,
+    Running,
+    Paused,
+    Ended,
+}
+
+#[derive(Clone, Copy, Debug, Enum, Eq, PartialEq)]
+#[graphql(remote = "changes::ChangeKind")]
+pub enum ChangeKind {
+    Created,
+    Updated,
+    Deleted,
+}
+
+pub struct Simulation(models::Simulation);
+
+#[Object]
+impl Simulation {
+    async fn id(&self) -> u64 {
+        self.0.id
+    }
+
+    async fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    async fn description(&self) -> Option<&str> {
+        self.0.description.as_deref()
+    }
+
+    async fn genre(&self) -> Option<&str> {
+        self.0.genre.as_deref()
+    }
+
+    async fn author(&self) -> Option<&str> {
+        self.0.author.as_deref()
+    }
+
+    async fn tags(&self) -> Vec<&str> {
+        self.0.tags.iter().map(String::as_str).collect()
+    }
+
+    /// Whether the safety protocols are on.
+    async fn protocols(&self) -> bool {
+        self.0.safety.protocols
+    }
+
+    async fn state(&self) -> State {
+        self.0.state.into()
+    }
+
+    /// RFC 3339.
+    async fn created_at(&self) -> Option<String> {
+        self.0.created_at.map(|at| at.to_rfc3339())
+    }
+
+    /// RFC 3339.
+    async fn updated_at(&self) -> Option<String> {
+        self.0.updated_at.map(|at| at.to_rfc3339())
+    }
+
+    async fn characters(&self) -> Vec<CharacterObject> {
+        self.0.characters.iter().cloned().map(CharacterObject).collect()
+    }
+}
+
+pub struct CharacterObject(Character);
+
+#[Object(name = "Character")]
+impl CharacterObject {
+    async fn id(&self) -> u64 {
+        self.0.id
+    }
+
+    async fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    async fn role(&self) -> &str {
+        &self.0.role
+    }
+}
+
+/// Conditions left out match everything.
+#[derive(Debug, Default, InputObject)]
+pub struct SimulationFilter {
+    /// Like `?tag=` on the REST route: every tag listed, except those starting with `!`, which must be absent.
+    tags: Option<Vec<String>>,
+    state: Option<State>,
+    /// Case-insensitive part of the name.
+    name: Option<String>,
+}
+
+#[derive(SimpleObject)]
+pub struct SimulationPage {
+    /// How many simulations match, over all pages.
+    total: usize,
+    items: Vec<Simulation>,
+}
+
+#[derive(Debug, InputObject)]
+pub struct NewSimulation {
+    id: u64,
+    name: String,
+    description: Option<String>,
+    genre: Option<String>,
+    author: Option<String>,
+    tags: Option<Vec<String>>,
+}
+
+#[derive(SimpleObject)]
+pub struct SimulationChange {
+    kind: ChangeKind,
+    id: u64,
+    /// Gone once deleted.
+    simulation: Option<Simulation>,
+}
+
+impl From<Change> for SimulationChange {
+    fn from(change: Change) -> Self {
+        SimulationChange {
+            kind: change.kind.into(),
+            id: change.id,
+            simulation: change.simulation.map(Simulation),
+        }
+    }
+}
+
+// The REST answers' messages, with the details a client may act on as extensions
+fn error(e: catalogue::Error) -> async_graphql::Error {
+    let message = e.to_string();
+    async_graphql::Error::new(message).extend_with(|_, extensions| match &e {
+        catalogue::Error::Exists { .. } => extensions.set("code", "ALREADY_EXISTS"),
+        catalogue::Error::NotFound(_) => extensions.set("code", "NOT_FOUND"),
+        catalogue::Error::QuotaExceeded(_) => extensions.set("code", "QUOTA_EXCEEDED"),
+        catalogue::Error::Invalid(invalid) => {
+            extensions.set("code", "INVALID");
+            extensions.set("errors", async_graphql::Value::from_json(serde_json::json!(invalid.errors)).unwrap_or_default());
+        }
+        catalogue::Error::Forbidden(forbidden) => {
+            extensions.set("code", "FORBIDDEN");
+            extensions.set(
+                "violations",
+                async_graphql::Value::from_json(serde_json::json!(forbidden.violations)).unwrap_or_default(),
+            );
+        }
+    })
+}
+
+fn actor(ctx: &Context<'_>) -> Actor {
+    ctx.data_opt::<Actor>().cloned().unwrap_or_default()
+}
+
+fn policy<'a>(ctx: &Context<'a>) -> &'a Policy {
+    ctx.data_unchecked::<Arc<Policy>>()
+}
+
+fn catalogue<'a>(ctx: &Context<'a>) -> &'a Catalogue {
+    ctx.data_unchecked::<Catalogue>()
+}
+
+pub struct Query;
+
+#[Object]
+impl Query {
+    async fn simulation(&self, ctx: &Context<'_>, id: u64) -> Option<Simulation> {
+        let map = catalogue(ctx).db.snapshot();
+        models::get_simulation(&map, id).cloned().map(Simulation)
+    }
+
+    /// Matching simulations by id, `limit` at a time (at most 100) from the `offset`th.
+    async fn simulations(
+        &self,
+        ctx: &Context<'_>,
+        filter: Option<SimulationFilter>,
+        #[graphql(default = 0)] offset: usize,
+        #[graphql(default = 20)] limit: usize,
+    ) -> SimulationPage {
+        let filter = filter.unwrap_or_default();
+        let mut tags = TagFilter::default();
+        for tag in filter.tags.iter().flatten() {
+            tags.push(tag);
+        }
+        let name = filter.name.as_ref().map(|name| name.to_lowercase());
+
+        let map = catalogue(ctx).db.snapshot();
+        let mut found: Vec<&models::Simulation> = map
+            .tagged(&tags)
+            .into_iter()
+            .filter(|sim| filter.state.is_none_or(|state| sim.state == state.into()))
+            .filter(|sim| name.as_ref().is_none_or(|name| sim.name.to_lowercase().contains(name)))
+            .collect();
+        found.sort_by_key(|sim| sim.id);
+
+        SimulationPage {
+            total: found.len(),
+            items: found
+                .into_iter()
+                .skip(offset)
+                .take(limit.min(PAGE_MAX))
+                .cloned()
+                .map(Simulation)
+                .collect(),
+        }
+    }
+}
+
+pub struct Mutation;
+
+#[Object]
+impl Mutation {
+    async fn create_simulation(&self, ctx: &Context<'_>, input: NewSimulation) -> async_graphql::Result<Simulation> {
+        let mut sim = models::Simulation::new(input.id, input.name);
+        sim.description = input.description;
+        sim.genre = input.genre;
+        sim.author = input.author;
+        sim.tags = input.tags.unwrap_or_default().into_iter().collect();
+
+        let created = catalogue::create(&catalogue(ctx).db, policy(ctx), &actor(ctx), sim).await;
+        created.map(Simulation).map_err(error)
+    }
+
+    /// Changes the name only; unlike a PUT, it never creates the simulation.
+    async fn rename_simulation(&self, ctx: &Context<'_>, id: u64, name: String) -> async_graphql::Result<Simulation> {
+        let updated = catalogue::update(&catalogue(ctx).db, policy(ctx), &actor(ctx), id, NewName::new(name), false).await;
+        updated.map(|(sim, _)| Simulation(sim)).map_err(error)
+    }
+
+    /// Hands back the simulation as it was before deletion. Its room reservations are cancelled.
+    async fn delete_simulation(&self, ctx: &Context<'_>, id: u64) -> async_graphql::Result<Simulation> {
+        let Catalogue { db, tenant } = catalogue(ctx);
+        let deleted = catalogue::delete(db, ctx.data_unchecked(), tenant.as_deref(), &actor(ctx), id).await;
+        deleted.map(Simulation).map_err(error)
+    }
+}
+
+pub struct Subscription;
+
+#[Subscription]
+impl Subscription {
+    /// Every write to the catalogue from now on, whichever API made it; only those to `id` when given.
+    async fn simulation_changes(&self, ctx: &Context<'_>, id: Option<u64>) -> impl Stream<Item = SimulationChange> {
+        let changes = catalogue(ctx).db.snapshot().subscribe();
+        stream::unfold(changes, move |mut changes| async move {
+            loop {
+                match changes.recv().await {
+                    Ok(change) if id.is_none_or(|id| id == change.id) => return Some((change.into(), changes)),
+                    Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libs::characters::NewCharacter;
+    use async_graphql::futures_util::StreamExt;
+    use async_graphql::Request;
+    use serde_json::json;
+
+    fn of(db: &Db, request: impl Into<Request>) -> Request {
+        request.into().data(Catalogue { db: db.clone(), tenant: None })
+    }
+
+    fn data(response: async_graphql::Response) -> serde_json::Value {
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        response.data.into_json().unwrap()
+    }
+
+    #[tokio::test]
+    async fn try_queries() {
+        let db = models::new_db();
+        for (id, name, tags) in [(1, "The Big Goodbye", "noir"), (2, "Fair Haven", "village"), (3, "The Long Goodbye", "noir")] {
+            let mut sim = models::Simulation::new(id, name);
+            sim.tags.insert(tags.to_string());
+            sim.add_character(NewCharacter::new("Dixon Hill", "Detective"));
+            db.lock().await.insert(sim);
+        }
+        let schema = schema(rooms::new_db(), Arc::new(Policy::default()));
+
+        let query = r#"{
+            simulations(filter: {tags: ["noir"], name: "goodbye"}, offset: 1, limit: 1) {
+                total
+                items { id name state characters { name } }
+            }
+            simulation(id: 2) { tags }
+            missing: simulation(id: 9) { id }
+        }"#;
+        assert_eq!(
+            data(schema.execute(of(&db, query)).await),
+            json!({
+                "simulations": {
+                    "total": 2,
+                    "items": [{ "id": 3, "name": "The Long Goodbye", "state": "LOADED", "characters": [{ "name": "Dixon Hill" }] }],
+                },
+                "simulation": { "tags": ["village"] },
+                "missing": null,
+            })
+        );
+
+        let query = r#"{ simulations(filter: {tags: ["!noir"], state: LOADED}) { items { id } } }"#;
+        assert_eq!(data(schema.execute(of(&db, query)).await), json!({ "simulations": { "items": [{ "id": 2 }] } }));
+    }
+
+    #[tokio::test]
+    async fn try_mutations() {
+        let db = models::new_db();
+        let schema = schema(rooms::new_db(), Arc::new(Policy::default()));
+
+        let create = r#"mutation { createSimulation(input: {id: 1, name: "The Big Goodbye", tags: ["noir"]}) { id state } }"#;
+        assert_eq!(data(schema.execute(of(&db, create)).await), json!({ "createSimulation": { "id": 1, "state": "LOADED" } }));
+
+        // Same errors as the REST routes
+        let response = schema.execute(of(&db, create)).await;
+        assert_eq!(response.errors[0].message, "Simulation #1 already exists under the name The Big Goodbye");
+        let response = schema.execute(of(&db, r#"mutation { createSimulation(input: {id: 0, name: ""}) { id } }"#)).await;
+        let extensions = response.errors[0].extensions.as_ref().unwrap();
+        assert_eq!(extensions.get("code"), Some(&async_graphql::Value::from("INVALID")));
+
+        let rename = r#"mutation { renameSimulation(id: 1, name: "The Long Goodbye") { name tags } }"#;
+        assert_eq!(
+            data(schema.execute(of(&db, rename)).await),
+            json!({ "renameSimulation": { "name": "The Long Goodbye", "tags": ["noir"] } })
+        );
+        let response = schema.execute(of(&db, r#"mutation { renameSimulation(id: 2, name: "Nowhere") { id } }"#)).await;
+        assert_eq!(response.errors[0].message, "Simulation #2 does not exist.");
+
+        assert_eq!(
+            data(schema.execute(of(&db, r#"mutation { deleteSimulation(id: 1) { name } }"#)).await),
+            json!({ "deleteSimulation": { "name": "The Long Goodbye" } })
+        );
+        assert!(db.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn try_subscription() {
+        let db = models::new_db();
+        let schema = schema(rooms::new_db(), Arc::new(Policy::default()));
+
+        let mut changes = schema.execute_stream(of(&db, "subscription { simulationChanges { kind id simulation { name } } }"));
+        // Subscribing happens on the first poll, so the writes below must wait for it
+        let next = tokio::spawn(async move {
+            let first = changes.next().await.unwrap();
+            let second = changes.next().await.unwrap();
+            (first, second)
+        });
+        while db.snapshot().subscribers() == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        db.lock().await.insert(models::Simulation::new(1, "The Big Goodbye"));
+        schema.execute(of(&db, r#"mutation { deleteSimulation(id: 1) { id } }"#)).await;
+
+        let (first, second) = next.await.unwrap();
+        assert_eq!(
+            data(first),
+            json!({ "simulationChanges": { "kind": "CREATED", "id": 1, "simulation": { "name": "The Big Goodbye" } } })
+        );
+        assert_eq!(data(second), json!({ "simulationChanges": { "kind": "DELETED", "id": 1, "simulation": null } }));
+    }
+}
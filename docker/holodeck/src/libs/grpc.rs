@@ -0,0 +1,314 @@
+use futures_util::stream::{self, Stream};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+use tonic::metadata::MetadataMap;
+use tonic::transport::server::TcpIncoming;
+use tonic::transport::Server;
+use tonic::{Request, Response, Status};
+
+use super::catalogue;
+use super::changes::{self, Change};
+use super::lifecycle;
+use super::models::{self, Db, NewName};
+use super::rooms;
+use super::safety::{self, Actor, Policy};
+use super::tags::TagFilter;
+
+/// The messages, service and client `build.rs` generates from `proto/holodeck.proto`.
+pub mod pb {
+    tonic::include_proto!("holodeck");
+}
+
+impl From<&safety::Safety> for pb::Safety {
+    fn from(safety: &safety::Safety) -> Self {
+        pb::Safety {
+            protocols: safety.protocols,
+            reason: safety.reason.clone(),
+        }
+    }
+}
+
+impl From<pb::Safety> for safety::Safety {
+    fn from(safety: pb::Safety) -> Self {
+        safety::Safety {
+            protocols: safety.protocols,
+            reason: safety.reason,
+        }
+    }
+}
+
+impl From<&models::Simulation> for pb::Simulation {
+    fn from(sim: &models::Simulation) -> Self {
+        let state = match sim.state {
+            lifecycle::State::Loaded => pb::State::Loaded,
+            lifecycle::State::Running => pb::State::Running,
+            lifecycle::State::Paused => pb::State::Paused,
+            lifecycle::State::Ended => pb::State::Ended,
+        };
+        pb::Simulation {
+            id: sim.id,
+            name: sim.name.clone(),
+            description: sim.description.clone(),
+            genre: sim.genre.clone(),
+            author: sim.author.clone(),
+            tags: sim.tags.iter().cloned().collect(),
+            safety: Some((&sim.safety).into()),
+            state: state as i32,
+            created_at: sim.created_at.map(|at| at.to_rfc3339()).unwrap_or_default(),
+            updated_at: sim.updated_at.map(|at| at.to_rfc3339()).unwrap_or_default(),
+            characters: sim
+                .characters
+                .iter()
+                .map(|character| pb::Character {
+                    id: character.id,
+                    name: character.name.clone(),
+                    role: character.role.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+// What the server maintains itself is left behind, as in a REST body
+impl From<pb::Simulation> for models::Simulation {
+    fn from(sim: pb::Simulation) -> Self {
+        let mut new = models::Simulation::new(sim.id, sim.name);
+        new.description = sim.description;
+        new.genre = sim.genre;
+        new.author = sim.author;
+        new.tags = sim.tags.into_iter().collect();
+        new.safety = sim.safety.map(Into::into).unwrap_or_default();
+        new
+    }
+}
+
+impl From<pb::UpdateRequest> for NewName {
+    fn from(update: pb::UpdateRequest) -> Self {
+        NewName {
+            name: update.name,
+            description: update.description,
+            genre: update.genre,
+            author: update.author,
+            tags: update.tags.map(|tags| tags.tags.into_iter().collect()),
+            safety: update.safety.map(Into::into),
+        }
+    }
+}
+
+impl From<Change> for pb::Change {
+    fn from(change: Change) -> Self {
+        let kind = match change.kind {
+            changes::ChangeKind::Created => pb::ChangeKind::Created,
+            changes::ChangeKind::Updated => pb::ChangeKind::Updated,
+            changes::ChangeKind::Deleted => pb::ChangeKind::Deleted,
+        };
+        pb::Change {
+            kind: kind as i32,
+            id: change.id,
+            simulation: change.simulation.as_ref().map(Into::into),
+        }
+    }
+}
+
+fn status(e: catalogue::Error) -> Status {
+    let message = e.to_string();
+    match e {
+        catalogue::Error::Exists { .. } => Status::already_exists(message),
+        catalogue::Error::NotFound(_) => Status::not_found(message),
+        catalogue::Error::Invalid(_) => Status::invalid_argument(message),
+        catalogue::Error::Forbidden(_) => Status::permission_denied(message),
+        catalogue::Error::QuotaExceeded(_) => Status::resource_exhausted(message),
+    }
+}
+
+// The same `x-holodeck-*` headers as REST requests, sent as metadata
+fn actor(metadata: &MetadataMap) -> Actor {
+    let header = |key: &str| metadata.get(key).and_then(|value| value.to_str().ok()).map(str::to_string);
+    Actor::from_headers(header("x-holodeck-user"), header("x-holodeck-roles"), header("x-holodeck-approved-by"))
+}
+
+/// The `Holodeck` service, over the same rooms and safety rules as the REST routes. It has no notion of
+/// tenants: `db` is the default tenant's catalogue, and the only one it serves.
+#[derive(Clone)]
+pub struct Holodeck {
+    db: Db,
+    rooms: rooms::Db,
+    policy: Arc<Policy>,
+}
+
+impl Holodeck {
+    pub fn new(db: Db, rooms: rooms::Db, policy: Arc<Policy>) -> Holodeck {
+        Holodeck { db, rooms, policy }
+    }
+}
+
+type ChangeStream = Pin<Box<dyn Stream<Item = Result<pb::Change, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl pb::holodeck_server::Holodeck for Holodeck {
+    async fn list(&self, request: Request<pb::ListRequest>) -> Result<Response<pb::ListResponse>, Status> {
+        let request = request.into_inner();
+        let mut tags = TagFilter::default();
+        for tag in &request.tags {
+            tags.push(tag);
+        }
+
+        let map = self.db.snapshot();
+        let mut simulations: Vec<pb::Simulation> = map.tagged(&tags).into_iter().map(Into::into).collect();
+        simulations.sort_by_key(|sim| sim.id);
+        if !request.characters {
+            simulations.iter_mut().for_each(|sim| sim.characters.clear());
+        }
+        Ok(Response::new(pb::ListResponse { simulations }))
+    }
+
+    async fn get(&self, request: Request<pb::GetRequest>) -> Result<Response<pb::Simulation>, Status> {
+        let id = request.into_inner().id;
+        match models::get_simulation(&self.db.snapshot(), id) {
+            Some(sim) => Ok(Response::new(sim.into())),
+            None => Err(status(catalogue::Error::NotFound(id))),
+        }
+    }
+
+    async fn create(&self, request: Request<pb::Simulation>) -> Result<Response<pb::Simulation>, Status> {
+        let actor = actor(request.metadata());
+        let created = catalogue::create(&self.db, &self.policy, &actor, request.into_inner().into()).await;
+        created.map(|sim| Response::new((&sim).into())).map_err(status)
+    }
+
+    async fn update(&self, request: Request<pb::UpdateRequest>) -> Result<Response<pb::Simulation>, Status> {
+        let actor = actor(request.metadata());
+        let request = request.into_inner();
+        let updated = catalogue::update(&self.db, &self.policy, &actor, request.id, request.into(), false).await;
+        updated.map(|(sim, _)| Response::new((&sim).into())).map_err(status)
+    }
+
+    async fn delete(&self, request: Request<pb::DeleteRequest>) -> Result<Response<pb::Simulation>, Status> {
+        let actor = actor(request.metadata());
+        let deleted = catalogue::delete(&self.db, &self.rooms, None, &actor, request.into_inner().id).await;
+        deleted.map(|sim| Response::new((&sim).into())).map_err(status)
+    }
+
+    type WatchStream = ChangeStream;
+
+    async fn watch(&self, request: Request<pb::WatchRequest>) -> Result<Response<ChangeStream>, Status> {
+        let id = request.into_inner().id;
+        let changes = self.db.snapshot().subscribe();
+        let changes = stream::unfold(changes, move |mut changes| async move {
+            loop {
+                match changes.recv().await {
+                    Ok(change) if id.is_none_or(|id| id == change.id) => return Some((Ok(change.into()), changes)),
+                    Ok(_) => continue,
+                    // Better the client hears it missed something than think it saw everything
+                    Err(RecvError::Lagged(missed)) => {
+                        let lagged = Status::data_loss(format!("Fell behind and missed {} change(s).", missed));
+                        return Some((Err(lagged), changes));
+                    }
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        });
+        Ok(Response::new(Box::pin(changes)))
+    }
+}
+
+/// Serves the service on `incoming` with `server`, plain or TLS, until `shutdown` completes.
+pub async fn serve(
+    mut server: Server,
+    incoming: TcpIncoming,
+    db: Db,
+    rooms: rooms::Db,
+    policy: Arc<Policy>,
+    shutdown: impl Future<Output = ()>,
+) -> Result<(), tonic::transport::Error> {
+    server
+        .add_service(pb::holodeck_server::HolodeckServer::new(Holodeck::new(db, rooms, policy)))
+        .serve_with_incoming_shutdown(incoming, shutdown)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pb::holodeck_client::HolodeckClient;
+    use tonic::transport::Channel;
+    use std::net::SocketAddr;
+    use tokio::net::TcpListener;
+    use tonic::Code;
+
+    async fn spawn(db: Db, policy: Arc<Policy>) -> SocketAddr {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let incoming = TcpIncoming::from_listener(listener, true, None).unwrap();
+        tokio::spawn(serve(Server::builder(), incoming, db, rooms::new_db(), policy, futures_util::future::pending()));
+        addr
+    }
+
+    #[tokio::test]
+    async fn try_service() {
+        let db = models::new_db();
+        let addr = spawn(db.clone(), Arc::new(Policy::default())).await;
+        let channel = Channel::from_shared(format!("http://{}", addr)).unwrap().connect().await.unwrap();
+        let mut client = HolodeckClient::new(channel);
+
+        let mut watch = client.watch(pb::WatchRequest { id: Some(1) }).await.unwrap().into_inner();
+
+        let created = client
+            .create(pb::Simulation {
+                id: 1,
+                name: "The Big Goodbye".to_string(),
+                tags: vec!["noir".to_string()],
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(created.safety.map(|safety| safety.protocols), Some(true));
+        assert!(!created.created_at.is_empty());
+
+        // The store is shared with REST, so writes made there are seen too
+        db.lock().await.insert(models::Simulation::new(2, "Fair Haven"));
+        let listed = client.list(pb::ListRequest { tags: vec!["!noir".to_string()], characters: false }).await.unwrap();
+        let names: Vec<String> = listed.into_inner().simulations.into_iter().map(|sim| sim.name).collect();
+        assert_eq!(names, ["Fair Haven"]);
+
+        let update = pb::UpdateRequest { id: 1, name: "The Long Goodbye".to_string(), ..Default::default() };
+        let updated = client.update(update).await.unwrap().into_inner();
+        assert_eq!((updated.name.as_str(), updated.tags.as_slice()), ("The Long Goodbye", &["noir".to_string()][..]));
+
+        let error = client.create(pb::Simulation { id: 1, name: "Again".to_string(), ..Default::default() }).await.unwrap_err();
+        assert_eq!(error.code(), Code::AlreadyExists);
+        let error = client.create(pb::Simulation { id: 0, name: String::new(), ..Default::default() }).await.unwrap_err();
+        assert_eq!(error.code(), Code::InvalidArgument);
+        let update = pb::UpdateRequest { id: 3, name: "Nowhere".to_string(), ..Default::default() };
+        assert_eq!(client.update(update).await.unwrap_err().code(), Code::NotFound);
+
+        // Turning the protocols off takes an admin and two approvals, as over REST
+        let unsafe_sim = pb::Simulation {
+            id: 3,
+            name: "Klingon Calisthenics".to_string(),
+            safety: Some(pb::Safety { protocols: false, reason: None }),
+            ..Default::default()
+        };
+        let error = client.create(unsafe_sim.clone()).await.unwrap_err();
+        assert_eq!(error.code(), Code::PermissionDenied);
+        let mut request = Request::new(unsafe_sim);
+        request.metadata_mut().insert("x-holodeck-user", "worf".parse().unwrap());
+        request.metadata_mut().insert("x-holodeck-roles", "admin".parse().unwrap());
+        request.metadata_mut().insert("x-holodeck-approved-by", "picard,riker".parse().unwrap());
+        assert!(client.create(request).await.is_ok());
+
+        assert_eq!(client.delete(pb::DeleteRequest { id: 1 }).await.unwrap().into_inner().name, "The Long Goodbye");
+        assert_eq!(client.get(pb::GetRequest { id: 1 }).await.unwrap_err().code(), Code::NotFound);
+
+        let kinds: Vec<i32> = vec![
+            watch.message().await.unwrap().unwrap().kind,
+            watch.message().await.unwrap().unwrap().kind,
+            watch.message().await.unwrap().unwrap().kind,
+        ];
+        let expected = [pb::ChangeKind::Created, pb::ChangeKind::Updated, pb::ChangeKind::Deleted];
+        assert_eq!(kinds, expected.iter().map(|kind| *kind as i32).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,269 @@
+use serde::Serialize;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::future::Future;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use warp::http::{HeaderMap, StatusCode};
+use warp::hyper::body::{self, Bytes};
+use warp::reply::Response;
+use warp::Reply;
+
+/// Header a client sets to make retrying a POST safe.
+pub const HEADER: &str = "idempotency-key";
+
+/// How long responses are kept for replay unless `HOLODECK_IDEMPOTENCY_TTL` says otherwise.
+pub const DEFAULT_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+// Keys are only unique per tenant, like everything else in the catalogue, and per user, so nobody
+// is replayed a response that was made for somebody else
+type Key = (Option<String>, Option<String>, String);
+
+#[derive(Debug)]
+enum Entry {
+    /// The first request with the key is still being handled.
+    InFlight(u64),
+    Done { fingerprint: u64, stored: Stored, at: Instant },
+}
+
+#[derive(Clone, Debug)]
+struct Stored {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+impl Stored {
+    fn response(&self) -> Response {
+        let mut response = Response::new(self.body.clone().into());
+        *response.status_mut() = self.status;
+        *response.headers_mut() = self.headers.clone();
+        response
+    }
+}
+
+/// Responses to POSTs made with an `Idempotency-Key`, kept in memory for `ttl`.
+#[derive(Clone, Debug)]
+pub struct Cache {
+    ttl: Duration,
+    entries: Arc<Mutex<HashMap<Key, Entry>>>,
+}
+
+impl Default for Cache {
+    fn default() -> Self {
+        Cache::new(DEFAULT_TTL)
+    }
+}
+
+enum Begin {
+    Fresh,
+    Replay(Stored),
+    Mismatch,
+    InFlight,
+}
+
+impl Cache {
+    pub fn new(ttl: Duration) -> Cache {
+        Cache {
+            ttl,
+            entries: Arc::default(),
+        }
+    }
+
+    fn begin(&self, key: &Key, fingerprint: u64) -> Begin {
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        let ttl = self.ttl;
+        entries.retain(|_, entry| match entry {
+            Entry::Done { at, .. } => at.elapsed() < ttl,
+            Entry::InFlight(_) => true,
+        });
+
+        match entries.get(key) {
+            None => {
+                entries.insert(key.clone(), Entry::InFlight(fingerprint));
+                Begin::Fresh
+            }
+            Some(Entry::InFlight(first)) | Some(Entry::Done { fingerprint: first, .. }) if *first != fingerprint => Begin::Mismatch,
+            Some(Entry::InFlight(_)) => Begin::InFlight,
+            Some(Entry::Done { stored, .. }) => Begin::Replay(stored.clone()),
+        }
+    }
+}
+
+/// What a request asked for, down to its decoded body, so a retry can be told from a different
+/// request that reuses the key.
+pub fn fingerprint<T: Serialize>(method: &str, path: &str, body: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    method.hash(&mut hasher);
+    path.hash(&mut hasher);
+    // Serializing to memory only fails for maps with non-string keys, which no body has
+    serde_json::to_vec(body).unwrap_or_default().hash(&mut hasher);
+    hasher.finish()
+}
+
+/// A request's claim on its `Idempotency-Key`, if it sent one.
+#[derive(Debug)]
+pub struct Claim {
+    cache: Cache,
+    key: Option<Key>,
+    fingerprint: u64,
+}
+
+impl Claim {
+    /// `user` is the request's `X-Holodeck-User`.
+    pub fn new(cache: Cache, key: Option<String>, tenant: Option<String>, user: Option<String>, fingerprint: u64) -> Claim {
+        Claim {
+            cache,
+            key: key.map(|key| (tenant, user, key)),
+            fingerprint,
+        }
+    }
+
+    /// Runs `handling` unless the key was seen before: then the first response is replayed as it
+    /// was, or the request is refused when it differs from the first one or that one is still running.
+    pub async fn run<F, R>(self, handling: F) -> Result<Response, Infallible>
+    where
+        F: Future<Output = Result<R, Infallible>>,
+        R: Reply,
+    {
+        let key = match self.key {
+            Some(key) => key,
+            None => return Ok(handling.await?.into_response()),
+        };
+        match self.cache.begin(&key, self.fingerprint) {
+            Begin::Fresh => {}
+            Begin::Replay(stored) => return Ok(stored.response()),
+            Begin::Mismatch => return Ok(warp::reply::with_status(
+                format!("Idempotency-Key {:?} was already used for a different request.\n", key.2),
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ).into_response()),
+            Begin::InFlight => return Ok(warp::reply::with_status(
+                format!("A request with Idempotency-Key {:?} is still being handled.\n", key.2),
+                StatusCode::CONFLICT,
+            ).into_response()),
+        }
+
+        let mut pending = Pending {
+            cache: &self.cache,
+            key: Some(key),
+        };
+        let (parts, body) = handling.await?.into_response().into_parts();
+        // Replies are built in memory, so buffering them costs nothing extra
+        let body = match body::to_bytes(body).await {
+            Ok(body) => body,
+            Err(e) => {
+                eprintln!("Failed to read a reply body: {}", e);
+                return Ok(Response::from_parts(parts, body::Body::empty()));
+            }
+        };
+        let stored = Stored {
+            status: parts.status,
+            headers: parts.headers.clone(),
+            body: body.clone(),
+        };
+        // Server errors may not happen again, so those are worth retrying for real
+        if !stored.status.is_server_error() {
+            pending.finish(stored, self.fingerprint);
+        }
+        Ok(Response::from_parts(parts, body.into()))
+    }
+}
+
+// Frees the key when the request never finishes, say because the client went away, so a retry can run
+struct Pending<'a> {
+    cache: &'a Cache,
+    key: Option<Key>,
+}
+
+impl Pending<'_> {
+    fn finish(&mut self, stored: Stored, fingerprint: u64) {
+        if let Some(key) = self.key.take() {
+            let mut entries = self.cache.entries.lock().unwrap_or_else(PoisonError::into_inner);
+            entries.insert(key, Entry::Done { fingerprint, stored, at: Instant::now() });
+        }
+    }
+}
+
+impl Drop for Pending<'_> {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.cache.entries.lock().unwrap_or_else(PoisonError::into_inner).remove(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn try_claim() {
+        let cache = Cache::new(Duration::from_millis(100));
+        let runs = AtomicUsize::new(0);
+        let handle = |name: &'static str| {
+            let runs = &runs;
+            async move {
+                runs.fetch_add(1, Ordering::SeqCst);
+                Ok(warp::reply::with_status(format!("{} created.\n", name), StatusCode::CREATED))
+            }
+        };
+        let claim_as = |key: Option<&str>, tenant: Option<&str>, user: Option<&str>, name: &str| {
+            let fingerprint = fingerprint("POST", "/holodeck", &name);
+            Claim::new(cache.clone(), key.map(str::to_string), tenant.map(str::to_string), user.map(str::to_string), fingerprint)
+        };
+        let claim = |key: Option<&str>, tenant: Option<&str>, name: &str| claim_as(key, tenant, None, name);
+
+        let first = claim(Some("k1"), None, "Fair Haven").run(handle("Fair Haven")).await.unwrap();
+        assert_eq!(first.status(), StatusCode::CREATED);
+        let again = claim(Some("k1"), None, "Fair Haven").run(handle("Fair Haven")).await.unwrap();
+        assert_eq!(again.status(), StatusCode::CREATED);
+        assert_eq!(body::to_bytes(again.into_body()).await.unwrap(), "Fair Haven created.\n");
+        let different = claim(Some("k1"), None, "Sherlock").run(handle("Sherlock")).await.unwrap();
+        assert_eq!(different.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+
+        // Other tenants, other users, and requests without a key, are never replayed
+        claim(Some("k1"), Some("enterprise"), "Sherlock").run(handle("Sherlock")).await.unwrap();
+        let other = claim_as(Some("k1"), None, Some("barclay"), "Fair Haven").run(handle("Fair Haven")).await.unwrap();
+        assert_eq!(other.status(), StatusCode::CREATED);
+        claim(None, None, "Sherlock").run(handle("Sherlock")).await.unwrap();
+        assert_eq!(cache.entries.lock().unwrap().len(), 3);
+
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        let expired = claim(Some("k1"), None, "Sherlock").run(handle("Sherlock")).await.unwrap();
+        assert_eq!(expired.status(), StatusCode::CREATED);
+        assert_eq!(runs.load(Ordering::SeqCst), 5);
+    }
+
+    #[tokio::test]
+    async fn try_in_flight() {
+        let cache = Cache::default();
+        let key = || Some("k1".to_string());
+        let first = Claim::new(cache.clone(), key(), None, None, 1);
+        let (started, wait) = tokio::sync::oneshot::channel::<()>();
+        let (release, released) = tokio::sync::oneshot::channel::<()>();
+        let running = tokio::spawn(first.run(async move {
+            started.send(()).unwrap();
+            released.await.unwrap();
+            Ok::<_, Infallible>("Done")
+        }));
+        wait.await.unwrap();
+
+        let second = Claim::new(cache.clone(), key(), None, None, 1).run(async { Ok::<_, Infallible>("Done") }).await.unwrap();
+        assert_eq!(second.status(), StatusCode::CONFLICT);
+        release.send(()).unwrap();
+        assert_eq!(running.await.unwrap().unwrap().status(), StatusCode::OK);
+
+        // Abandoned requests free their key
+        let abandoned = tokio::spawn(Claim::new(cache.clone(), Some("k2".to_string()), None, None, 1).run(async {
+            futures_util::future::pending::<Result<&str, Infallible>>().await
+        }));
+        tokio::task::yield_now().await;
+        abandoned.abort();
+        let _ = abandoned.await;
+        assert!(!cache.entries.lock().unwrap().contains_key(&(None, None, "k2".to_string())));
+    }
+}
@@ -1,320 +1,2871 @@
+pub mod audit;
+pub mod catalogue;
+pub mod changes;
+pub mod codec;
+pub mod compression;
+pub mod conditional;
+pub mod config;
+pub mod cors;
+pub mod graphql;
+pub mod grpc;
+pub mod idempotency;
+pub mod openapi;
+pub mod replication;
+pub mod rooms;
+pub mod safety;
+pub mod shutdown;
+pub mod tags;
+pub mod tenants;
+pub mod tls;
+
+// Shared with clients, through `holodeck-models`
+pub use holodeck_models::{backup, characters, lifecycle, validation};
+
 pub mod models {
+    use chrono::{DateTime, Utc};
     use serde::{Deserialize, Serialize};
-    use std::collections::HashSet;
-    use std::hash::{Hash, Hasher};
-    use std::sync::Arc;
-    use tokio::sync::Mutex;
+    use std::collections::{BTreeMap, BTreeSet, HashSet};
+    use std::fs;
+    use std::io;
+    use std::iter::FromIterator;
+    use std::mem;
+    use std::ops::{Deref, DerefMut};
+    use std::path::Path;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{Arc, PoisonError, RwLock};
+    use tokio::sync::{broadcast, Mutex, MutexGuard};
+
+    use super::audit;
+    use super::changes::{self, Change, ChangeKind};
+    use super::tags::{TagFilter, TagIndex};
 
-    #[derive(Clone, Debug, Deserialize, Serialize)]
-    pub struct Simulation {
-        pub id: u64,
-        pub name: String,
+    pub use holodeck_models::models::{ApiError, NewName, Simulation, SCHEMA_VERSION};
+
+    pub fn get_simulation(sims: &Simulations, id: u64) -> Option<&Simulation>{
+        sims.get(id)
     }
 
-    #[derive(Debug, Deserialize, Serialize)]
-    pub struct NewName{ pub name: String }
+    /// Query string of the list route, e.g. `?expand=characters&tag=noir&tag=!combat`.
+    #[derive(Clone, Debug, Default, PartialEq)]
+    pub struct ListOptions {
+        pub characters: bool,
+        pub tags: TagFilter,
+    }
 
-    impl PartialEq for Simulation{
-        //https://doc.rust-lang.org/std/cmp/trait.Eq.html
-        fn eq(&self, other: &Self) -> bool {
-            self.id == other.id
+    impl ListOptions {
+        // Parameters are repeatable, so they come in as pairs rather than a struct; unknown ones are ignored
+        pub fn parse(query: &[(String, String)]) -> Result<ListOptions, String> {
+            let mut options = ListOptions::default();
+            for (key, value) in query {
+                match key.as_str() {
+                    "expand" => {
+                        for part in value.split(',').map(str::trim).filter(|part| !part.is_empty()) {
+                            if part != "characters" {
+                                return Err(format!("Cannot expand {:?}; only \"characters\" can be.", part));
+                            }
+                            options.characters = true;
+                        }
+                    }
+                    "tag" => options.tags.push(value),
+                    _ => {}
+                }
+            }
+            Ok(options)
         }
     }
-    
-    impl Eq for Simulation {}
-    
-    impl Hash for Simulation{
-        fn hash<H: Hasher>(&self, state: &mut H){
-                self.id.hash(state);
+
+    /// The simulations by id. Cloning shares structure with the original rather than copying it,
+    /// which is what makes `Db` snapshots cheap.
+    #[derive(Clone, Debug, Default)]
+    pub struct Simulations(im::HashMap<u64, Simulation>);
+
+    impl Simulations {
+        pub fn get(&self, id: u64) -> Option<&Simulation> {
+            self.0.get(&id)
+        }
+
+        pub fn contains(&self, id: u64) -> bool {
+            self.0.contains_key(&id)
+        }
+
+        pub fn len(&self) -> usize {
+            self.0.len()
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.0.is_empty()
+        }
+
+        pub fn iter(&self) -> impl Iterator<Item = &Simulation> {
+            self.0.values()
         }
     }
 
-    pub fn get_simulation<'a>(sims: &'a HashSet<Simulation>, id: u64) -> Option<&'a Simulation>{
-        sims.get(&Simulation{
-            id,
-            name: String::new(),
-        })
+    // Same shape as the `HashSet<Simulation>` data files have always held
+    impl Serialize for Simulations {
+        fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.collect_seq(self.iter())
+        }
+    }
+
+    impl<'de> Deserialize<'de> for Simulations {
+        fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            Vec::<Simulation>::deserialize(deserializer).map(Simulations::from_iter)
+        }
+    }
+
+    // Later simulations win over earlier ones with the same id
+    impl FromIterator<Simulation> for Simulations {
+        fn from_iter<I: IntoIterator<Item = Simulation>>(iter: I) -> Self {
+            Simulations(iter.into_iter().map(|sim| (sim.id, sim)).collect())
+        }
+    }
+
+    /// The simulations, indexed by tag. Reads go straight to the `Simulations`; writes go through
+    /// `insert`, `replace` and `remove` so the index never falls out of step and every write is published.
+    #[derive(Clone, Debug)]
+    pub struct Store {
+        sims: Simulations,
+        tags: TagIndex,
+        changes: broadcast::Sender<Change>,
+        epoch: String,
+        revision: u64,
+        modified_at: DateTime<Utc>,
+        modified: im::HashMap<u64, DateTime<Utc>>,
+        quota: Option<usize>,
+        audit: audit::Log,
+        // Writes made through a `WriteGuard`, sent out once the guard has published the store
+        unsent: Vec<Change>,
+        unrecorded: Vec<audit::Entry>,
+    }
+
+    impl Default for Store {
+        fn default() -> Self {
+            Store {
+                sims: Simulations::default(),
+                tags: TagIndex::default(),
+                changes: broadcast::channel(changes::BUFFER).0,
+                epoch: epoch(),
+                revision: 0,
+                modified_at: Utc::now(),
+                modified: im::HashMap::new(),
+                quota: None,
+                audit: audit::Log::default(),
+                unsent: Vec::new(),
+                unrecorded: Vec::new(),
+            }
+        }
+    }
+
+    // Tells stores apart: when the process started them, and which it was
+    fn epoch() -> String {
+        static STORES: AtomicU64 = AtomicU64::new(0);
+        let started = Utc::now().timestamp_nanos_opt().unwrap_or_default();
+        format!("{:x}.{}", started, STORES.fetch_add(1, Ordering::Relaxed))
+    }
+
+    impl Deref for Store {
+        type Target = Simulations;
+
+        fn deref(&self) -> &Simulations {
+            &self.sims
+        }
+    }
+
+    impl Store {
+        /// Like `HashSet::insert`: leaves the store alone and answers `false` when the id is taken.
+        pub fn insert(&mut self, sim: Simulation) -> bool {
+            if self.sims.contains(sim.id) {
+                return false;
+            }
+            self.tags.add(sim.id, &sim.tags);
+            self.publish(Change::written(ChangeKind::Created, &sim));
+            self.sims.0.insert(sim.id, sim);
+            true
+        }
+
+        pub fn replace(&mut self, sim: Simulation) -> Option<Simulation> {
+            self.tags.add(sim.id, &sim.tags);
+            let kind = if self.sims.contains(sim.id) { ChangeKind::Updated } else { ChangeKind::Created };
+            self.publish(Change::written(kind, &sim));
+            let old = self.sims.0.insert(sim.id, sim)?;
+            let kept = &self.sims.get(old.id)?.tags;
+            self.tags.remove(old.id, old.tags.difference(kept));
+            Some(old)
+        }
+
+        pub fn remove(&mut self, sim: &Simulation) -> bool {
+            match self.sims.0.remove(&sim.id) {
+                Some(old) => {
+                    self.tags.remove(old.id, &old.tags);
+                    self.publish(Change::deleted(old.id));
+                    true
+                }
+                None => false,
+            }
+        }
+
+        /// Swaps every simulation for `sims` in one write, publishing the removals and replacements.
+        pub fn restore(&mut self, sims: Vec<Simulation>) {
+            let kept: BTreeSet<u64> = sims.iter().map(|sim| sim.id).collect();
+            let gone: Vec<Simulation> = self.sims.iter().filter(|sim| !kept.contains(&sim.id)).cloned().collect();
+            for sim in &gone {
+                self.remove(sim);
+            }
+            for sim in sims {
+                self.replace(sim);
+            }
+        }
+
+        /// Every write made from now on. A subscriber falling more than `changes::BUFFER` behind skips ahead.
+        pub fn subscribe(&self) -> broadcast::Receiver<Change> {
+            self.changes.subscribe()
+        }
+
+        /// How many subscribers are listening for writes.
+        pub fn subscribers(&self) -> usize {
+            self.changes.receiver_count()
+        }
+
+        /// How many writes have been published; goes up by one with each change sent to subscribers.
+        pub fn revision(&self) -> u64 {
+            self.revision
+        }
+
+        /// Different for every store the process creates, and again after a restart, so together with
+        /// `revision` it names one state of one store, which the revision alone does not.
+        pub fn epoch(&self) -> &str {
+            &self.epoch
+        }
+
+        /// When the last write was made, or the store created if there has been none.
+        pub fn modified_at(&self) -> DateTime<Utc> {
+            self.modified_at
+        }
+
+        /// When the simulation was last written; `None` when there is no such simulation.
+        pub fn modified(&self, id: u64) -> Option<DateTime<Utc>> {
+            self.modified.get(&id).copied()
+        }
+
+        fn publish(&mut self, change: Change) {
+            self.revision += 1;
+            self.modified_at = Utc::now();
+            match change.kind {
+                ChangeKind::Deleted => self.modified.remove(&change.id),
+                _ => self.modified.insert(change.id, self.modified_at),
+            };
+            self.unsent.push(change);
+        }
+
+        /// Most simulations the store may hold, for `catalogue` to enforce; `None` for no limit.
+        pub fn quota(&self) -> Option<usize> {
+            self.quota
+        }
+
+        pub fn set_quota(&mut self, quota: Option<usize>) {
+            self.quota = quota;
+        }
+
+        /// Who changed what. Unlike the simulations, it is shared by every snapshot rather than copied.
+        pub fn audit(&self) -> &audit::Log {
+            &self.audit
+        }
+
+        /// Adds `entry` to the audit log along with the write it describes, once that is published.
+        pub fn record(&mut self, entry: audit::Entry) {
+            self.unrecorded.push(entry);
+        }
+
+        // What was written since the last call, for subscribers and the audit log
+        fn take_unsent(&mut self) -> (Vec<Change>, Vec<audit::Entry>) {
+            (mem::take(&mut self.unsent), mem::take(&mut self.unrecorded))
+        }
+
+        pub fn set_audit(&mut self, audit: audit::Log) {
+            self.audit = audit;
+        }
+
+        /// How many simulations carry each tag.
+        pub fn tag_counts(&self) -> BTreeMap<String, usize> {
+            self.tags.counts()
+        }
+
+        /// Simulations matching `filter`, found through the index whenever it names a tag to include.
+        pub fn tagged(&self, filter: &TagFilter) -> Vec<&Simulation> {
+            match self.tags.matching(filter) {
+                Some(ids) => ids.into_iter().filter_map(|id| self.sims.get(id)).collect(),
+                None => {
+                    let excluded = self.tags.any(&filter.exclude);
+                    self.sims.iter().filter(|sim| !excluded.contains(&sim.id)).collect()
+                }
+            }
+        }
+    }
+
+    impl FromIterator<Simulation> for Store {
+        fn from_iter<I: IntoIterator<Item = Simulation>>(iter: I) -> Self {
+            let mut store = Store::default();
+            for sim in iter {
+                store.replace(sim);
+            }
+            store
+        }
+    }
+
+    /// The catalogue, shared by every request. Readers take a `snapshot` of the store as it was after
+    /// the last write and never wait on writers, which take turns through `lock` and work on a copy
+    /// that replaces the snapshot once they are done.
+    #[derive(Clone, Debug, Default)]
+    pub struct Db {
+        shared: Arc<Shared>,
+    }
+
+    #[derive(Debug, Default)]
+    struct Shared {
+        // Only ever held long enough to clone or swap the `Arc`
+        snapshot: RwLock<Arc<Store>>,
+        writer: Mutex<()>,
+    }
+
+    impl From<Store> for Db {
+        fn from(mut store: Store) -> Self {
+            // Nobody can have subscribed to a store that was not shared yet
+            store.take_unsent();
+            Db {
+                shared: Arc::new(Shared {
+                    snapshot: RwLock::new(Arc::new(store)),
+                    writer: Mutex::new(()),
+                }),
+            }
+        }
+    }
+
+    impl Db {
+        /// The store as of the last finished write; later writes do not show up in it.
+        pub fn snapshot(&self) -> Arc<Store> {
+            self.shared.snapshot.read().unwrap_or_else(PoisonError::into_inner).clone()
+        }
+
+        /// Waits for the writers before this one. What is done through the guard becomes visible to
+        /// readers all at once, when it is dropped.
+        pub async fn lock(&self) -> WriteGuard<'_> {
+            let writer = self.shared.writer.lock().await;
+            WriteGuard {
+                store: Some(Store::clone(&self.snapshot())),
+                shared: &self.shared,
+                _writer: writer,
+            }
+        }
+    }
+
+    pub struct WriteGuard<'a> {
+        store: Option<Store>,
+        shared: &'a Shared,
+        _writer: MutexGuard<'a, ()>,
+    }
+
+    impl Deref for WriteGuard<'_> {
+        type Target = Store;
+
+        fn deref(&self) -> &Store {
+            self.store.as_ref().expect("store taken before drop")
+        }
+    }
+
+    impl DerefMut for WriteGuard<'_> {
+        fn deref_mut(&mut self) -> &mut Store {
+            self.store.as_mut().expect("store taken before drop")
+        }
     }
 
-    pub type Db = Arc<Mutex<HashSet<Simulation>>>;
+    impl Drop for WriteGuard<'_> {
+        fn drop(&mut self) {
+            if let Some(mut store) = self.store.take() {
+                let (changes, entries) = store.take_unsent();
+                let (sender, audit) = (store.changes.clone(), store.audit.clone());
+                *self.shared.snapshot.write().unwrap_or_else(PoisonError::into_inner) = Arc::new(store);
+                // Only now, so whoever hears of a write finds it in the snapshot; the writer lock is
+                // still held, which keeps them in order with the next write's
+                for entry in entries {
+                    audit.record(entry);
+                }
+                for change in changes {
+                    // Nobody listening is not an error
+                    let _ = sender.send(change);
+                }
+            }
+        }
+    }
 
     #[allow(dead_code)]
     pub fn new_db() -> Db {
-        Arc::new(Mutex::new(HashSet::new()))
+        Db::default()
+    }
+
+    // Starts empty when the file does not exist yet; older snapshots are brought up to the current schema
+    pub fn load_db(path: &Path) -> io::Result<Db> {
+        let sims: HashSet<Simulation> = match fs::read(path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashSet::new(),
+            Err(e) => return Err(e),
+        };
+        let sims: Store = sims
+            .into_iter()
+            .map(|mut sim| {
+                sim.schema_version = SCHEMA_VERSION;
+                sim
+            })
+            .collect();
+        Ok(Db::from(sims))
+    }
+
+    // Writes to a temporary file first so a crash never leaves a half-written snapshot behind
+    pub async fn save_db(db: &Db, path: &Path) -> io::Result<usize> {
+        let sims = db.snapshot();
+        let bytes = serde_json::to_vec(&sims.sims)?;
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, bytes)?;
+        fs::rename(&tmp, path)?;
+        Ok(sims.len())
     }
 }
 
 #[allow(dead_code)]
 pub mod filters{
-    use warp::Filter;
-    use super::{handlers, models};
+    use warp::hyper::body::Bytes;
+    use serde::de::DeserializeOwned;
+    use std::sync::Arc;
+    use warp::filters::BoxedFilter;
+    use warp::{Filter, Rejection};
+    use super::{audit, conditional, graphql, handlers, idempotency, models, openapi, rooms, safety, tenants};
+    use super::tenants::{NewTenant, Tenants};
+    use super::codec::Format;
+    use super::config::Compat;
+    use super::characters::NewCharacter;
+    use super::lifecycle::Action;
+    use super::validation::{validate_update, Validate, ValidationErrors};
+
+    /// What the filters in `FILTERS` are built from.
+    #[derive(Clone)]
+    pub struct Context {
+        pub tenants: Tenants,
+        pub rooms: rooms::Db,
+        pub policy: Arc<safety::Policy>,
+        pub idempotency: idempotency::Cache,
+        pub compat: Compat,
+        /// What followers must present to `GET /replication`, which is refused to everyone when unset.
+        pub replication_secret: Option<String>,
+        /// The leader's base URL when this server follows one; GraphQL mutations are sent there.
+        pub leader: Option<String>,
+    }
+
+    /// Where a filter is served: catalogue filters are served again under `/v2`, with `Compat::V2`.
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub enum Mount {
+        Catalogue,
+        Root,
+    }
+
+    pub type Route = BoxedFilter<(warp::reply::Response,)>;
+
+    /// Builds one of the filters in `FILTERS`.
+    pub type Build = fn(&Context) -> Route;
+
+    /// Every filter the server serves, by name, in the order they are tried. `routes` is built from
+    /// this table and nothing else, and `openapi::spec` must document each of them.
+    pub const FILTERS: &[(&str, Mount, Build)] = &[
+        ("list_sims", Mount::Catalogue, |cx| boxed(list_sims(cx.tenants.clone()))),
+        ("get_sim", Mount::Catalogue, |cx| boxed(get_sim(cx.tenants.clone(), cx.compat))),
+        ("post_sim", Mount::Catalogue, |cx| boxed(post_sim(cx.tenants.clone(), cx.policy.clone(), cx.idempotency.clone()))),
+        ("update_sim", Mount::Catalogue, |cx| boxed(update_sim(cx.tenants.clone(), cx.policy.clone(), cx.compat))),
+        ("delete_sim", Mount::Catalogue, |cx| boxed(delete_sim(cx.tenants.clone(), cx.rooms.clone(), cx.compat))),
+        ("transition_sim", Mount::Catalogue, |cx| boxed(transition_sim(cx.tenants.clone(), cx.policy.clone()))),
+        ("list_characters", Mount::Catalogue, |cx| boxed(list_characters(cx.tenants.clone()))),
+        ("get_character", Mount::Catalogue, |cx| boxed(get_character(cx.tenants.clone()))),
+        ("post_character", Mount::Catalogue, |cx| boxed(post_character(cx.tenants.clone(), cx.idempotency.clone()))),
+        ("update_character", Mount::Catalogue, |cx| boxed(update_character(cx.tenants.clone()))),
+        ("delete_character", Mount::Catalogue, |cx| boxed(delete_character(cx.tenants.clone()))),
+        ("list_tags", Mount::Root, |cx| boxed(list_tags(cx.tenants.clone()))),
+        ("list_audit", Mount::Root, |cx| boxed(list_audit(cx.tenants.clone()))),
+        ("list_rooms", Mount::Root, |cx| boxed(list_rooms(cx.rooms.clone()))),
+        ("post_room", Mount::Root, |cx| boxed(post_room(cx.rooms.clone(), cx.idempotency.clone()))),
+        ("delete_room", Mount::Root, |cx| boxed(delete_room(cx.rooms.clone()))),
+        ("list_reservations", Mount::Root, |cx| boxed(list_reservations(cx.rooms.clone()))),
+        ("post_reservation", Mount::Root, |cx| boxed(post_reservation(cx.tenants.clone(), cx.rooms.clone(), cx.idempotency.clone()))),
+        ("delete_reservation", Mount::Root, |cx| boxed(delete_reservation(cx.rooms.clone()))),
+        ("list_tenants", Mount::Root, |cx| boxed(list_tenants(cx.tenants.clone()))),
+        ("post_tenant", Mount::Root, |cx| boxed(post_tenant(cx.tenants.clone(), cx.idempotency.clone()))),
+        ("delete_tenant", Mount::Root, |cx| boxed(delete_tenant(cx.tenants.clone(), cx.rooms.clone()))),
+        ("backup", Mount::Root, |cx| boxed(backup(cx.tenants.clone()))),
+        ("restore", Mount::Root, |cx| boxed(restore(cx.tenants.clone(), cx.rooms.clone(), cx.policy.clone()))),
+        ("graphql", Mount::Root, |cx| boxed(graphql(cx.tenants.clone(), cx.rooms.clone(), cx.policy.clone(), cx.leader.clone()))),
+        // Replication serves the default tenant only
+        ("replication", Mount::Root, |cx| boxed(replication(cx.tenants.default_db().clone(), cx.replication_secret.clone()))),
+        ("openapi", Mount::Root, |_| boxed(openapi())),
+        ("docs", Mount::Root, |_| boxed(docs())),
+    ];
+
+    fn boxed<F, R>(filter: F) -> Route
+    where
+        F: Filter<Extract = (R,), Error = Rejection> + Clone + Send + Sync + 'static,
+        R: warp::Reply + 'static,
+    {
+        filter.map(warp::Reply::into_response).boxed()
+    }
+
+    // Every route the server answers
+    pub fn routes(
+        tenants: Tenants,
+        rooms: rooms::Db,
+        policy: Arc<safety::Policy>,
+        idempotency: idempotency::Cache,
+        compat: Compat,
+    ) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+        routes_for(Context { tenants, rooms, policy, idempotency, compat, replication_secret: None, leader: None })
+    }
+
+    // Every route the server answers, built from a whole `Context`
+    pub fn routes_for(context: Context) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+        // Where clients moving to strict semantics go, whatever the server's own settings
+        let v2 = Context { compat: Compat::V2, ..context.clone() };
+
+        mounted(&context, Mount::Catalogue)
+            .or(warp::path("v2").and(mounted(&v2, Mount::Catalogue)))
+            .or(mounted(&context, Mount::Root))
+            .recover(handlers::handle_unknown_tenant)
+    }
+
+    // The filters of `FILTERS` served at `mount`, tried in the order they are listed
+    fn mounted(context: &Context, mount: Mount) -> Route {
+        FILTERS
+            .iter()
+            .filter(|(_, at, _)| *at == mount)
+            .map(|(_, _, filter)| filter(context))
+            .reduce(|routes, filter| routes.or(filter).unify().boxed())
+            .expect("`FILTERS` serves filters at every mount")
+    }
+
+    // Payloads failing `Validate`; `handlers::handle_invalid` answers them with a 422
+    #[derive(Debug)]
+    pub struct Invalid(pub ValidationErrors);
+
+    impl warp::reject::Reject for Invalid {}
+
+    fn valid<T: Validate>(value: T) -> Result<T, Rejection> {
+        match value.validate() {
+            Ok(()) => Ok(value),
+            Err(errors) => Err(warp::reject::custom(Invalid(errors))),
+        }
+    }
+
+    // Bodies that are not JSON, MessagePack or CBOR; `handlers::handle_invalid` answers them with a 400
+    #[derive(Debug)]
+    pub struct Undecodable(pub String);
+
+    impl warp::reject::Reject for Undecodable {}
+
+    // MessagePack and CBOR are decoded here; any other `Content-Type` is left to `warp::body::json`,
+    // which also takes bodies sent without one
+    fn decoded<T: DeserializeOwned + Send>() -> impl Filter<Extract = (T,), Error = warp::Rejection> + Clone {
+        let binary = warp::header::<Format>("content-type")
+            .and_then(|format| async move {
+                match format {
+                    Format::Json => Err(warp::reject::not_found()),
+                    binary => Ok(binary),
+                }
+            })
+            .and(warp::body::bytes())
+            .and_then(|format: Format, bytes: Bytes| async move {
+                format.decode(&bytes).map_err(|e| warp::reject::custom(Undecodable(e)))
+            });
+
+        binary.or(warp::body::json()).unify()
+    }
+
+    // `Accept` picks the encoding of the answer; `None` when none of the formats will do
+    fn accept() -> impl Filter<Extract = (Option<Format>,), Error = warp::Rejection> + Clone {
+        warp::header::optional::<String>("accept")
+            .map(|accept: Option<String>| Format::negotiate(accept.as_deref()))
+    }
 
     fn json_body() -> impl Filter<Extract = (models::Simulation,), Error = warp::Rejection> + Clone {
-        warp::body::content_length_limit(1024 * 16).and(warp::body::json())
+        warp::body::content_length_limit(1024 * 16)
+            .and(decoded())
+            .and_then(|sim| async { valid(sim) })
+    }
+
+    // Requests naming a tenant that does not exist; `handlers::handle_unknown_tenant` answers them with a 404
+    #[derive(Debug)]
+    pub struct UnknownTenant(pub String);
+
+    impl warp::reject::Reject for UnknownTenant {}
+
+    // The tenant named by the request, `None` for the default one
+    fn tenant() -> impl Filter<Extract = (Option<String>,), Error = warp::Rejection> + Clone {
+        warp::header::optional::<String>(tenants::HEADER)
+    }
+
+    // The catalogue of that tenant; requests naming one that does not exist are rejected with `UnknownTenant`
+    fn tenant_db(tenants: Tenants) -> impl Filter<Extract = (models::Db,), Error = warp::Rejection> + Clone {
+        tenant()
+            .and_then(move |name: Option<String>| {
+                let tenants = tenants.clone();
+                async move {
+                    match tenants.get(name.as_deref()).await {
+                        Some(db) => Ok(db),
+                        None => Err(warp::reject::custom(UnknownTenant(name.unwrap_or_default()))),
+                    }
+                }
+            })
+    }
+
+    // The requester, as set by the authenticating proxy in front of the server
+    fn actor() -> impl Filter<Extract = (safety::Actor,), Error = warp::Rejection> + Clone {
+        warp::header::optional::<String>("x-holodeck-user")
+            .and(warp::header::optional::<String>("x-holodeck-roles"))
+            .and(warp::header::optional::<String>("x-holodeck-approved-by"))
+            .map(safety::Actor::from_headers)
     }
 
+    fn character_body() -> impl Filter<Extract = (NewCharacter,), Error = warp::Rejection> + Clone {
+        warp::body::content_length_limit(1024 * 16)
+            .and(warp::body::json())
+            .and_then(|character| async { valid(character) })
+    }
+
+    // Validated by `update_sim` together with the id from the path
     fn json_body_put() -> impl Filter<Extract = (models::NewName,), Error = warp::Rejection> + Clone {
-        warp::body::content_length_limit(1024 * 16).and(warp::body::json())
+        warp::body::content_length_limit(1024 * 16).and(decoded())
     }
 
-    pub fn list_sims(db: models::Db) ->  impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
-        let db_map = warp::any()
-            .map(move || db.clone());
+    // The body, with the request's claim on its `Idempotency-Key` for `idempotency::Claim::run` to honour
+    fn keyed<T, F>(body: F, cache: idempotency::Cache) -> impl Filter<Extract = (T, idempotency::Claim), Error = warp::Rejection> + Clone
+    where
+        T: serde::Serialize + Send,
+        F: Filter<Extract = (T,), Error = warp::Rejection> + Clone + Send + Sync + 'static,
+    {
+        warp::method()
+            .and(warp::path::full())
+            .and(warp::header::optional::<String>(idempotency::HEADER))
+            .and(warp::header::optional::<String>(tenants::HEADER))
+            .and(warp::header::optional::<String>("x-holodeck-user"))
+            .and(body)
+            .map(move |method: warp::http::Method, path: warp::path::FullPath, key, tenant, user, body: T| {
+                let fingerprint = idempotency::fingerprint(method.as_str(), path.as_str(), &body);
+                (body, idempotency::Claim::new(cache.clone(), key, tenant, user, fingerprint))
+            })
+            .untuple_one()
+    }
 
-        let opt = warp::path::param::<u64>()
-            .map(Some)
-            .or_else(|_| async { 
-                // Ok(None) 
-                Ok::<(Option<u64>,), std::convert::Infallible>((None,))
-            });
+    pub fn list_sims(tenants: Tenants) ->  impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+        let db_map = tenant_db(tenants);
 
-        warp::path!("holodeck" / ..)
-            .and(opt)
-            .and(warp::path::end())
+        warp::path!("holodeck")
+            .map(|| None)
             .and(warp::get())
+            .and(warp::query::<Vec<(String, String)>>())
+            .and(accept())
+            .and(conditional::preconditions())
+            .and(tenant())
             .and(db_map)
             .and_then(handlers::handle_list_sims)
     }
 
-    pub fn post_sim(db: models::Db) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
-        let db_map = warp::any()
-            .map(move || db.clone());
+    pub fn get_sim(tenants: Tenants, compat: Compat) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+        let db_map = tenant_db(tenants);
+
+        warp::path!("holodeck" / u64)
+            .and(warp::get())
+            .and(warp::query::<Vec<(String, String)>>())
+            .and(accept())
+            .and(conditional::preconditions())
+            .and(warp::any().map(move || compat))
+            .and(tenant())
+            .and(db_map)
+            .and_then(handlers::handle_get_sim)
+    }
+
+    pub fn post_sim(tenants: Tenants, policy: Arc<safety::Policy>, idempotency: idempotency::Cache) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+        let db_map = tenant_db(tenants);
+        let policy_map = warp::any()
+            .map(move || policy.clone());
 
+        // Plain text unless `Accept: application/json` asks for the simulation as stored
         warp::path!("holodeck")
             .and(warp::post())
-            .and(json_body())
+            .and(keyed(json_body(), idempotency))
+            .and(warp::header::optional::<String>("accept"))
+            .and(actor())
+            .and(policy_map)
             .and(db_map)
-            .and_then(handlers::handle_create_sim)
+            .and_then(|sim, claim: idempotency::Claim, accept, actor, policy, db| {
+                claim.run(handlers::handle_create_sim(sim, accept, actor, policy, db))
+            })
+            .recover(handlers::handle_invalid)
     }
 
-    pub fn update_sim(db: models::Db) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
-        let db_map = warp::any()
-            .map(move || db.clone());
+    pub fn update_sim(tenants: Tenants, policy: Arc<safety::Policy>, compat: Compat) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+        let db_map = tenant_db(tenants);
+        let policy_map = warp::any()
+            .map(move || policy.clone());
 
+        // Plain text unless `Accept: application/json` asks for the simulation as stored
         warp::path!("holodeck" / u64)
             .and(warp::put())
             .and(json_body_put())
+            .and_then(|id, new: models::NewName| async move {
+                match validate_update(id, &new) {
+                    Ok(()) => Ok((id, new)),
+                    Err(errors) => Err(warp::reject::custom(Invalid(errors))),
+                }
+            })
+            .untuple_one()
+            // Strict PUTs only create when the client says that is what it means, with `If-None-Match: *`
+            .and(warp::header::optional::<String>("if-none-match").map(move |tag: Option<String>| {
+                !compat.strict || tag.is_some_and(|tag| tag.trim() == "*")
+            }))
+            .and(warp::header::optional::<String>("accept"))
+            .and(actor())
+            .and(policy_map)
             .and(db_map)
             .and_then(handlers::handle_update_sim)
+            .recover(handlers::handle_invalid)
     }
 
-    pub fn delete_sim(db: models::Db) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
-        let db_map = warp::any()
-            .map(move || db.clone());
+    // Cancels the simulation's room reservations too
+    pub fn delete_sim(tenants: Tenants, rooms: rooms::Db, compat: Compat) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+        let db_map = tenant_db(tenants);
+        let rooms_map = warp::any()
+            .map(move || rooms.clone());
 
         warp::path!("holodeck" / u64)
             .and(warp::delete())
+            .and(actor())
+            .and(warp::any().map(move || compat))
+            .and(tenant())
             .and(db_map)
+            .and(rooms_map)
             .and_then(handlers::handle_delete_sim)
     }
-}
 
-#[allow(dead_code)]
-mod handlers{
-    use warp::{http::StatusCode};
-    use std::convert::Infallible;
-    use crate::libs::models::Simulation;
+    // POST /holodeck/{id}/run, /pause, /resume and /end
+    pub fn transition_sim(tenants: Tenants, policy: Arc<safety::Policy>) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+        let db_map = tenant_db(tenants);
+        let policy_map = warp::any()
+            .map(move || policy.clone());
+
+        warp::path!("holodeck" / u64 / Action)
+            .and(warp::post())
+            .and(actor())
+            .and(policy_map)
+            .and(db_map)
+            .and_then(handlers::handle_transition)
+    }
+
+    pub fn list_tags(tenants: Tenants) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+        let db_map = tenant_db(tenants);
+
+        warp::path!("tags")
+            .and(warp::get())
+            .and(db_map)
+            .and_then(handlers::handle_list_tags)
+    }
+
+    // Oldest first; `Accept: application/x-ndjson` exports it one entry per line
+    pub fn list_audit(tenants: Tenants) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+        let db_map = tenant_db(tenants);
+
+        warp::path!("audit")
+            .and(warp::get())
+            .and(warp::query::<audit::Query>())
+            .and(warp::header::optional::<String>("accept"))
+            .and(actor())
+            .and(db_map)
+            .and_then(handlers::handle_list_audit)
+    }
+
+    pub fn list_characters(tenants: Tenants) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+        let db_map = tenant_db(tenants);
+
+        warp::path!("holodeck" / u64 / "characters")
+            .and(warp::get())
+            .and(db_map)
+            .and_then(handlers::handle_list_characters)
+    }
+
+    pub fn get_character(tenants: Tenants) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+        let db_map = tenant_db(tenants);
+
+        warp::path!("holodeck" / u64 / "characters" / u64)
+            .and(warp::get())
+            .and(db_map)
+            .and_then(handlers::handle_get_character)
+    }
+
+    pub fn post_character(tenants: Tenants, idempotency: idempotency::Cache) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+        let db_map = tenant_db(tenants);
+
+        warp::path!("holodeck" / u64 / "characters")
+            .and(warp::post())
+            .and(keyed(character_body(), idempotency))
+            .and(actor())
+            .and(db_map)
+            .and_then(|id, new, claim: idempotency::Claim, actor, db| {
+                claim.run(handlers::handle_create_character(id, new, actor, db))
+            })
+            .recover(handlers::handle_invalid)
+    }
+
+    // Character ids are given by the server, so unlike simulations a PUT never creates one
+    pub fn update_character(tenants: Tenants) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+        let db_map = tenant_db(tenants);
+
+        warp::path!("holodeck" / u64 / "characters" / u64)
+            .and(warp::put())
+            .and(character_body())
+            .and(actor())
+            .and(db_map)
+            .and_then(handlers::handle_update_character)
+            .recover(handlers::handle_invalid)
+    }
+
+    pub fn delete_character(tenants: Tenants) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+        let db_map = tenant_db(tenants);
+
+        warp::path!("holodeck" / u64 / "characters" / u64)
+            .and(warp::delete())
+            .and(actor())
+            .and(db_map)
+            .and_then(handlers::handle_delete_character)
+    }
+
+    pub fn list_rooms(rooms: rooms::Db) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+        let rooms_map = warp::any()
+            .map(move || rooms.clone());
+
+        warp::path!("rooms")
+            .and(warp::get())
+            .and(rooms_map)
+            .and_then(handlers::handle_list_rooms)
+    }
+
+    pub fn post_room(rooms: rooms::Db, idempotency: idempotency::Cache) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+        let rooms_map = warp::any()
+            .map(move || rooms.clone());
+        let body = warp::body::content_length_limit(1024 * 16)
+            .and(warp::body::json())
+            .and_then(|room: rooms::Room| async { valid(room) });
+
+        warp::path!("rooms")
+            .and(warp::post())
+            .and(keyed(body, idempotency))
+            .and(rooms_map)
+            .and_then(|room, claim: idempotency::Claim, rooms| {
+                claim.run(handlers::handle_create_room(room, rooms))
+            })
+            .recover(handlers::handle_invalid)
+    }
+
+    // Takes the room's reservations with it
+    pub fn delete_room(rooms: rooms::Db) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+        let rooms_map = warp::any()
+            .map(move || rooms.clone());
+
+        warp::path!("rooms" / u64)
+            .and(warp::delete())
+            .and(rooms_map)
+            .and_then(handlers::handle_delete_room)
+    }
+
+    // The room's schedule: reservations that have not ended yet, earliest first
+    pub fn list_reservations(rooms: rooms::Db) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+        let rooms_map = warp::any()
+            .map(move || rooms.clone());
+
+        warp::path!("rooms" / u64 / "reservations")
+            .and(warp::get())
+            .and(rooms_map)
+            .and_then(handlers::handle_list_reservations)
+    }
+
+    // Needs the catalogue too, since a booking must reference an existing simulation
+    pub fn post_reservation(tenants: Tenants, rooms: rooms::Db, idempotency: idempotency::Cache) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+        let tenants_map = warp::any()
+            .map(move || tenants.clone());
+        let rooms_map = warp::any()
+            .map(move || rooms.clone());
+        let body = warp::body::content_length_limit(1024 * 16).and(warp::body::json::<rooms::NewReservation>());
+
+        warp::path!("rooms" / u64 / "reservations")
+            .and(warp::post())
+            .and(keyed(body, idempotency))
+            .and(tenant())
+            .and(tenants_map)
+            .and(rooms_map)
+            .and_then(|room, new, claim: idempotency::Claim, tenant, tenants, rooms| {
+                claim.run(handlers::handle_create_reservation(room, new, tenant, tenants, rooms))
+            })
+    }
+
+    pub fn delete_reservation(rooms: rooms::Db) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+        let rooms_map = warp::any()
+            .map(move || rooms.clone());
+
+        warp::path!("rooms" / u64 / "reservations" / u64)
+            .and(warp::delete())
+            .and(rooms_map)
+            .and_then(handlers::handle_delete_reservation)
+    }
+
+    // POST /graphql runs queries and mutations, GET serves GraphiQL, and /graphql/ws carries subscriptions
+    pub fn graphql(
+        tenants: Tenants,
+        rooms: rooms::Db,
+        policy: Arc<safety::Policy>,
+        leader: Option<String>,
+    ) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+        let schema = graphql::schema(rooms, policy);
+        let schema_map = warp::any()
+            .map(move || schema.clone());
+        // Like the REST routes, each request reads and writes its tenant's catalogue
+        let catalogue = tenant()
+            .and(tenant_db(tenants))
+            .map(|tenant, db| graphql::Catalogue { db, tenant });
+
+        let query = warp::path!("graphql")
+            .and(warp::post())
+            .and(warp::body::content_length_limit(1024 * 64))
+            .and(warp::body::json())
+            .and(actor())
+            .and(catalogue.clone())
+            .and(schema_map.clone())
+            .and(warp::any().map(move || leader.clone()))
+            .and_then(handlers::handle_graphql);
+
+        let graphiql = warp::path!("graphql")
+            .and(warp::get())
+            .map(|| warp::reply::html(graphql::graphiql()));
+
+        let subscriptions = warp::path!("graphql" / "ws")
+            .and(warp::ws())
+            .and(warp::header::optional::<String>("sec-websocket-protocol"))
+            .and(catalogue)
+            .and(schema_map)
+            .and_then(handlers::handle_graphql_ws);
+
+        query.or(graphiql).or(subscriptions)
+    }
+
+    // Admin routes: the requester must have the `admin` role
+    pub fn list_tenants(tenants: Tenants) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+        let tenants_map = warp::any()
+            .map(move || tenants.clone());
+
+        warp::path!("admin" / "tenants")
+            .and(warp::get())
+            .and(actor())
+            .and(tenants_map)
+            .and_then(handlers::handle_list_tenants)
+    }
+
+    pub fn post_tenant(tenants: Tenants, idempotency: idempotency::Cache) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+        let tenants_map = warp::any()
+            .map(move || tenants.clone());
+        let body = warp::body::content_length_limit(1024 * 16)
+            .and(warp::body::json())
+            .and_then(|tenant: NewTenant| async { valid(tenant) });
+
+        warp::path!("admin" / "tenants")
+            .and(warp::post())
+            .and(keyed(body, idempotency))
+            .and(actor())
+            .and(tenants_map)
+            .and_then(|tenant, claim: idempotency::Claim, actor, tenants| {
+                claim.run(handlers::handle_create_tenant(tenant, actor, tenants))
+            })
+            .recover(handlers::handle_invalid)
+    }
+
+    // Takes the tenant's simulations, and their room reservations, with it
+    pub fn delete_tenant(tenants: Tenants, rooms: rooms::Db) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+        let tenants_map = warp::any()
+            .map(move || tenants.clone());
+        let rooms_map = warp::any()
+            .map(move || rooms.clone());
+
+        warp::path!("admin" / "tenants" / String)
+            .and(warp::delete())
+            .and(actor())
+            .and(tenants_map)
+            .and(rooms_map)
+            .and_then(handlers::handle_delete_tenant)
+    }
+
+    pub fn backup(tenants: Tenants) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+        let db_map = tenant_db(tenants);
+
+        warp::path!("admin" / "backup")
+            .and(warp::post())
+            .and(actor())
+            .and(db_map)
+            .and_then(handlers::handle_backup)
+    }
+
+    // Archives are whole catalogues, so they get more room than any other body
+    pub fn restore(tenants: Tenants, rooms: rooms::Db, policy: Arc<safety::Policy>) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+        let db_map = tenant_db(tenants);
+        let rooms_map = warp::any()
+            .map(move || rooms.clone());
+        let policy_map = warp::any()
+            .map(move || policy.clone());
+
+        warp::path!("admin" / "restore")
+            .and(warp::post())
+            .and(warp::body::content_length_limit(1024 * 1024 * 64))
+            .and(warp::body::json())
+            .and(actor())
+            .and(policy_map)
+            .and(tenant())
+            .and(db_map)
+            .and(rooms_map)
+            .and_then(handlers::handle_restore)
+    }
+
+    // Followers tail this to keep their copy of the default tenant's catalogue up to date
+    pub fn replication(db: models::Db, secret: Option<String>) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+        let db_map = warp::any()
+            .map(move || db.clone());
+        let secret_map = warp::any()
+            .map(move || secret.clone());
+
+        warp::path!("replication")
+            .and(warp::get())
+            .and(warp::header::optional::<String>("authorization"))
+            .and(secret_map)
+            .and(db_map)
+            .and_then(handlers::handle_replication)
+    }
+
+    pub fn openapi() -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+        warp::path!("openapi.json")
+            .and(warp::get())
+            .map(|| warp::reply::json(&openapi::spec()))
+    }
+
+    pub fn docs() -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+        warp::path!("docs")
+            .and(warp::get())
+            .map(|| warp::reply::html(openapi::DOCS))
+    }
+}
+
+#[allow(dead_code)]
+mod handlers{
+    use chrono::Utc;
+    use warp::{http::StatusCode, Rejection, Reply};
+    use std::collections::BTreeSet;
+    use std::convert::Infallible;
+    use crate::libs::characters::NewCharacter;
+    use crate::libs::codec::{Format, FORMATS};
+    use crate::libs::conditional::{Preconditions, Validators};
+    use crate::libs::config::Compat;
+    use crate::libs::lifecycle::Action;
+    use crate::libs::models::Simulation;
+    use crate::libs::rooms::{BookingError, NewReservation, Room};
+    use crate::libs::safety::{Actor, Event, Policy, Violations, ADMIN_ROLE};
+    use crate::libs::tenants::{NewTenant, Tenants};
+    use crate::libs::validation::validate_booking;
+    use std::sync::Arc;
+
+    use super::{audit, catalogue, filters, graphql, models, replication, rooms};
+    use super::audit::Operation;
+    use super::backup::Archive;
+    use async_graphql::http::{WebSocket, WebSocketProtocols, WsMessage};
+    use futures_util::{future, SinkExt, StreamExt};
+    use warp::ws::{Message, Ws};
+
+    pub async fn handle_list_sims(
+        opt: Option<u64>,
+        query: Vec<(String, String)>,
+        format: Option<Format>,
+        preconditions: Preconditions,
+        tenant: Option<String>,
+        db: models::Db,
+    ) -> Result<warp::reply::Response, Infallible> {
+        let format = match format {
+            Some(format) => format,
+            None => return Ok(warp::reply::with_status(
+                format!("Simulations can be listed as {}.\n", FORMATS.iter().map(Format::content_type).collect::<Vec<_>>().join(", ")),
+                StatusCode::NOT_ACCEPTABLE,
+            ).into_response()),
+        };
+
+        let options = match models::ListOptions::parse(&query) {
+            Ok(options) => options,
+            Err(message) => return Ok(warp::reply::with_status(
+                format!("{}\n", message),
+                StatusCode::BAD_REQUEST,
+            ).into_response()),
+        };
+
+        let map = db.snapshot();
+        // Checked before anything is encoded, so pollers with a fresh copy cost next to nothing
+        let validators = match opt.and_then(|id| item_validators(&map, id, format)) {
+            Some(validators) => validators,
+            // Revisions start over with each store, so the tag names the store and its tenant as well
+            None => {
+                let tag = format!("{}:{}-{}-{}", tenant.as_deref().unwrap_or_default(), map.epoch(), map.revision(), format_name(format));
+                Validators::new(&tag, map.modified_at())
+            }
+        };
+        if validators.fresh(&preconditions) {
+            return Ok(validators.not_modified());
+        }
+
+        let found: Vec<&Simulation> = match opt {
+            Some(param) => models::get_simulation(&map, param)
+                .filter(|sim| options.tags.include.is_subset(&sim.tags) && options.tags.exclude.is_disjoint(&sim.tags))
+                .into_iter()
+                .collect(),
+            None => map.tagged(&options.tags),
+        };
+
+        let result: Vec<Simulation> = found
+            .into_iter()
+            .map(|sim| if options.characters {
+                sim.clone()
+            } else {
+                Simulation { characters: Vec::new(), ..sim.clone() }
+            })
+            .collect();
+        Ok(validators.reply(format.reply(&result)))
+    }
+
+    pub async fn handle_get_sim(
+        id: u64,
+        query: Vec<(String, String)>,
+        format: Option<Format>,
+        preconditions: Preconditions,
+        compat: Compat,
+        tenant: Option<String>,
+        db: models::Db,
+    ) -> Result<warp::reply::Response, Infallible> {
+        if compat.item_arrays {
+            return handle_list_sims(Some(id), query, format, preconditions, tenant, db).await;
+        }
+        let format = match format {
+            Some(format) => format,
+            None => return Ok(warp::reply::with_status(
+                format!("Simulations can be read as {}.\n", FORMATS.iter().map(Format::content_type).collect::<Vec<_>>().join(", ")),
+                StatusCode::NOT_ACCEPTABLE,
+            ).into_response()),
+        };
+        // Tags are a way to narrow a list, so only `expand` means anything here
+        let options = match models::ListOptions::parse(&query) {
+            Ok(options) => options,
+            Err(message) => return Ok(warp::reply::with_status(
+                format!("{}\n", message),
+                StatusCode::BAD_REQUEST,
+            ).into_response()),
+        };
+
+        let map = db.snapshot();
+        let (sim, validators) = match (models::get_simulation(&map, id), item_validators(&map, id, format)) {
+            (Some(sim), Some(validators)) => (sim, validators),
+            _ => {
+                let error = models::ApiError {
+                    code: StatusCode::NOT_FOUND.as_u16(),
+                    message: format!("Simulation #{} does not exist.", id),
+                };
+                return Ok(warp::reply::with_status(warp::reply::json(&error), StatusCode::NOT_FOUND).into_response());
+            }
+        };
+        if validators.fresh(&preconditions) {
+            return Ok(validators.not_modified());
+        }
+
+        let sim = if options.characters {
+            sim.clone()
+        } else {
+            Simulation { characters: Vec::new(), ..sim.clone() }
+        };
+        Ok(validators.reply(format.reply(&sim)))
+    }
+
+    fn format_name(format: Format) -> &'static str {
+        format.content_type().trim_start_matches("application/")
+    }
+
+    // One simulation changes less often than the catalogue, so it is validated on its own
+    fn item_validators(map: &models::Store, id: u64, format: Format) -> Option<Validators> {
+        let modified = map.modified(id)?;
+        let tag = format!("{}-{}-{}", id, modified.timestamp_nanos_opt().unwrap_or_default(), format_name(format));
+        Some(Validators::new(&tag, modified))
+    }
+
+    pub async fn handle_list_tags(db: models::Db) -> Result<impl warp::Reply, Infallible> {
+        Ok(warp::reply::json(&db.snapshot().tag_counts()))
+    }
+
+    pub async fn handle_list_audit(
+        query: audit::Query,
+        accept: Option<String>,
+        actor: Actor,
+        db: models::Db,
+    ) -> Result<warp::reply::Response, Infallible> {
+        // Entries hold whole simulations, including those the safety rules keep others from changing
+        if let Some(refused) = not_admin(&actor, "Reading the audit log") {
+            return Ok(refused);
+        }
+        let entries = db.snapshot().audit().query(&query);
+        if accept.is_some_and(|accept| accept.contains(audit::NDJSON)) {
+            return Ok(warp::reply::with_header(audit::to_ndjson(&entries), "content-type", audit::NDJSON).into_response());
+        }
+        Ok(warp::reply::json(&entries).into_response())
+    }
+
+    pub async fn handle_create_sim(
+        sim: models::Simulation,
+        accept: Option<String>,
+        actor: Actor,
+        policy: Arc<Policy>,
+        db: models::Db,
+    ) -> Result<warp::reply::Response, Infallible> {
+        match catalogue::create(&db, &policy, &actor, sim).await {
+            Ok(sim) if wants_json(&accept) => {
+                Ok(warp::reply::with_status(warp::reply::json(&sim), StatusCode::CREATED).into_response())
+            }
+            Ok(sim) => Ok(warp::reply::with_status(format!("Simulation #{} created.\n", sim.id), StatusCode::CREATED).into_response()),
+            Err(e) => Ok(catalogue_error(e)),
+        }
+    }
+
+    pub async fn handle_update_sim(
+        id: u64,
+        new: models::NewName,
+        upsert: bool,
+        accept: Option<String>,
+        actor: Actor,
+        policy: Arc<Policy>,
+        db: models::Db,
+    ) -> Result<warp::reply::Response, Infallible> {
+        let (sim, created) = match catalogue::update(&db, &policy, &actor, id, new, upsert).await {
+            Ok(updated) => updated,
+            Err(e) => return Ok(catalogue_error(e)),
+        };
+
+        if wants_json(&accept) {
+            let status = if created { StatusCode::CREATED } else { StatusCode::OK };
+            return Ok(warp::reply::with_status(warp::reply::json(&sim), status).into_response());
+        }
+
+        // Create entry
+        if created {
+            return Ok(warp::reply::with_status(
+                format!("Simulation #{} was inserted.\n", id), 
+                StatusCode::CREATED,
+            ).into_response());
+        }
+
+        // Replaced entry
+        Ok(warp::reply::with_status(
+            format!("Simulation #{} was updated.\n", id), 
+            StatusCode::OK,
+        ).into_response())
+    }
+
+    // Writes answer with a line of text, unless the client asks for the simulation as stored
+    fn wants_json(accept: &Option<String>) -> bool {
+        accept.as_deref().is_some_and(|accept| accept.contains("application/json"))
+    }
+
+    pub async fn handle_transition(
+        id: u64,
+        action: Action,
+        actor: Actor,
+        policy: Arc<Policy>,
+        db: models::Db,
+    ) -> Result<warp::reply::Response, Infallible> {
+        let mut map = db.lock().await;
+        let mut sim = match models::get_simulation(&map, id) {
+            Some(sim) => sim.clone(),
+            None => return Ok(warp::reply::with_status(
+                format!("Simulation #{} does not exist.\n", id),
+                StatusCode::NOT_FOUND,
+            ).into_response()),
+        };
+
+        if let Some(event) = Event::for_action(action) {
+            if let Err(violations) = policy.check(event, &sim, &actor) {
+                return Ok(forbidden(violations));
+            }
+        }
+
+        if let Err(state) = sim.transition(action, Utc::now()) {
+            return Ok(warp::reply::with_status(
+                format!("Cannot {} simulation #{} while it is {}.\n", action, id, state),
+                StatusCode::CONFLICT,
+            ).into_response());
+        }
+
+        let before = map.replace(sim.clone());
+        map.record(audit::Entry::new(&actor, action.into(), id, before, Some(sim.clone())));
+        Ok(warp::reply::json(&sim).into_response())
+    }
+
+    fn no_simulation(id: u64) -> warp::reply::Response {
+        warp::reply::with_status(format!("Simulation #{} does not exist.\n", id), StatusCode::NOT_FOUND).into_response()
+    }
+
+    fn no_character(id: u64, character: u64) -> warp::reply::Response {
+        warp::reply::with_status(
+            format!("Simulation #{} has no character #{}.\n", id, character),
+            StatusCode::NOT_FOUND,
+        ).into_response()
+    }
+
+    pub async fn handle_list_characters(id: u64, db: models::Db) -> Result<warp::reply::Response, Infallible> {
+        match models::get_simulation(&db.snapshot(), id) {
+            Some(sim) => Ok(warp::reply::json(&sim.characters).into_response()),
+            None => Ok(no_simulation(id)),
+        }
+    }
+
+    pub async fn handle_get_character(id: u64, character: u64, db: models::Db) -> Result<warp::reply::Response, Infallible> {
+        let map = db.snapshot();
+        let sim = match models::get_simulation(&map, id) {
+            Some(sim) => sim,
+            None => return Ok(no_simulation(id)),
+        };
+        match sim.character(character) {
+            Some(found) => Ok(warp::reply::json(found).into_response()),
+            None => Ok(no_character(id, character)),
+        }
+    }
+
+    pub async fn handle_create_character(
+        id: u64,
+        new: NewCharacter,
+        actor: Actor,
+        db: models::Db,
+    ) -> Result<warp::reply::Response, Infallible> {
+        let mut map = db.lock().await;
+        let mut sim = match models::get_simulation(&map, id) {
+            Some(sim) => sim.clone(),
+            None => return Ok(no_simulation(id)),
+        };
+
+        let character = sim.add_character(new);
+        sim.touch(Utc::now());
+        let before = map.replace(sim.clone());
+        map.record(audit::Entry::new(&actor, Operation::CreateCharacter, id, before, Some(sim)));
+        Ok(warp::reply::with_status(warp::reply::json(&character), StatusCode::CREATED).into_response())
+    }
+
+    pub async fn handle_update_character(
+        id: u64,
+        character: u64,
+        new: NewCharacter,
+        actor: Actor,
+        db: models::Db,
+    ) -> Result<warp::reply::Response, Infallible> {
+        let mut map = db.lock().await;
+        let mut sim = match models::get_simulation(&map, id) {
+            Some(sim) => sim.clone(),
+            None => return Ok(no_simulation(id)),
+        };
+
+        let updated = match sim.update_character(character, new) {
+            Some(updated) => updated,
+            None => return Ok(no_character(id, character)),
+        };
+        sim.touch(Utc::now());
+        let before = map.replace(sim.clone());
+        map.record(audit::Entry::new(&actor, Operation::UpdateCharacter, id, before, Some(sim)));
+        Ok(warp::reply::json(&updated).into_response())
+    }
+
+    pub async fn handle_delete_character(
+        id: u64,
+        character: u64,
+        actor: Actor,
+        db: models::Db,
+    ) -> Result<warp::reply::Response, Infallible> {
+        let mut map = db.lock().await;
+        let mut sim = match models::get_simulation(&map, id) {
+            Some(sim) => sim.clone(),
+            None => return Ok(no_simulation(id)),
+        };
+
+        if sim.remove_character(character).is_none() {
+            return Ok(no_character(id, character));
+        }
+        sim.touch(Utc::now());
+        let before = map.replace(sim.clone());
+        map.record(audit::Entry::new(&actor, Operation::DeleteCharacter, id, before, Some(sim)));
+        Ok(warp::reply::with_status(
+            format!("Character #{} of simulation #{} was deleted.\n", character, id),
+            StatusCode::OK,
+        ).into_response())
+    }
+
+    pub async fn handle_list_rooms(rooms: rooms::Db) -> Result<impl warp::Reply, Infallible> {
+        Ok(warp::reply::json(&rooms.lock().await.rooms()))
+    }
+
+    pub async fn handle_create_room(room: Room, rooms: rooms::Db) -> Result<impl warp::Reply, Infallible> {
+        let id = room.id;
+        if let Err(existing) = rooms.lock().await.insert(room) {
+            return Ok(warp::reply::with_status(
+                format!("Room #{} already exists under the name {}\n", existing.id, existing.name),
+                StatusCode::BAD_REQUEST,
+            ));
+        }
+        Ok(warp::reply::with_status(format!("Room #{} created.\n", id), StatusCode::CREATED))
+    }
+
+    pub async fn handle_delete_room(id: u64, rooms: rooms::Db) -> Result<impl warp::Reply, Infallible> {
+        if rooms.lock().await.remove(id).is_none() {
+            return Ok(warp::reply::with_status(
+                format!("Room #{} does not exist.\n", id),
+                StatusCode::NOT_FOUND,
+            ));
+        }
+        Ok(warp::reply::with_status(format!("Room #{} was deleted.\n", id), StatusCode::OK))
+    }
+
+    pub async fn handle_list_reservations(id: u64, rooms: rooms::Db) -> Result<warp::reply::Response, Infallible> {
+        let rooms = rooms.lock().await;
+        if rooms.get(id).is_none() {
+            return Ok(warp::reply::with_status(
+                format!("Room #{} does not exist.\n", id),
+                StatusCode::NOT_FOUND,
+            ).into_response());
+        }
+        Ok(warp::reply::json(&rooms.schedule(id, Utc::now())).into_response())
+    }
+
+    pub async fn handle_create_reservation(
+        room: u64,
+        new: NewReservation,
+        tenant: Option<String>,
+        tenants: Tenants,
+        rooms: rooms::Db,
+    ) -> Result<warp::reply::Response, Infallible> {
+        // Checked under the rooms lock, which deleting a simulation or tenant holds until it is gone,
+        // so a booking never outlives what it books
+        let mut rooms = rooms.lock().await;
+        let db = match tenants.get(tenant.as_deref()).await {
+            Some(db) => db,
+            None => return Ok(no_tenant(tenant.as_deref().unwrap_or_default())),
+        };
+        let in_catalogue = models::get_simulation(&db.snapshot(), new.simulation).is_some();
+        if let Err(errors) = validate_booking(&new, Utc::now(), in_catalogue) {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&errors),
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ).into_response());
+        }
+
+        match rooms.book(room, tenant.as_deref(), new) {
+            Ok(reservation) => Ok(warp::reply::with_status(
+                warp::reply::json(&reservation),
+                StatusCode::CREATED,
+            ).into_response()),
+            Err(BookingError::NoSuchRoom) => Ok(warp::reply::with_status(
+                format!("Room #{} does not exist.\n", room),
+                StatusCode::NOT_FOUND,
+            ).into_response()),
+            Err(BookingError::Conflict(taken)) => Ok(warp::reply::with_status(
+                format!(
+                    "Room #{} is already booked from {} to {} (reservation #{}).\n",
+                    room, taken.start, taken.end, taken.id,
+                ),
+                StatusCode::CONFLICT,
+            ).into_response()),
+        }
+    }
+
+    pub async fn handle_delete_reservation(room: u64, id: u64, rooms: rooms::Db) -> Result<impl warp::Reply, Infallible> {
+        if rooms.lock().await.cancel(room, id).is_none() {
+            return Ok(warp::reply::with_status(
+                format!("Room #{} has no reservation #{}.\n", room, id),
+                StatusCode::NOT_FOUND,
+            ));
+        }
+        Ok(warp::reply::with_status(format!("Reservation #{} was cancelled.\n", id), StatusCode::OK))
+    }
+
+    fn forbidden(violations: Violations) -> warp::reply::Response {
+        warp::reply::with_status(warp::reply::json(&violations), StatusCode::FORBIDDEN).into_response()
+    }
+
+    fn catalogue_error(e: catalogue::Error) -> warp::reply::Response {
+        match e {
+            catalogue::Error::Exists { .. } => warp::reply::with_status(format!("{}\n", e), StatusCode::BAD_REQUEST).into_response(),
+            catalogue::Error::NotFound(id) => no_simulation(id),
+            catalogue::Error::Invalid(errors) => warp::reply::with_status(
+                warp::reply::json(&errors),
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ).into_response(),
+            catalogue::Error::Forbidden(violations) => forbidden(violations),
+            catalogue::Error::QuotaExceeded(_) => warp::reply::with_status(format!("{}\n", e), StatusCode::FORBIDDEN).into_response(),
+        }
+    }
+
+    fn not_admin(actor: &Actor, doing: &str) -> Option<warp::reply::Response> {
+        if actor.roles.contains(ADMIN_ROLE) {
+            return None;
+        }
+        Some(warp::reply::with_status(
+            format!("{} requires the {} role.\n", doing, ADMIN_ROLE),
+            StatusCode::FORBIDDEN,
+        ).into_response())
+    }
+
+    pub async fn handle_list_tenants(actor: Actor, tenants: Tenants) -> Result<warp::reply::Response, Infallible> {
+        if let Some(refused) = not_admin(&actor, "Managing tenants") {
+            return Ok(refused);
+        }
+        Ok(warp::reply::json(&tenants.list().await).into_response())
+    }
+
+    pub async fn handle_create_tenant(new: NewTenant, actor: Actor, tenants: Tenants) -> Result<warp::reply::Response, Infallible> {
+        if let Some(refused) = not_admin(&actor, "Managing tenants") {
+            return Ok(refused);
+        }
+        if tenants.is_single() {
+            return Ok(warp::reply::with_status(
+                "Tenants cannot be created while the server persists to a data file; they would be lost on exit.\n",
+                StatusCode::CONFLICT,
+            ).into_response());
+        }
+        let name = new.name.clone();
+        if !tenants.create(new).await {
+            return Ok(warp::reply::with_status(
+                format!("Tenant {} already exists.\n", name),
+                StatusCode::BAD_REQUEST,
+            ).into_response());
+        }
+        Ok(warp::reply::with_status(format!("Tenant {} created.\n", name), StatusCode::CREATED).into_response())
+    }
+
+    pub async fn handle_delete_tenant(name: String, actor: Actor, tenants: Tenants, rooms: rooms::Db) -> Result<warp::reply::Response, Infallible> {
+        if let Some(refused) = not_admin(&actor, "Managing tenants") {
+            return Ok(refused);
+        }
+        let mut rooms = rooms.lock().await;
+        if tenants.remove(&name).await.is_none() {
+            return Ok(no_tenant(&name));
+        }
+        rooms.cancel_simulations(Some(&name), |_| true);
+        Ok(warp::reply::with_status(format!("Tenant {} was deleted.\n", name), StatusCode::OK).into_response())
+    }
+
+    fn no_tenant(name: &str) -> warp::reply::Response {
+        warp::reply::with_status(format!("Tenant {} does not exist.\n", name), StatusCode::NOT_FOUND).into_response()
+    }
+
+    // Taken from a snapshot, so it is consistent without holding up writers
+    pub async fn handle_backup(actor: Actor, db: models::Db) -> Result<warp::reply::Response, Infallible> {
+        if let Some(refused) = not_admin(&actor, "Backing up the catalogue") {
+            return Ok(refused);
+        }
+        let archive = Archive::of(db.snapshot().iter());
+        let filename = format!("attachment; filename=\"holodeck-{}.json\"", archive.created_at.format("%Y%m%dT%H%M%SZ"));
+        Ok(warp::reply::with_header(warp::reply::json(&archive), "content-disposition", filename).into_response())
+    }
+
+    pub async fn handle_restore(
+        archive: Archive,
+        actor: Actor,
+        policy: Arc<Policy>,
+        tenant: Option<String>,
+        db: models::Db,
+        rooms: rooms::Db,
+    ) -> Result<warp::reply::Response, Infallible> {
+        if let Some(refused) = not_admin(&actor, "Restoring the catalogue") {
+            return Ok(refused);
+        }
+        let created_at = archive.created_at;
+        let sims = match archive.open() {
+            Ok(sims) => sims,
+            Err(e) => return Ok(warp::reply::with_status(format!("{}\n", e), StatusCode::UNPROCESSABLE_ENTITY).into_response()),
+        };
+
+        let mut map = db.lock().await;
+        if let Some(quota) = map.quota().filter(|quota| sims.len() > *quota) {
+            return Ok(catalogue_error(catalogue::Error::QuotaExceeded(quota)));
+        }
+        // Each simulation brought back is a create or an update, and the safety rules have their say on it
+        let mut violations = Vec::new();
+        for sim in &sims {
+            let event = if models::get_simulation(&map, sim.id).is_some() { Event::Update } else { Event::Create };
+            if let Err(refused) = policy.check(event, sim, &actor) {
+                for mut violation in refused.violations {
+                    violation.message = format!("simulation #{} {}", sim.id, violation.message);
+                    violations.push(violation);
+                }
+            }
+        }
+        if !violations.is_empty() {
+            return Ok(forbidden(Violations { violations }));
+        }
+        let before = models::Store::clone(&map);
+        let count = sims.len();
+        map.restore(sims);
+        // Readers see the old catalogue or the restored one, never a mix: the swap happens when `map` is dropped
+        let mut ids: Vec<u64> = before.iter().chain(map.iter()).map(|sim| sim.id).collect();
+        ids.sort_unstable();
+        ids.dedup();
+        for id in ids {
+            let entry = audit::Entry::new(
+                &actor,
+                Operation::Restore,
+                id,
+                models::get_simulation(&before, id).cloned(),
+                models::get_simulation(&map, id).cloned(),
+            );
+            map.record(entry);
+        }
+
+        // Simulations the archive does not hold lose their bookings, as if deleted
+        let mut rooms = rooms.lock().await;
+        let kept: BTreeSet<u64> = map.iter().map(|sim| sim.id).collect();
+        drop(map);
+        rooms.cancel_simulations(tenant.as_deref(), |id| !kept.contains(&id));
+        Ok(warp::reply::with_status(
+            format!("Restored {} simulation(s) from the backup of {}.\n", count, created_at.to_rfc3339()),
+            StatusCode::OK,
+        ).into_response())
+    }
+
+    pub async fn handle_replication(
+        authorization: Option<String>,
+        secret: Option<String>,
+        db: models::Db,
+    ) -> Result<warp::reply::Response, Infallible> {
+        // The stream hands over every simulation, whichever safety rules would hide it, so the role headers
+        // clients set themselves are not enough; only followers holding the shared secret get it
+        if !replication::authorized(secret.as_deref(), authorization.as_deref()) {
+            let reason = match secret {
+                Some(_) => "Following the catalogue requires Authorization: Bearer with the leader's HOLODECK_REPLICATION_SECRET.\n",
+                None => "Replication is off; start the leader with HOLODECK_REPLICATION_SECRET to serve followers.\n",
+            };
+            return Ok(warp::reply::with_status(reason, StatusCode::FORBIDDEN).into_response());
+        }
+        // Holding the write lock keeps any write from falling between the snapshot and the changes after it
+        let map = db.lock().await;
+        Ok(replication::feed(&map))
+    }
+
+    pub async fn handle_unknown_tenant(rejection: Rejection) -> Result<warp::reply::Response, Rejection> {
+        match rejection.find() {
+            Some(filters::UnknownTenant(name)) => Ok(no_tenant(name)),
+            None => Err(rejection),
+        }
+    }
+
+    pub async fn handle_graphql(
+        request: async_graphql::Request,
+        actor: Actor,
+        catalogue: graphql::Catalogue,
+        schema: graphql::HolodeckSchema,
+        leader: Option<String>,
+    ) -> Result<warp::reply::Response, Infallible> {
+        // A follower answers queries from its copy, but mutations would be lost to the next snapshot
+        if let Some(leader) = leader.filter(|_| graphql::is_mutation(&request)) {
+            let reply = warp::reply::with_status(
+                format!("This server is a read-only follower; send mutations to {}.\n", leader),
+                StatusCode::TEMPORARY_REDIRECT,
+            );
+            return Ok(warp::reply::with_header(reply, "location", format!("{}/graphql", leader)).into_response());
+        }
+        Ok(warp::reply::json(&schema.execute(request.data(actor).data(catalogue)).await).into_response())
+    }
+
+    // Both subscription protocols are spoken; clients that name neither get the older graphql-ws
+    pub async fn handle_graphql_ws(
+        ws: Ws,
+        protocols: Option<String>,
+        catalogue: graphql::Catalogue,
+        schema: graphql::HolodeckSchema,
+    ) -> Result<warp::reply::Response, Infallible> {
+        let protocol = protocols
+            .unwrap_or_default()
+            .split(',')
+            .find_map(|protocol| protocol.trim().parse::<WebSocketProtocols>().ok())
+            .unwrap_or(WebSocketProtocols::SubscriptionsTransportWS);
+
+        let reply = ws.on_upgrade(move |socket| async move {
+            let (mut sink, stream) = socket.split();
+            let input = stream
+                .take_while(|message| future::ready(message.is_ok()))
+                .filter_map(|message| future::ready(message.ok().filter(|m| m.is_text() || m.is_binary())))
+                .map(Message::into_bytes);
+
+            let mut data = async_graphql::Data::default();
+            data.insert(catalogue);
+            let mut output = WebSocket::new(schema, input, protocol).connection_data(data);
+            while let Some(message) = output.next().await {
+                let message = match message {
+                    WsMessage::Text(text) => Message::text(text),
+                    WsMessage::Close(code, reason) => Message::close_with(code, reason),
+                };
+                if sink.send(message).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(warp::reply::with_header(reply, "sec-websocket-protocol", protocol.sec_websocket_protocol()).into_response())
+    }
+
+    pub async fn handle_invalid(rejection: Rejection) -> Result<warp::reply::Response, Rejection> {
+        if let Some(filters::Invalid(errors)) = rejection.find() {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(errors),
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ).into_response());
+        }
+        if let Some(filters::Undecodable(e)) = rejection.find() {
+            return Ok(warp::reply::with_status(
+                format!("Request body deserialize error: {}\n", e),
+                StatusCode::BAD_REQUEST,
+            ).into_response());
+        }
+        Err(rejection)
+    }
+
+    // Characters live inside their simulation, so they go with it
+    pub async fn handle_delete_sim(
+        id: u64,
+        actor: Actor,
+        compat: Compat,
+        tenant: Option<String>,
+        db: models::Db,
+        rooms: rooms::Db,
+    ) -> Result<warp::reply::Response, Infallible> {
+        let deleted = catalogue::delete(&db, &rooms, tenant.as_deref(), &actor, id).await;
+        if compat.strict {
+            return Ok(match deleted {
+                Ok(_) => StatusCode::NO_CONTENT.into_response(),
+                Err(e) => catalogue_error(e),
+            });
+        }
+
+        // Only a missing simulation is let through, as deleting it again is what retrying clients do
+        Ok(match deleted {
+            Ok(_) => warp::reply::with_status(
+                format!("Simulation #{} was deleted.\n", id),
+                StatusCode::OK,
+            ).into_response(),
+            Err(catalogue::Error::NotFound(_)) => warp::reply::with_status(
+                "No data was deleted.\n".to_string(),
+                StatusCode::OK,
+            ).into_response(),
+            Err(e) => catalogue_error(e),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use warp::http::StatusCode;
+    use warp::test::request;
+    use super::{filters,models,safety};
+    use std::sync::Arc;
+
+    fn policy() -> Arc<safety::Policy> {
+        Arc::new(safety::Policy::default())
+    }
+
+    #[tokio::test]
+    async fn try_snapshots() {
+        let db = models::new_db();
+        db.lock().await.insert(models::Simulation::new(1, "The Big Goodbye"));
+        let before = db.snapshot();
+
+        // Readers neither wait for a write in progress nor see it until it is done
+        let mut map = db.lock().await;
+        map.replace(models::Simulation::new(1, "The Long Hello"));
+        map.insert(models::Simulation::new(2, "Fair Haven"));
+        assert_eq!(db.snapshot().len(), 1);
+        drop(map);
+
+        let after = db.snapshot();
+        assert_eq!(models::get_simulation(&after, 1).unwrap().name, "The Long Hello");
+        assert_eq!(after.len(), 2);
+        assert_eq!(models::get_simulation(&before, 1).unwrap().name, "The Big Goodbye");
+        assert_eq!(before.len(), 1);
+
+        // Subscribers and the audit log hear of a write only once readers can see it
+        let mut changes = db.snapshot().subscribe();
+        let mut map = db.lock().await;
+        map.remove(&models::Simulation::new(2, "Fair Haven"));
+        map.record(super::audit::Entry::new(&Default::default(), super::audit::Operation::Delete, 2, None, None));
+        assert!(changes.try_recv().is_err());
+        assert!(map.audit().query(&Default::default()).is_empty());
+        drop(map);
+        assert_eq!(changes.try_recv().unwrap().id, 2);
+        assert!(models::get_simulation(&db.snapshot(), 2).is_none());
+        assert_eq!(db.snapshot().audit().query(&Default::default()).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn try_list() {
+        use std::str;
+        use serde_json;
+        use warp::Filter;
+        use super::config::Compat;
+        use super::tenants::Tenants;
+
+        let simulation1 = models::Simulation::new(1, "The Big Goodbye!");
+
+
+        let simulation2 = models::Simulation::new(2, "Bride Of Chaotica!");
+
+        let db = models::new_db();
+        db.lock().await.insert(simulation1.clone());
+        db.lock().await.insert(simulation2.clone());
+
+        // Old clients can still have a single simulation listed in an array
+        let tenants = Tenants::from(db);
+        let api = filters::list_sims(tenants.clone()).or(filters::get_sim(tenants, Compat { item_arrays: true, ..Compat::default() }));
+
+        let response = request()
+            .method("GET")
+            .path("/holodeck")
+            .reply(&api)
+            .await;
+
+        let result: Vec<u8> = response.into_body().into_iter().collect();
+        let result = str::from_utf8(&result).unwrap();
+        let result: models::Simulations = serde_json::from_str(result).unwrap();
+        assert_eq!(models::get_simulation(&result, 1).unwrap(), &simulation1);
+        assert_eq!(models::get_simulation(&result, 2).unwrap(), &simulation2);
+
+        let response = request()
+            .method("GET")
+            .path("/holodeck/2")
+            .reply(&api)
+            .await;
+
+        let result: Vec<u8> = response.into_body().into_iter().collect();
+        let result = str::from_utf8(&result).unwrap();
+        let result: models::Simulations = serde_json::from_str(result).unwrap();
+        assert_eq!(result.len(),1);
+        assert_eq!(models::get_simulation(&result, 2).unwrap(), &simulation2);
+
+        let response = request().path("/holodeck/3").reply(&api).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.body(), "[]");
+        assert_eq!(request().path("/holodeck/2?tag=western").reply(&api).await.body(), "[]");
+    }
+
+    #[tokio::test]
+    async fn try_get() {
+        use super::characters::NewCharacter;
+        use super::config::Compat;
+
+        let mut sim = models::Simulation::new(1, "The Big Goodbye");
+        sim.add_character(NewCharacter::new("Dixon Hill", "Detective"));
+        let db = models::new_db();
+        db.lock().await.insert(sim.clone());
+        let api = filters::get_sim(db.into(), Compat::default());
+
+        let response = request().path("/holodeck/1").reply(&api).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let found: models::Simulation = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(found.name, "The Big Goodbye");
+        assert!(found.characters.is_empty());
+        let response = request().path("/holodeck/1?expand=characters").reply(&api).await;
+        let found: models::Simulation = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(found.characters, sim.characters);
+
+        let response = request().path("/holodeck/2").reply(&api).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.headers()["content-type"], "application/json");
+        let error: models::ApiError = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(error, models::ApiError { code: 404, message: "Simulation #2 does not exist.".to_string() });
+        assert_eq!(request().path("/holodeck/1?expand=rooms").reply(&api).await.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn try_create() {
+        let db = models::new_db();
+        let api = filters::post_sim(db.into(), policy(), Default::default());
+    
+        let response = request()
+            .method("POST")
+            .path("/holodeck")
+            .json(&models::Simulation::new(1, "The Big Goodbye"))
+            .reply(&api)
+            .await;
+    
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
+    #[tokio::test]
+    async fn try_create_duplicates() {
+        let db = models::new_db();
+        let api = filters::post_sim(db.into(), policy(), Default::default());
+    
+        let response = request()
+            .method("POST")
+            .path("/holodeck")
+            .json(&models::Simulation::new(1, "Bride Of Chaotica!"))
+            .reply(&api)
+            .await;
+    
+        assert_eq!(response.status(), StatusCode::CREATED);
+    
+        let response = request()
+            .method("POST")
+            .path("/holodeck")
+            .json(&models::Simulation::new(1, "Bride Of Chaotica!"))
+            .reply(&api)
+            .await;
+    
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+    
+    #[tokio::test]
+    async fn try_update() {
+        let db = models::new_db();
+        let api = filters::update_sim(db.into(), policy(), Default::default());
+
+        let response = request()
+            .method("PUT")
+            .path("/holodeck/1")
+            .json(&models::NewName::new("The Big Goodbye!"))
+            .reply(&api)
+            .await;
+
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let response = request()
+            .method("PUT")
+            .path("/holodeck/1")
+            .json(&models::NewName::new("The Short Hello!"))
+            .reply(&api)
+            .await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn try_delete() {
+        let simulation = models::Simulation::new(1, "The Big Goodbye!");
+
+        let db = models::new_db();
+        db.lock().await.insert(simulation);
+
+        let api = filters::delete_sim(db.into(), super::rooms::new_db(), Default::default());
+
+        let response = request()
+            .method("DELETE")
+            .path("/holodeck/1")
+            .reply(&api)
+            .await;
+
+            assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn try_create_invalid() {
+        use super::validation::ValidationErrors;
+
+        let db = models::new_db();
+        let api = filters::post_sim(db.clone().into(), policy(), Default::default());
+
+        let response = request()
+            .method("POST")
+            .path("/holodeck")
+            .json(&models::Simulation::new(0, "  "))
+            .reply(&api)
+            .await;
+
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body: ValidationErrors = serde_json::from_slice(response.body()).unwrap();
+        let fields: Vec<&str> = body.errors.iter().map(|e| e.field.as_str()).collect();
+        assert_eq!(fields, ["id", "name"]);
+        assert!(db.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn try_update_invalid() {
+        let db = models::new_db();
+        let api = filters::update_sim(db.clone().into(), policy(), Default::default());
+
+        let response = request()
+            .method("PUT")
+            .path("/holodeck/1")
+            .json(&models::NewName::new("The Big\nGoodbye"))
+            .reply(&api)
+            .await;
+
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let response = request()
+            .method("PUT")
+            .path("/holodeck/0")
+            .json(&models::NewName::new("The Big Goodbye"))
+            .reply(&api)
+            .await;
+
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(db.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn try_metadata() {
+        let db = models::new_db();
+        let api = filters::routes(db.clone().into(), super::rooms::new_db(), policy(), Default::default(), Default::default());
+
+        // A version 1 payload is still accepted, and timestamps sent by the client are ignored
+        let response = request()
+            .method("POST")
+            .path("/holodeck")
+            .body(r#"{"id": 1, "name": "The Big Goodbye", "created_at": "1941-12-07T00:00:00Z"}"#)
+            .reply(&api)
+            .await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let created = models::get_simulation(&*db.lock().await, 1).cloned().unwrap();
+        assert_eq!(created.schema_version, models::SCHEMA_VERSION);
+        assert_eq!(created.created_at, created.updated_at);
+        assert!(created.created_at.unwrap().timestamp() > 0);
+
+        let mut details = models::NewName::new("The Big Goodbye");
+        details.genre = Some(String::from("Noir"));
+        details.tags = Some(vec![String::from("noir")].into_iter().collect());
+        request().method("PUT").path("/holodeck/1").json(&details).reply(&api).await;
+
+        // A plain rename keeps the metadata
+        let response = request()
+            .method("PUT")
+            .path("/holodeck/1")
+            .json(&models::NewName::new("The Short Hello"))
+            .reply(&api)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let updated = models::get_simulation(&*db.lock().await, 1).cloned().unwrap();
+        assert_eq!(updated.name, "The Short Hello");
+        assert_eq!(updated.genre.as_deref(), Some("Noir"));
+        assert!(updated.tags.contains("noir"));
+        assert_eq!(updated.created_at, created.created_at);
+        assert!(updated.updated_at >= created.updated_at);
+
+        // Asked for JSON, the create answers with the simulation as stored
+        let response = request()
+            .method("POST")
+            .path("/holodeck")
+            .header("accept", "application/json")
+            .json(&models::Simulation::new(2, "Bride Of Chaotica!"))
+            .reply(&api)
+            .await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let stored: models::Simulation = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(stored.name, "Bride Of Chaotica!");
+        assert_eq!(stored.created_at, models::get_simulation(&*db.lock().await, 2).unwrap().created_at);
+
+        // And so does the update
+        let response = request()
+            .method("PUT")
+            .path("/holodeck/2")
+            .header("accept", "application/json")
+            .json(&models::NewName::new("The Revenge Of Chaotica!"))
+            .reply(&api)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let stored: models::Simulation = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(stored.name, "The Revenge Of Chaotica!");
+        assert_eq!(stored.updated_at, models::get_simulation(&*db.lock().await, 2).unwrap().updated_at);
+    }
+
+    #[tokio::test]
+    async fn try_transitions() {
+        use super::lifecycle::{Action, State};
 
-    use super::models;
+        let db = models::new_db();
+        db.lock().await.insert(models::Simulation::new(1, "The Big Goodbye!"));
+        let api = filters::transition_sim(db.clone().into(), policy());
 
-    pub async fn handle_list_sims(opt: Option<u64>, db: models::Db) -> Result<impl warp::Reply, Infallible> {
-        let mut result = db.lock().await.clone();
-        if let Some(param) = opt {
-            result.retain(|k| k.id == param);
+        for action in ["run", "pause", "resume", "end"].iter() {
+            let response = request()
+                .method("POST")
+                .path(&format!("/holodeck/1/{}", action))
+                .reply(&api)
+                .await;
+            assert_eq!(response.status(), StatusCode::OK, "{}", action);
         }
-        Ok(warp::reply::json(&result)) 
-    }
 
-    pub async fn handle_create_sim(sim: models::Simulation, db: models::Db) -> Result<impl warp::Reply, Infallible> {
-        let mut map = db.lock().await;
+        let response = request().method("POST").path("/holodeck/1/run").reply(&api).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        assert_eq!(response.body(), "Cannot run simulation #1 while it is ended.\n");
 
-        // if let Some(result) = map.get(&sim){
-        if let Some(result) = models::get_simulation(&*map, sim.id){ //0
-            return Ok(warp::reply::with_status(
-                format!("Simulation #{} already exists under the name {}\n", result.id, result.name), 
-                StatusCode::BAD_REQUEST,
-            ));
-        }
+        let sim = models::get_simulation(&*db.lock().await, 1).cloned().unwrap();
+        assert_eq!(sim.state, State::Ended);
+        let actions: Vec<Action> = sim.transitions.iter().map(|t| t.action).collect();
+        assert_eq!(actions, [Action::Run, Action::Pause, Action::Resume, Action::End]);
+        assert!(sim.transitions.windows(2).all(|pair| pair[0].at <= pair[1].at && pair[0].to == pair[1].from));
 
-        map.insert(sim.clone());
-        Ok(warp::reply::with_status(format!("Simulation #{} created.\n", sim.id), StatusCode::CREATED))
-    }
+        let response = request().method("POST").path("/holodeck/2/run").reply(&api).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
 
-    pub async fn handle_update_sim(id: u64, new: models::NewName, db: models::Db) -> Result<impl warp::Reply, Infallible> {
-        // Replaced entry
-        if let Some(_) = db.lock().await.replace(Simulation{id, name: new.name}){
-            return Ok(warp::reply::with_status(
-                format!("Simulation #{} was updated.\n", id), 
-                StatusCode::OK,
-            ));
-        }
-        
-        // Create entry
-        Ok(warp::reply::with_status(
-            format!("Simulation #{} was inserted.\n", id), 
-            StatusCode::CREATED,
-        ))
+        let response = request().method("POST").path("/holodeck/1/explode").reply(&api).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
     }
 
-    pub async fn handle_delete_sim(id: u64, db: models::Db) -> Result<impl warp::Reply, Infallible> {
-        if db.lock().await.remove(&Simulation{id, name: String::new(),}){
-            return Ok(warp::reply::with_status(
-                format!("Simulation #{} was deleted.\n", id), 
-                StatusCode::OK,
-            ))
+    #[tokio::test]
+    async fn try_reservations() {
+        use super::rooms::{self, NewReservation, Reservation, Room};
+        use chrono::{Duration, Utc};
+
+        let db = models::new_db();
+        db.lock().await.insert(models::Simulation::new(1, "The Big Goodbye!"));
+        let api = filters::routes(db.into(), rooms::new_db(), policy(), Default::default(), Default::default());
+
+        let response = request()
+            .method("POST")
+            .path("/rooms")
+            .json(&Room { id: 3, name: "Holodeck 3".to_string() })
+            .reply(&api)
+            .await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let now = Utc::now();
+        let booking = |simulation, from: i64, to: i64| NewReservation {
+            simulation,
+            start: now + Duration::hours(from),
+            end: now + Duration::hours(to),
         };
-        
-        Ok(warp::reply::with_status(
-            format!("No data was deleted.\n"),
-            StatusCode::OK,
-        ))
-    }
-}
 
-#[cfg(test)]
-mod tests {
-    use warp::http::StatusCode;
-    use warp::test::request;
-    use super::{filters,models};
-    use std::collections::HashSet;
+        let response = request().method("POST").path("/rooms/3/reservations").json(&booking(1, 1, 3)).reply(&api).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let first: Reservation = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!((first.room, first.simulation), (3, 1));
+
+        let response = request().method("POST").path("/rooms/3/reservations").json(&booking(1, 2, 4)).reply(&api).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        assert!(String::from_utf8_lossy(response.body()).contains(&format!("reservation #{}", first.id)));
+
+        let response = request().method("POST").path("/rooms/3/reservations").json(&booking(2, 3, 4)).reply(&api).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let response = request().method("POST").path("/rooms/4/reservations").json(&booking(1, 3, 4)).reply(&api).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = request().method("POST").path("/rooms/3/reservations").json(&booking(1, 3, 4)).reply(&api).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let response = request().method("GET").path("/rooms/3/reservations").reply(&api).await;
+        let schedule: Vec<Reservation> = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(schedule.len(), 2);
+        assert!(schedule[0].end <= schedule[1].start);
+
+        let path = format!("/rooms/3/reservations/{}", first.id);
+        let response = request().method("DELETE").path(&path).reply(&api).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = request().method("DELETE").path(&path).reply(&api).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = request().method("DELETE").path("/rooms/3").reply(&api).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = request().method("GET").path("/rooms/3/reservations").reply(&api).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
 
     #[tokio::test]
-    async fn try_list() {
-        use std::str;
-        use serde_json;
+    async fn try_reservations_follow_simulations() {
+        use super::rooms::{self, NewReservation, Reservation, Room};
+        use super::tenants::NewTenant;
+        use chrono::{Duration, Utc};
+
+        let db = models::new_db();
+        db.lock().await.insert(models::Simulation::new(1, "The Big Goodbye!"));
+        let api = filters::routes(db.into(), rooms::new_db(), policy(), Default::default(), Default::default());
+        request().method("POST").path("/rooms").json(&Room { id: 3, name: "Holodeck 3".to_string() }).reply(&api).await;
+        let tenant = NewTenant { name: "enterprise".to_string(), quota: None };
+        let response = request().method("POST").path("/admin/tenants").header("x-holodeck-roles", "admin").json(&tenant).reply(&api).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
 
-        let simulation1 = models::Simulation{
-            id: 1, 
-            name: String::from("The Big Goodbye!"),
+        let now = Utc::now();
+        let booking = |from: i64, to: i64| NewReservation {
+            simulation: 1,
+            start: now + Duration::hours(from),
+            end: now + Duration::hours(to),
+        };
+        let schedule = || async {
+            let response = request().method("GET").path("/rooms/3/reservations").reply(&api).await;
+            serde_json::from_slice::<Vec<Reservation>>(response.body()).unwrap()
         };
 
+        // Simulation #1 of the default tenant is not the enterprise's
+        let enterprise = |method: &str, path: &str| request().method(method).path(path).header("x-holodeck-tenant", "enterprise");
+        let response = enterprise("POST", "/rooms/3/reservations").json(&booking(1, 2)).reply(&api).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        enterprise("POST", "/holodeck").json(&models::Simulation::new(1, "Fair Haven")).reply(&api).await;
+        let response = enterprise("POST", "/rooms/3/reservations").json(&booking(1, 2)).reply(&api).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let theirs: Reservation = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(theirs.tenant.as_deref(), Some("enterprise"));
+        let response = request().method("POST").path("/rooms/3/reservations").json(&booking(2, 3)).reply(&api).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
 
-        let simulation2 = models::Simulation{
-            id: 2, 
-            name: String::from("Bride Of Chaotica!"),
-        };
+        // Deleting the simulation cancels its bookings, so one created under the same id starts with none
+        request().method("DELETE").path("/holodeck/1").reply(&api).await;
+        request().method("POST").path("/holodeck").json(&models::Simulation::new(1, "The Long Goodbye")).reply(&api).await;
+        assert_eq!(schedule().await, std::slice::from_ref(&theirs));
 
-        let db = models::new_db();
-        db.lock().await.insert(simulation1.clone());
-        db.lock().await.insert(simulation2.clone());
+        let response = request().method("DELETE").path("/admin/tenants/enterprise").header("x-holodeck-roles", "admin").reply(&api).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(schedule().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn try_safety_policy() {
+        use super::safety::Violations;
 
-        let api = filters::list_sims(db);
+        let db = models::new_db();
+        let api = filters::routes(db.clone().into(), super::rooms::new_db(), policy(), Default::default(), Default::default());
+        let unsafe_sim = r#"{"id": 1, "name": "The Big Goodbye", "safety": {"protocols": false, "reason": "Realism"}}"#;
 
         let response = request()
-            .method("GET")
+            .method("POST")
             .path("/holodeck")
+            .header("x-holodeck-user", "barclay")
+            .header("x-holodeck-approved-by", "barclay")
+            .body(unsafe_sim)
             .reply(&api)
             .await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let body: Violations = serde_json::from_slice(response.body()).unwrap();
+        let rules: Vec<&str> = body.violations.iter().map(|v| v.rule.as_str()).collect();
+        assert_eq!(rules, ["safety-off", "safety-off"]);
+        assert!(db.lock().await.is_empty());
 
-        let result: Vec<u8> = response.into_body().into_iter().collect();
-        let result = str::from_utf8(&result).unwrap();
-        let result: HashSet<models::Simulation> = serde_json::from_str(result).unwrap();
-        assert_eq!(models::get_simulation(&result, 1).unwrap(), &simulation1);
-        assert_eq!(models::get_simulation(&result, 2).unwrap(), &simulation2);
+        let approved = |method: &str, path: &str| {
+            request()
+                .method(method)
+                .path(path)
+                .header("x-holodeck-user", "picard")
+                .header("x-holodeck-roles", "captain, admin")
+                .header("x-holodeck-approved-by", "riker,troi")
+        };
+        let response = approved("POST", "/holodeck").body(unsafe_sim).reply(&api).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let created = models::get_simulation(&*db.lock().await, 1).cloned().unwrap();
+        assert!(!created.safety.protocols);
+        assert_eq!(created.safety.reason.as_deref(), Some("Realism"));
 
+        // Running, and changing, a simulation with the protocols off needs the same sign-off
+        let response = request().method("POST").path("/holodeck/1/run").reply(&api).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
         let response = request()
-            .method("GET")
-            .path("/holodeck/2")
+            .method("PUT")
+            .path("/holodeck/1")
+            .json(&models::NewName::new("The Short Hello"))
             .reply(&api)
             .await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
 
-        let result: Vec<u8> = response.into_body().into_iter().collect();
-        let result = str::from_utf8(&result).unwrap();
-        let result: HashSet<models::Simulation> = serde_json::from_str(result).unwrap();
-        assert_eq!(result.len(),1);
-        assert_eq!(models::get_simulation(&result, 2).unwrap(), &simulation2);
+        let response = approved("POST", "/holodeck/1/run").reply(&api).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // Turning the protocols back on is always allowed
+        let mut safe = models::NewName::new("The Big Goodbye");
+        safe.safety = Some(Default::default());
+        let response = request().method("PUT").path("/holodeck/1").json(&safe).reply(&api).await;
+        assert_eq!(response.status(), StatusCode::OK);
     }
 
     #[tokio::test]
-    async fn try_create() {
+    async fn try_characters() {
+        use super::characters::{Character, NewCharacter};
+
         let db = models::new_db();
-        let api = filters::post_sim(db);
-    
+        db.lock().await.insert(models::Simulation::new(1, "The Big Goodbye"));
+        let api = filters::routes(db.clone().into(), super::rooms::new_db(), policy(), Default::default(), Default::default());
+
+        for (name, role) in [("Dixon Hill", "Detective"), ("Cyrus Redblock", "Gangster")].iter() {
+            let response = request()
+                .method("POST")
+                .path("/holodeck/1/characters")
+                .json(&NewCharacter::new(*name, *role))
+                .reply(&api)
+                .await;
+            assert_eq!(response.status(), StatusCode::CREATED);
+        }
+
+        let response = request()
+            .method("PUT")
+            .path("/holodeck/1/characters/2")
+            .json(&NewCharacter::new("Cyrus Redblock", "Villain"))
+            .reply(&api)
+            .await;
+        let updated: Character = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!((updated.id, updated.role.as_str()), (2, "Villain"));
+
+        let response = request().method("GET").path("/holodeck/1/characters/2").reply(&api).await;
+        assert_eq!(serde_json::from_slice::<Character>(response.body()).unwrap(), updated);
+        let response = request().method("DELETE").path("/holodeck/1/characters/1").reply(&api).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = request().method("GET").path("/holodeck/1/characters/1").reply(&api).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = request()
+            .method("POST")
+            .path("/holodeck/1/characters")
+            .json(&NewCharacter::new("", "Extra"))
+            .reply(&api)
+            .await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        // Characters are left out of the list unless asked for
+        let response = request().method("GET").path("/holodeck").reply(&api).await;
+        let sims: Vec<models::Simulation> = serde_json::from_slice(response.body()).unwrap();
+        assert!(sims[0].characters.is_empty());
+        let response = request().method("GET").path("/holodeck/1?expand=characters").reply(&api).await;
+        let sim: models::Simulation = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(sim.characters, [updated]);
+        let response = request().method("GET").path("/holodeck?expand=cast").reply(&api).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        // Deleting the simulation takes its characters with it
+        request().method("DELETE").path("/holodeck/1").reply(&api).await;
+        let response = request().method("GET").path("/holodeck/1/characters").reply(&api).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
         let response = request()
             .method("POST")
             .path("/holodeck")
-            .json(&models::Simulation{
-                id: 1,
-                name: String::from("The Big Goodbye")
-            })
+            .body(r#"{"id": 1, "name": "The Big Goodbye", "characters": [{"id": 9, "name": "Felix Leech", "role": "Henchman"}]}"#)
             .reply(&api)
             .await;
-    
         assert_eq!(response.status(), StatusCode::CREATED);
+        let response = request().method("GET").path("/holodeck/1/characters").reply(&api).await;
+        assert_eq!(response.body(), "[]");
+
+        // A new simulation numbers from 1 again, and never reuses the id of a character it removed
+        let add = |name: &'static str| request().method("POST").path("/holodeck/1/characters").json(&NewCharacter::new(name, "Extra"));
+        let added = |response: warp::http::Response<warp::hyper::body::Bytes>| serde_json::from_slice::<Character>(response.body()).unwrap().id;
+        assert_eq!(added(add("Jimmy").reply(&api).await), 1);
+        request().method("DELETE").path("/holodeck/1/characters/1").reply(&api).await;
+        assert_eq!(added(add("Bernie").reply(&api).await), 2);
     }
 
     #[tokio::test]
-    async fn try_create_duplicates() {
+    async fn try_tags() {
+        use std::collections::BTreeMap;
+
         let db = models::new_db();
-        let api = filters::post_sim(db);
-    
+        let api = filters::routes(db.clone().into(), super::rooms::new_db(), policy(), Default::default(), Default::default());
+        for (id, tags) in [(1, vec!["noir", "detective"]), (2, vec!["noir", "combat"]), (3, vec!["combat"])] {
+            let mut sim = models::Simulation::new(id, format!("Simulation {}", id));
+            sim.tags = tags.into_iter().map(String::from).collect();
+            request().method("POST").path("/holodeck").json(&sim).reply(&api).await;
+        }
+
+        let ids = |body: &[u8]| {
+            let mut ids: Vec<u64> = serde_json::from_slice::<Vec<models::Simulation>>(body).unwrap().iter().map(|sim| sim.id).collect();
+            ids.sort_unstable();
+            ids
+        };
+        for (query, expected) in [
+            ("?tag=noir", vec![1, 2]),
+            ("?tag=noir&tag=!combat", vec![1]),
+            ("?tag=noir&tag=%21combat", vec![1]),
+            ("?tag=!noir", vec![3]),
+            ("?tag=western", vec![]),
+        ] {
+            let response = request().method("GET").path(&format!("/holodeck{}", query)).reply(&api).await;
+            assert_eq!(ids(response.body()), expected, "{}", query);
+        }
+
+        // The index follows updates and deletes
+        let mut retag = models::NewName::new("Simulation 2");
+        retag.tags = Some(vec![String::from("western")].into_iter().collect());
+        request().method("PUT").path("/holodeck/2").json(&retag).reply(&api).await;
+        request().method("DELETE").path("/holodeck/3").reply(&api).await;
+
+        let response = request().method("GET").path("/tags").reply(&api).await;
+        let counts: BTreeMap<String, usize> = serde_json::from_slice(response.body()).unwrap();
+        let expected: BTreeMap<String, usize> =
+            vec![("detective", 1), ("noir", 1), ("western", 1)].into_iter().map(|(tag, n)| (tag.to_string(), n)).collect();
+        assert_eq!(counts, expected);
+        let response = request().method("GET").path("/holodeck?tag=combat").reply(&api).await;
+        assert!(ids(response.body()).is_empty());
+    }
+
+    #[tokio::test]
+    async fn try_formats() {
+        use super::codec::Format;
+
+        let db = models::new_db();
+        let api = filters::routes(db.clone().into(), super::rooms::new_db(), policy(), Default::default(), Default::default());
+
+        let mut sim = models::Simulation::new(1, "The Big Goodbye");
+        sim.tags.insert("noir".to_string());
         let response = request()
             .method("POST")
             .path("/holodeck")
-            .json(&models::Simulation{
-                id: 1,
-                name: String::from("Bride Of Chaotica!")
-            })
+            .header("content-type", "application/msgpack")
+            .body(Format::MsgPack.encode(&sim).unwrap())
             .reply(&api)
             .await;
-    
         assert_eq!(response.status(), StatusCode::CREATED);
-    
+
+        let response = request()
+            .method("PUT")
+            .path("/holodeck/2")
+            .header("content-type", "application/cbor")
+            .body(Format::Cbor.encode(&models::NewName::new("Bride Of Chaotica!")).unwrap())
+            .reply(&api)
+            .await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        for format in super::codec::FORMATS.iter() {
+            let response = request()
+                .method("GET")
+                .path("/holodeck/1")
+                .header("accept", format.content_type())
+                .reply(&api)
+                .await;
+            assert_eq!(response.headers()["content-type"], format.content_type());
+            let sim: models::Simulation = format.decode(response.body()).unwrap();
+            assert_eq!(sim.name, "The Big Goodbye");
+            assert!(sim.tags.contains("noir"));
+        }
+
+        let response = request().method("GET").path("/holodeck").header("accept", "text/html").reply(&api).await;
+        assert_eq!(response.status(), StatusCode::NOT_ACCEPTABLE);
+
         let response = request()
             .method("POST")
             .path("/holodeck")
-            .json(&models::Simulation{
-                id: 1,
-                name: String::from("Bride Of Chaotica!")
-            })
+            .header("content-type", "application/cbor")
+            .body("not cbor at all")
             .reply(&api)
             .await;
-    
         assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = request()
+            .method("POST")
+            .path("/holodeck")
+            .header("content-type", "text/plain")
+            .body("The Big Goodbye")
+            .reply(&api)
+            .await;
+        assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(db.lock().await.len(), 2);
     }
-    
+
     #[tokio::test]
-    async fn try_update() {
+    async fn try_graphql() {
+        use super::tenants::{NewTenant, Tenants};
+
         let db = models::new_db();
-        let api = filters::update_sim(db);
+        db.lock().await.insert(models::Simulation::new(1, "The Big Goodbye"));
+        let tenants = Tenants::from(db.clone());
+        assert!(tenants.create(NewTenant { name: "enterprise".to_string(), quota: None }).await);
+        let api = filters::graphql(tenants.clone(), super::rooms::new_db(), policy(), None);
 
         let response = request()
-            .method("PUT")
-            .path("/holodeck/1")
-            .json(&models::NewName{ name: String::from("The Big Goodbye!")})
+            .method("POST")
+            .path("/graphql")
+            .json(&serde_json::json!({ "query": "{ simulations { total items { name } } }" }))
+            .reply(&api)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let result: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(result["data"]["simulations"], serde_json::json!({ "total": 1, "items": [{ "name": "The Big Goodbye" }] }));
+
+        // The tenant header picks the catalogue, as it does for REST
+        let response = request()
+            .method("POST")
+            .path("/graphql")
+            .header("x-holodeck-tenant", "enterprise")
+            .json(&serde_json::json!({ "query": "mutation { createSimulation(input: {id: 1, name: \"Fair Haven\"}) { name } }" }))
             .reply(&api)
             .await;
+        let result: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(result["data"]["createSimulation"]["name"], "Fair Haven");
+        assert_eq!(models::get_simulation(&db.snapshot(), 1).unwrap().name, "The Big Goodbye");
+        let response = request()
+            .method("POST")
+            .path("/graphql")
+            .header("x-holodeck-tenant", "voyager")
+            .json(&serde_json::json!({ "query": "{ simulations { total } }" }))
+            .reply(&filters::routes(tenants, super::rooms::new_db(), policy(), Default::default(), Default::default()))
+            .await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let mut ws = warp::test::ws()
+            .path("/graphql/ws")
+            .header("sec-websocket-protocol", "graphql-transport-ws")
+            .handshake(api.clone())
+            .await
+            .unwrap();
+        ws.send_text(r#"{"type": "connection_init"}"#).await;
+        assert_eq!(ws.recv().await.unwrap().to_str().unwrap(), r#"{"type":"connection_ack"}"#);
+        ws.send_text(r#"{"id": "1", "type": "subscribe", "payload": {"query": "subscription { simulationChanges { kind id } }"}}"#).await;
+        while db.snapshot().subscribers() == 0 {
+            tokio::task::yield_now().await;
+        }
 
+        // Writes made through REST are seen too
+        let response = request()
+            .method("POST")
+            .path("/holodeck")
+            .json(&models::Simulation::new(2, "Fair Haven"))
+            .reply(&filters::post_sim(db.into(), policy(), Default::default()))
+            .await;
         assert_eq!(response.status(), StatusCode::CREATED);
 
+        let message: serde_json::Value = serde_json::from_str(ws.recv().await.unwrap().to_str().unwrap()).unwrap();
+        assert_eq!(
+            message,
+            serde_json::json!({ "id": "1", "type": "next", "payload": { "data": { "simulationChanges": { "kind": "CREATED", "id": 2 } } } })
+        );
+    }
+
+    #[tokio::test]
+    async fn try_tenants() {
+        use super::tenants::{Tenant, Tenants};
+
+        let tenants = Tenants::from(models::new_db());
+        let api = filters::routes(tenants.clone(), super::rooms::new_db(), policy(), Default::default(), Default::default());
+        let admin = |method: &str, path: &str| {
+            request()
+                .method(method)
+                .path(path)
+                .header("x-holodeck-user", "picard")
+                .header("x-holodeck-roles", "admin")
+        };
+
         let response = request()
-            .method("PUT")
-            .path("/holodeck/1")
-            .json(&models::NewName{ name: String::from("The Short Hello!")})
+            .method("POST")
+            .path("/admin/tenants")
+            .json(&serde_json::json!({ "name": "enterprise" }))
+            .reply(&api)
+            .await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = admin("POST", "/admin/tenants").json(&serde_json::json!({ "name": "Enterprise D" })).reply(&api).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let response = admin("POST", "/admin/tenants")
+            .json(&serde_json::json!({ "name": "enterprise", "quota": 1 }))
             .reply(&api)
             .await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let response = admin("POST", "/admin/tenants").json(&serde_json::json!({ "name": "enterprise" })).reply(&api).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        // A server flushing the default tenant to a data file keeps to that one
+        let single = filters::routes(Tenants::single(models::new_db()), super::rooms::new_db(), policy(), Default::default(), Default::default());
+        let response = admin("POST", "/admin/tenants").json(&serde_json::json!({ "name": "voyager" })).reply(&single).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        // The same id in two tenants names two different simulations
+        let post = |tenant: Option<&str>, sim: models::Simulation| {
+            let builder = request().method("POST").path("/holodeck").json(&sim);
+            match tenant {
+                Some(tenant) => builder.header("x-holodeck-tenant", tenant),
+                None => builder,
+            }
+        };
+        let response = post(Some("enterprise"), models::Simulation::new(1, "Fair Haven")).reply(&api).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let response = post(None, models::Simulation::new(1, "The Big Goodbye")).reply(&api).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let response = request().path("/holodeck").header("x-holodeck-tenant", "enterprise").reply(&api).await;
+        let listed: Vec<models::Simulation> = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(listed.iter().map(|s| s.name.as_str()).collect::<Vec<_>>(), ["Fair Haven"]);
+
+        let response = post(Some("enterprise"), models::Simulation::new(2, "Sherlock Holmes")).reply(&api).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = post(Some("voyager"), models::Simulation::new(1, "Captain Proton")).reply(&api).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
 
+        let response = admin("GET", "/admin/tenants").reply(&api).await;
+        let listed: Vec<Tenant> = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(
+            listed,
+            [Tenant {
+                name: "enterprise".to_string(),
+                quota: Some(1),
+                simulations: 1,
+            }]
+        );
+
+        let response = admin("DELETE", "/admin/tenants/enterprise").reply(&api).await;
         assert_eq!(response.status(), StatusCode::OK);
+        let response = admin("DELETE", "/admin/tenants/enterprise").reply(&api).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(tenants.default_db().lock().await.len(), 1);
     }
 
     #[tokio::test]
-    async fn try_delete() {
-        let simulation = models::Simulation{
-            id: 1, 
-            name: String::from("The Big Goodbye!"),
+    async fn try_idempotency() {
+        let db = models::new_db();
+        let api = filters::routes(db.clone().into(), super::rooms::new_db(), policy(), Default::default(), Default::default());
+        let post = |key: &str, sim: models::Simulation| {
+            request().method("POST").path("/holodeck").header("idempotency-key", key).json(&sim)
         };
 
-        let db = models::new_db();
-        db.lock().await.insert(simulation);
+        let first = post("retry-1", models::Simulation::new(1, "The Big Goodbye")).reply(&api).await;
+        assert_eq!(first.status(), StatusCode::CREATED);
+        // The retry gets the same answer rather than a 400 for the simulation it created
+        let retried = post("retry-1", models::Simulation::new(1, "The Big Goodbye")).reply(&api).await;
+        assert_eq!(retried.status(), StatusCode::CREATED);
+        assert_eq!(retried.body(), first.body());
+        let reused = post("retry-1", models::Simulation::new(2, "Fair Haven")).reply(&api).await;
+        assert_eq!(reused.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(db.snapshot().len(), 1);
+        // Somebody else's key is their own, even when it is spelled the same
+        let theirs = post("retry-1", models::Simulation::new(1, "The Big Goodbye")).header("x-holodeck-user", "barclay").reply(&api).await;
+        assert_eq!(theirs.status(), StatusCode::BAD_REQUEST);
 
-        let api = filters::delete_sim(db);
+        // Characters get a new id on every POST, so a retry without a key would add a second one
+        let add = |key: &str| {
+            request()
+                .method("POST")
+                .path("/holodeck/1/characters")
+                .header("idempotency-key", key)
+                .json(&serde_json::json!({ "name": "Dixon Hill", "role": "Detective" }))
+        };
+        assert_eq!(add("retry-2").reply(&api).await.status(), StatusCode::CREATED);
+        assert_eq!(add("retry-2").reply(&api).await.status(), StatusCode::CREATED);
+        assert_eq!(models::get_simulation(&db.snapshot(), 1).unwrap().characters.len(), 1);
+        assert_eq!(add("retry-3").reply(&api).await.status(), StatusCode::CREATED);
+        assert_eq!(models::get_simulation(&db.snapshot(), 1).unwrap().characters.len(), 2);
+    }
 
-        let response = request()
-            .method("DELETE")
-            .path("/holodeck/1")
+    #[tokio::test]
+    async fn try_audit() {
+        use super::audit::{Entry, Operation};
+
+        let api = filters::routes(models::new_db().into(), super::rooms::new_db(), policy(), Default::default(), Default::default());
+        let as_user = |user: &str, method: &str, path: &str| request().method(method).path(path).header("x-holodeck-user", user);
+
+        as_user("barclay", "POST", "/holodeck").json(&models::Simulation::new(1, "The Big Goodbye")).reply(&api).await;
+        as_user("data", "PUT", "/holodeck/1").json(&models::NewName::new("The Long Hello")).reply(&api).await;
+        as_user("data", "POST", "/holodeck/1/characters")
+            .json(&serde_json::json!({ "name": "Dixon Hill", "role": "Detective" }))
             .reply(&api)
             .await;
+        as_user("barclay", "POST", "/holodeck").json(&models::Simulation::new(2, "Fair Haven")).reply(&api).await;
+        as_user("barclay", "POST", "/holodeck/2/run").reply(&api).await;
+        as_user("picard", "DELETE", "/holodeck/2").reply(&api).await;
+        // Refused writes change nothing, so there is nothing to record
+        as_user("barclay", "POST", "/holodeck").json(&models::Simulation::new(1, "The Big Goodbye")).reply(&api).await;
 
-            assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(request().path("/audit").reply(&api).await.status(), StatusCode::FORBIDDEN);
+        let audit = |path: &str| as_user("picard", "GET", path).header("x-holodeck-roles", "admin");
+        let response = audit("/audit").reply(&api).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let entries: Vec<Entry> = serde_json::from_slice(response.body()).unwrap();
+        let operations: Vec<Operation> = entries.iter().map(|e| e.operation).collect();
+        assert_eq!(
+            operations,
+            [Operation::Create, Operation::Update, Operation::CreateCharacter, Operation::Create, Operation::Run, Operation::Delete]
+        );
+        assert_eq!(entries[1].before.as_ref().unwrap().name, "The Big Goodbye");
+        assert_eq!(entries[1].after.as_ref().unwrap().name, "The Long Hello");
+        assert_eq!(entries[5].actor.as_deref(), Some("picard"));
+        assert!(entries[5].after.is_none());
+
+        let response = audit("/audit?actor=barclay&id=2").reply(&api).await;
+        let entries: Vec<Entry> = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(entries.iter().map(|e| e.operation).collect::<Vec<_>>(), [Operation::Create, Operation::Run]);
+        let since = entries[1].at.to_rfc3339_opts(chrono::SecondsFormat::AutoSi, true);
+        let response = audit(&format!("/audit?since={}", since)).reply(&api).await;
+        let entries: Vec<Entry> = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(entries.iter().map(|e| e.operation).collect::<Vec<_>>(), [Operation::Run, Operation::Delete]);
+
+        let response = audit("/audit?id=1").header("accept", "application/x-ndjson").reply(&api).await;
+        assert_eq!(response.headers()["content-type"], "application/x-ndjson");
+        let lines: Vec<Entry> = response.body().split(|b| *b == b'\n').filter(|line| !line.is_empty()).map(|line| serde_json::from_slice(line).unwrap()).collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(audit("/audit?since=yesterday").reply(&api).await.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn try_backup() {
+        use super::audit::{Entry, Operation};
+        use super::backup::Archive;
+        use super::tenants::{NewTenant, Tenants};
+
+        let tenants = Tenants::from(models::new_db());
+        assert!(tenants.create(NewTenant { name: "enterprise".to_string(), quota: None }).await);
+        let api = filters::routes(tenants, super::rooms::new_db(), policy(), Default::default(), Default::default());
+        let admin = |method: &str, path: &str| {
+            request()
+                .method(method)
+                .path(path)
+                .header("x-holodeck-user", "picard")
+                .header("x-holodeck-roles", "admin")
+        };
+
+        for sim in [models::Simulation::new(1, "The Big Goodbye"), models::Simulation::new(2, "Fair Haven")] {
+            request().method("POST").path("/holodeck").json(&sim).reply(&api).await;
+        }
+        request()
+            .method("POST")
+            .path("/holodeck")
+            .header("x-holodeck-tenant", "enterprise")
+            .json(&models::Simulation::new(3, "Sherlock"))
+            .reply(&api)
+            .await;
+
+        assert_eq!(request().method("POST").path("/admin/backup").reply(&api).await.status(), StatusCode::FORBIDDEN);
+        let response = admin("POST", "/admin/backup").reply(&api).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers()["content-disposition"].to_str().unwrap().starts_with("attachment"));
+        let archive: Archive = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(archive.count, 2);
+
+        let mut tampered = archive.clone();
+        tampered.simulations[1]["name"] = "Fair Haven II".into();
+        let response = admin("POST", "/admin/restore").header("x-holodeck-tenant", "enterprise").json(&tampered).reply(&api).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let response = request().method("POST").path("/admin/restore").json(&archive).reply(&api).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        // The restored catalogue replaces the tenant's whole catalogue, not just the ids it holds
+        let response = admin("POST", "/admin/restore").header("x-holodeck-tenant", "enterprise").json(&archive).reply(&api).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = request().path("/holodeck").header("x-holodeck-tenant", "enterprise").reply(&api).await;
+        let sims: models::Simulations = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(sims.len(), 2);
+        assert!(sims.contains(1) && sims.contains(2) && !sims.contains(3));
+
+        let response = admin("GET", "/audit").header("x-holodeck-tenant", "enterprise").reply(&api).await;
+        let entries: Vec<Entry> = serde_json::from_slice(response.body()).unwrap();
+        let restored: Vec<(u64, bool)> = entries.iter().filter(|e| e.operation == Operation::Restore).map(|e| (e.id, e.after.is_some())).collect();
+        assert_eq!(restored, [(1, true), (2, true), (3, false)]);
+    }
+
+    #[tokio::test]
+    async fn try_restore_safety() {
+        use super::backup::Archive;
+        use super::safety::Violations;
+
+        let db = models::new_db();
+        db.lock().await.insert(models::Simulation::new(1, "The Big Goodbye"));
+        let api = filters::routes(db.clone().into(), super::rooms::new_db(), policy(), Default::default(), Default::default());
+
+        // Restoring is no way around the approvals turning the protocols off takes
+        let mut unsafe_sim = models::Simulation::new(2, "Fair Haven");
+        unsafe_sim.safety.protocols = false;
+        let archive = Archive::of(&[models::Simulation::new(1, "The Big Goodbye"), unsafe_sim]);
+        let restore = || request().method("POST").path("/admin/restore").header("x-holodeck-user", "barclay").header("x-holodeck-roles", "admin");
+        let response = restore().json(&archive).reply(&api).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let refused: Violations = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(refused.violations.len(), 1);
+        assert_eq!(refused.violations[0].rule, "safety-off");
+        assert!(refused.violations[0].message.starts_with("simulation #2 requires 2 approvals"));
+        assert!(models::get_simulation(&db.snapshot(), 2).is_none());
+
+        let response = restore().header("x-holodeck-approved-by", "troi,riker").json(&archive).reply(&api).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(!models::get_simulation(&db.snapshot(), 2).unwrap().safety.protocols);
+    }
+
+    #[tokio::test]
+    async fn try_conditional_get() {
+        use super::tenants::{NewTenant, Tenants};
+
+        let db = models::new_db();
+        let tenants = Tenants::from(db.clone());
+        assert!(tenants.create(NewTenant { name: "enterprise".to_string(), quota: None }).await);
+        let api = filters::routes(tenants, super::rooms::new_db(), policy(), Default::default(), Default::default());
+        for tenant in ["", "enterprise"] {
+            for sim in [models::Simulation::new(1, "The Big Goodbye"), models::Simulation::new(2, "Fair Haven")] {
+                let post = request().method("POST").path("/holodeck").json(&sim);
+                let post = if tenant.is_empty() { post } else { post.header("x-holodeck-tenant", tenant) };
+                post.reply(&api).await;
+            }
+        }
+
+        let response = request().path("/holodeck").reply(&api).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["vary"], "x-holodeck-tenant, accept");
+        let etag = response.headers()["etag"].to_str().unwrap().to_string();
+        // Another tenant at the same revision holds other simulations
+        let response = request().path("/holodeck").header("x-holodeck-tenant", "enterprise").header("if-none-match", &etag).reply(&api).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_ne!(response.headers()["etag"], etag.as_str());
+        // As does the same catalogue once reloaded, its revisions starting over
+        let reloaded: models::Db = db.snapshot().iter().cloned().collect::<models::Store>().into();
+        let api_reloaded = filters::routes(reloaded.into(), super::rooms::new_db(), policy(), Default::default(), Default::default());
+        let response = request().path("/holodeck").header("if-none-match", &etag).reply(&api_reloaded).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let last_modified = response.headers()["last-modified"].to_str().unwrap().to_string();
+        let response = request().path("/holodeck").header("if-none-match", &etag).reply(&api).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert!(response.body().is_empty());
+        assert_eq!(response.headers()["etag"], etag.as_str());
+        assert_eq!(response.headers()["vary"], "x-holodeck-tenant, accept");
+        let response = request().path("/holodeck").header("if-modified-since", &last_modified).reply(&api).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        // Another format is another representation
+        let response = request().path("/holodeck").header("accept", "application/cbor").header("if-none-match", &etag).reply(&api).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // Each simulation is validated on its own, so writes to others leave it fresh
+        let response = request().path("/holodeck/1").reply(&api).await;
+        let item_etag = response.headers()["etag"].to_str().unwrap().to_string();
+        assert_ne!(item_etag, etag);
+        request().method("PUT").path("/holodeck/2").json(&models::NewName::new("Fair Haven II")).reply(&api).await;
+        let response = request().path("/holodeck/1").header("if-none-match", &item_etag).reply(&api).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        let response = request().path("/holodeck").header("if-none-match", &etag).reply(&api).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_ne!(response.headers()["etag"], etag.as_str());
+
+        request().method("PUT").path("/holodeck/1").json(&models::NewName::new("The Long Hello")).reply(&api).await;
+        let response = request().path("/holodeck/1").header("if-none-match", &item_etag).reply(&api).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["last-modified"], super::conditional::http_date(db.snapshot().modified(1).unwrap()).as_str());
+    }
+
+    #[tokio::test]
+    async fn try_strict() {
+        use super::config::Compat;
+        use super::rooms;
+
+        let db = models::new_db();
+        let api = filters::routes(db.clone().into(), rooms::new_db(), policy(), Default::default(), Default::default());
+        let put = |path: &str| request().method("PUT").path(path).json(&models::NewName::new("The Big Goodbye"));
+
+        // Under `/v2`, updates do not create unless asked to
+        assert_eq!(put("/v2/holodeck/1").reply(&api).await.status(), StatusCode::NOT_FOUND);
+        assert!(db.lock().await.is_empty());
+        let response = put("/v2/holodeck/1").header("if-none-match", "*").reply(&api).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(put("/v2/holodeck/1").reply(&api).await.status(), StatusCode::OK);
+        assert_eq!(request().path("/v2/holodeck/1").reply(&api).await.status(), StatusCode::OK);
+
+        let delete = |path: &str| request().method("DELETE").path(path);
+        let response = delete("/v2/holodeck/1").reply(&api).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert!(response.body().is_empty());
+        let response = delete("/v2/holodeck/1").reply(&api).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.body(), "Simulation #1 does not exist.\n");
+
+        // Unprefixed routes keep upserting and answering 200, unless configured otherwise
+        assert_eq!(put("/holodeck/2").reply(&api).await.status(), StatusCode::CREATED);
+        assert_eq!(delete("/holodeck/2").reply(&api).await.status(), StatusCode::OK);
+        assert_eq!(delete("/holodeck/2").reply(&api).await.status(), StatusCode::OK);
+        let strict = Compat { strict: true, ..Compat::default() };
+        let api = filters::routes(db.into(), rooms::new_db(), policy(), Default::default(), strict);
+        assert_eq!(put("/holodeck/3").reply(&api).await.status(), StatusCode::NOT_FOUND);
+        assert_eq!(put("/holodeck/3").header("if-none-match", "*").reply(&api).await.status(), StatusCode::CREATED);
+        assert_eq!(delete("/holodeck/3").reply(&api).await.status(), StatusCode::NO_CONTENT);
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,1220 @@
+use serde_json::{json, Value};
+
+use super::models::SCHEMA_VERSION;
+use super::validation::{DESCRIPTION_MAX_LEN, NAME_MAX_LEN, TAGS_MAX, TAG_MAX_LEN, TENANT_MAX_LEN};
+
+/// OpenAPI 3 description of every route in `filters::routes`.
+/// Each operation names the filter serving it in `x-filter`. `filters::routes` is built from
+/// `filters::FILTERS` alone, so the tests catch undocumented routes by checking that table against it.
+pub fn spec() -> Value {
+    let mut spec = json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "Holodeck",
+            "description": "Catalogue of holodeck simulations, and the rooms they are booked into.",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": {
+            "/holodeck": {
+                "get": {
+                    "operationId": "listSims",
+                    "x-filter": "list_sims",
+                    "summary": "List every simulation",
+                    "parameters": [expand(), tag()],
+                    "responses": {
+                        "200": simulations(),
+                        "400": error("`expand` names something that cannot be expanded"),
+                        "406": error("`Accept` allows none of JSON, MessagePack or CBOR"),
+                    },
+                },
+                "post": {
+                    "operationId": "createSim",
+                    "x-filter": "post_sim",
+                    "summary": "Create a simulation",
+                    "parameters": actor(),
+                    "requestBody": encoded_body("Simulation"),
+                    "responses": {
+                        "201": stored("Simulation created"),
+                        "400": error("The body is not a valid `Simulation`, or the id is already taken"),
+                        "403": forbidden_or_quota(),
+                        "413": error("The body is larger than 16 KiB"),
+                        "415": error("The body is not JSON, MessagePack or CBOR"),
+                        "422": invalid(),
+                    },
+                },
+            },
+            "/holodeck/{id}": {
+                "parameters": [id()],
+                "get": {
+                    "operationId": "getSim",
+                    "x-filter": "get_sim",
+                    "summary": "Get the simulation with this id",
+                    "description": "With `HOLODECK_ITEM_ARRAYS` set, answers as it used to instead: with an array holding the simulation, or an empty one when there is none.",
+                    "parameters": [expand()],
+                    "responses": {
+                        "200": simulation(),
+                        "400": error("`expand` names something that cannot be expanded"),
+                        "404": {
+                            "description": "There is no such simulation",
+                            "content": { "application/json": { "schema": { "$ref": "#/components/schemas/ApiError" } } },
+                        },
+                        "406": error("`Accept` allows none of JSON, MessagePack or CBOR"),
+                    },
+                },
+                "put": {
+                    "operationId": "updateSim",
+                    "x-filter": "update_sim",
+                    "summary": "Rename a simulation and update its metadata, creating it when missing",
+                    "parameters": actor(),
+                    "requestBody": encoded_body("NewName"),
+                    "responses": {
+                        "200": stored("Simulation updated"),
+                        "201": stored("Simulation inserted"),
+                        "400": error("The body is not a valid `NewName`"),
+                        "404": error("There is no such simulation, `HOLODECK_STRICT` is set and `If-None-Match: *` was not sent"),
+                        "403": forbidden_or_quota(),
+                        "413": error("The body is larger than 16 KiB"),
+                        "415": error("The body is not JSON, MessagePack or CBOR"),
+                        "422": invalid(),
+                    },
+                },
+                "delete": {
+                    "operationId": "deleteSim",
+                    "x-filter": "delete_sim",
+                    "summary": "Delete a simulation, cancelling its room reservations",
+                    "parameters": actor(),
+                    "responses": {
+                        "200": text("Simulation deleted, or nothing to delete"),
+                        "204": { "description": "Simulation deleted, with `HOLODECK_STRICT` set" },
+                        "404": error("There is no such simulation and `HOLODECK_STRICT` is set"),
+                    },
+                },
+            },
+            "/holodeck/{id}/run": transition("run", "Start a loaded simulation"),
+            "/holodeck/{id}/pause": transition("pause", "Pause a running simulation"),
+            "/holodeck/{id}/resume": transition("resume", "Resume a paused simulation"),
+            "/holodeck/{id}/end": transition("end", "End a running or paused simulation"),
+            "/tags": {
+                "get": {
+                    "operationId": "listTags",
+                    "x-filter": "list_tags",
+                    "summary": "Every tag in use, with how many simulations carry it",
+                    "responses": {
+                        "200": {
+                            "description": "Simulation counts, by tag",
+                            "content": {
+                                "application/json": {
+                                    "schema": { "type": "object", "additionalProperties": { "type": "integer", "minimum": 1 } },
+                                },
+                            },
+                        },
+                    },
+                },
+            },
+            "/holodeck/{id}/characters": {
+                "parameters": [id()],
+                "get": {
+                    "operationId": "listCharacters",
+                    "x-filter": "list_characters",
+                    "summary": "List the simulation's characters",
+                    "responses": {
+                        "200": {
+                            "description": "Characters, in the order they were added",
+                            "content": {
+                                "application/json": {
+                                    "schema": { "type": "array", "items": { "$ref": "#/components/schemas/Character" } },
+                                },
+                            },
+                        },
+                        "404": error("There is no such simulation"),
+                    },
+                },
+                "post": {
+                    "operationId": "createCharacter",
+                    "x-filter": "post_character",
+                    "summary": "Add a character to the simulation",
+                    "parameters": actor(),
+                    "requestBody": body("NewCharacter"),
+                    "responses": {
+                        "201": character("The character, with the id the server gave it"),
+                        "400": error("The body is not a valid `NewCharacter`"),
+                        "404": error("There is no such simulation"),
+                        "413": error("The body is larger than 16 KiB"),
+                        "422": invalid(),
+                    },
+                },
+            },
+            "/holodeck/{id}/characters/{character}": {
+                "parameters": [
+                    id(),
+                    {
+                        "name": "character",
+                        "in": "path",
+                        "required": true,
+                        "schema": { "type": "integer", "format": "int64", "minimum": 1 },
+                    },
+                ],
+                "get": {
+                    "operationId": "getCharacter",
+                    "x-filter": "get_character",
+                    "summary": "Show one character",
+                    "responses": {
+                        "200": character("The character"),
+                        "404": error("There is no such simulation or character"),
+                    },
+                },
+                "put": {
+                    "operationId": "updateCharacter",
+                    "x-filter": "update_character",
+                    "summary": "Rename a character or change its role",
+                    "parameters": actor(),
+                    "requestBody": body("NewCharacter"),
+                    "responses": {
+                        "200": character("The character as updated"),
+                        "400": error("The body is not a valid `NewCharacter`"),
+                        "404": error("There is no such simulation or character"),
+                        "413": error("The body is larger than 16 KiB"),
+                        "422": invalid(),
+                    },
+                },
+                "delete": {
+                    "operationId": "deleteCharacter",
+                    "x-filter": "delete_character",
+                    "summary": "Remove a character",
+                    "parameters": actor(),
+                    "responses": {
+                        "200": text("Character deleted"),
+                        "404": error("There is no such simulation or character"),
+                    },
+                },
+            },
+            "/rooms": {
+                "get": {
+                    "operationId": "listRooms",
+                    "x-filter": "list_rooms",
+                    "summary": "List every room",
+                    "responses": {
+                        "200": {
+                            "description": "Rooms, by id",
+                            "content": {
+                                "application/json": {
+                                    "schema": { "type": "array", "items": { "$ref": "#/components/schemas/Room" } },
+                                },
+                            },
+                        },
+                    },
+                },
+                "post": {
+                    "operationId": "createRoom",
+                    "x-filter": "post_room",
+                    "summary": "Add a room",
+                    "requestBody": body("Room"),
+                    "responses": {
+                        "201": text("Room created"),
+                        "400": error("The body is not a valid `Room`, or the id is already taken"),
+                        "413": error("The body is larger than 16 KiB"),
+                        "422": invalid(),
+                    },
+                },
+            },
+            "/rooms/{id}": {
+                "parameters": [id()],
+                "delete": {
+                    "operationId": "deleteRoom",
+                    "x-filter": "delete_room",
+                    "summary": "Remove a room along with its reservations",
+                    "responses": {
+                        "200": text("Room deleted"),
+                        "404": error("There is no such room"),
+                    },
+                },
+            },
+            "/rooms/{id}/reservations": {
+                "parameters": [id()],
+                "get": {
+                    "operationId": "listReservations",
+                    "x-filter": "list_reservations",
+                    "summary": "The room's schedule",
+                    "description": "Reservations that have not ended yet, earliest first. Ended ones are released automatically.",
+                    "responses": {
+                        "200": {
+                            "description": "Reservations",
+                            "content": {
+                                "application/json": {
+                                    "schema": { "type": "array", "items": { "$ref": "#/components/schemas/Reservation" } },
+                                },
+                            },
+                        },
+                        "404": error("There is no such room"),
+                    },
+                },
+                "post": {
+                    "operationId": "createReservation",
+                    "x-filter": "post_reservation",
+                    "summary": "Book a simulation from the catalogue into the room",
+                    "requestBody": body("NewReservation"),
+                    "responses": {
+                        "201": {
+                            "description": "The reservation, with the id the server gave it",
+                            "content": { "application/json": { "schema": { "$ref": "#/components/schemas/Reservation" } } },
+                        },
+                        "400": error("The body is not a valid `NewReservation`"),
+                        "404": error("There is no such room"),
+                        "409": error("The window overlaps a reservation already made for the room"),
+                        "413": error("The body is larger than 16 KiB"),
+                        "422": invalid(),
+                    },
+                },
+            },
+            "/rooms/{id}/reservations/{reservation}": {
+                "parameters": [
+                    id(),
+                    {
+                        "name": "reservation",
+                        "in": "path",
+                        "required": true,
+                        "schema": { "type": "integer", "format": "int64", "minimum": 1 },
+                    },
+                ],
+                "delete": {
+                    "operationId": "deleteReservation",
+                    "x-filter": "delete_reservation",
+                    "summary": "Cancel a reservation",
+                    "responses": {
+                        "200": text("Reservation cancelled"),
+                        "404": error("The room has no such reservation"),
+                    },
+                },
+            },
+            "/openapi.json": {
+                "get": {
+                    "operationId": "openapi",
+                    "x-filter": "openapi",
+                    "summary": "This document",
+                    "responses": {
+                        "200": {
+                            "description": "OpenAPI 3 document",
+                            "content": { "application/json": { "schema": { "type": "object" } } },
+                        },
+                    },
+                },
+            },
+            "/docs": {
+                "get": {
+                    "operationId": "docs",
+                    "x-filter": "docs",
+                    "summary": "Interactive documentation",
+                    "description": "Loads Swagger UI from unpkg.com, so it needs a browser that can reach it.",
+                    "responses": {
+                        "200": {
+                            "description": "Swagger UI page",
+                            "content": { "text/html": { "schema": { "type": "string" } } },
+                        },
+                    },
+                },
+            },
+            "/graphql": {
+                "get": {
+                    "operationId": "graphiql",
+                    "x-filter": "graphql",
+                    "summary": "GraphiQL, to explore the GraphQL schema",
+                    "responses": {
+                        "200": {
+                            "description": "GraphiQL page",
+                            "content": { "text/html": { "schema": { "type": "string" } } },
+                        },
+                    },
+                },
+                "post": {
+                    "operationId": "graphql",
+                    "x-filter": "graphql",
+                    "summary": "Run a GraphQL query or mutation over the catalogue",
+                    "description": "Mutations are checked against the same validation and safety rules as the REST routes.",
+                    "parameters": actor(),
+                    "requestBody": {
+                        "required": true,
+                        "content": {
+                            "application/json": {
+                                "schema": {
+                                    "type": "object",
+                                    "required": ["query"],
+                                    "properties": {
+                                        "query": { "type": "string" },
+                                        "operationName": { "type": "string" },
+                                        "variables": { "type": "object" },
+                                    },
+                                },
+                            },
+                        },
+                    },
+                    "responses": {
+                        "200": {
+                            "description": "GraphQL response; failures are reported in `errors`",
+                            "content": { "application/json": { "schema": { "type": "object" } } },
+                        },
+                        "307": text("This server is a read-only follower and the request is a mutation; `Location` is the leader's `/graphql`"),
+                        "400": text("The body is not a GraphQL request"),
+                    },
+                },
+            },
+            "/graphql/ws": {
+                "get": {
+                    "operationId": "graphqlSubscriptions",
+                    "x-filter": "graphql",
+                    "summary": "GraphQL subscriptions over a WebSocket",
+                    "description": "Speaks both `graphql-transport-ws` and the older `graphql-ws`, as chosen by `Sec-WebSocket-Protocol`.",
+                    "responses": {
+                        "101": { "description": "Switching to the WebSocket" },
+                        "400": text("Not a WebSocket upgrade"),
+                    },
+                },
+            },
+        },
+        "components": {
+            "schemas": {
+                "Simulation": {
+                    "type": "object",
+                    "required": ["id", "name"],
+                    "properties": {
+                        "id": { "type": "integer", "format": "int64", "minimum": 1 },
+                        "name": name(),
+                        "description": description(),
+                        "genre": name(),
+                        "author": name(),
+                        "tags": tags(),
+                        "safety": { "$ref": "#/components/schemas/Safety" },
+                        "created_at": timestamp("Set by the server when the simulation is created"),
+                        "updated_at": timestamp("Set by the server on every change"),
+                        "state": {
+                            "type": "string",
+                            "enum": ["loaded", "running", "paused", "ended"],
+                            "readOnly": true,
+                            "description": "Changed only through the lifecycle endpoints",
+                        },
+                        "transitions": {
+                            "type": "array",
+                            "readOnly": true,
+                            "items": { "$ref": "#/components/schemas/Transition" },
+                        },
+                        "characters": {
+                            "type": "array",
+                            "readOnly": true,
+                            "description": "Listed only with `?expand=characters`; managed through `/holodeck/{id}/characters`",
+                            "items": { "$ref": "#/components/schemas/Character" },
+                        },
+                        "schema_version": {
+                            "type": "integer",
+                            "minimum": 1,
+                            "default": 1,
+                            "description": format!("Shape of the payload; the server always writes {}", SCHEMA_VERSION),
+                        },
+                    },
+                },
+                "NewName": {
+                    "type": "object",
+                    "required": ["name"],
+                    "description": "Metadata left out keeps its current value",
+                    "properties": {
+                        "name": name(),
+                        "description": description(),
+                        "genre": name(),
+                        "author": name(),
+                        "tags": tags(),
+                        "safety": { "$ref": "#/components/schemas/Safety" },
+                    },
+                },
+                "Safety": {
+                    "type": "object",
+                    "description": "Checked against the server's safety rules on every create, update, run and resume",
+                    "properties": {
+                        "protocols": { "type": "boolean", "default": true },
+                        "reason": description(),
+                    },
+                },
+                "Transition": {
+                    "type": "object",
+                    "required": ["action", "from", "to", "at"],
+                    "properties": {
+                        "action": { "type": "string", "enum": ["run", "pause", "resume", "end"] },
+                        "from": { "type": "string", "enum": ["loaded", "running", "paused", "ended"] },
+                        "to": { "type": "string", "enum": ["loaded", "running", "paused", "ended"] },
+                        "at": { "type": "string", "format": "date-time" },
+                    },
+                },
+                "Character": {
+                    "type": "object",
+                    "required": ["id", "name", "role"],
+                    "properties": {
+                        "id": { "type": "integer", "format": "int64", "readOnly": true },
+                        "name": name(),
+                        "role": name(),
+                    },
+                },
+                "NewCharacter": {
+                    "type": "object",
+                    "required": ["name", "role"],
+                    "properties": {
+                        "name": name(),
+                        "role": name(),
+                    },
+                },
+                "Room": {
+                    "type": "object",
+                    "required": ["id", "name"],
+                    "properties": {
+                        "id": { "type": "integer", "format": "int64", "minimum": 1 },
+                        "name": name(),
+                    },
+                },
+                "NewReservation": {
+                    "type": "object",
+                    "required": ["simulation", "start", "end"],
+                    "description": "Books `[start, end)`; `end` must be after `start` and in the future",
+                    "properties": {
+                        "simulation": { "type": "integer", "format": "int64", "description": "Id of a simulation in the catalogue" },
+                        "start": { "type": "string", "format": "date-time" },
+                        "end": { "type": "string", "format": "date-time" },
+                    },
+                },
+                "Reservation": {
+                    "type": "object",
+                    "required": ["id", "room", "simulation", "start", "end"],
+                    "properties": {
+                        "id": { "type": "integer", "format": "int64", "readOnly": true },
+                        "room": { "type": "integer", "format": "int64" },
+                        "simulation": { "type": "integer", "format": "int64" },
+                        "tenant": {
+                            "type": "string",
+                            "readOnly": true,
+                            "description": "Tenant whose catalogue `simulation` is in, as named by `x-holodeck-tenant`; left out for the default tenant",
+                        },
+                        "start": { "type": "string", "format": "date-time" },
+                        "end": { "type": "string", "format": "date-time" },
+                    },
+                },
+                "Violations": {
+                    "type": "object",
+                    "required": ["violations"],
+                    "properties": {
+                        "violations": {
+                            "type": "array",
+                            "items": {
+                                "type": "object",
+                                "required": ["rule", "message"],
+                                "properties": {
+                                    "rule": { "type": "string" },
+                                    "message": { "type": "string" },
+                                },
+                            },
+                        },
+                    },
+                },
+                "ValidationErrors": {
+                    "type": "object",
+                    "required": ["errors"],
+                    "properties": {
+                        "errors": { "type": "array", "items": { "$ref": "#/components/schemas/FieldError" } },
+                    },
+                },
+                "FieldError": {
+                    "type": "object",
+                    "required": ["field", "message"],
+                    "properties": {
+                        "field": { "type": "string" },
+                        "message": { "type": "string" },
+                    },
+                },
+                "NewTenant": {
+                    "type": "object",
+                    "required": ["name"],
+                    "properties": {
+                        "name": { "type": "string", "pattern": "^[a-z0-9-]+$", "minLength": 1, "maxLength": TENANT_MAX_LEN },
+                        "quota": { "type": "integer", "minimum": 0, "description": "Most simulations the tenant may hold; no limit when left out" },
+                    },
+                },
+                "Tenant": {
+                    "type": "object",
+                    "required": ["name", "simulations"],
+                    "properties": {
+                        "name": { "type": "string" },
+                        "quota": { "type": "integer", "minimum": 0 },
+                        "simulations": { "type": "integer", "minimum": 0, "description": "How many simulations it holds" },
+                    },
+                },
+                "Error": {
+                    "type": "string",
+                    "description": "Plain text explanation of what went wrong",
+                },
+                "ApiError": {
+                    "type": "object",
+                    "required": ["code", "message"],
+                    "properties": {
+                        "code": { "type": "integer", "description": "The HTTP status code" },
+                        "message": { "type": "string" },
+                    },
+                },
+            },
+        },
+    });
+
+    spec["paths"]
+        .as_object_mut()
+        .unwrap()
+        .extend(tenant_paths().as_object().unwrap().clone());
+    spec["paths"]["/audit"] = audit_path();
+    spec["components"]["schemas"]["AuditEntry"] = audit_entry();
+    spec["paths"]
+        .as_object_mut()
+        .unwrap()
+        .extend(backup_paths().as_object().unwrap().clone());
+    spec["components"]["schemas"]["Archive"] = archive();
+    spec["paths"]["/replication"] = replication_path();
+
+    // Only strict updates look at `If-None-Match`
+    let parameters = spec["paths"]["/holodeck/{id}"]["put"]["parameters"].as_array_mut().unwrap();
+    parameters.push(header("If-None-Match", "`*` to create the simulation when it does not exist; needed only with `HOLODECK_STRICT` set"));
+
+    // `/v2` serves the catalogue again, always with strict updates and deletes
+    let v2: Vec<(String, Value)> = spec["paths"]
+        .as_object()
+        .unwrap()
+        .iter()
+        .filter(|(path, _)| path.starts_with("/holodeck"))
+        .map(|(path, item)| (format!("/v2{}", path), strict(item)))
+        .collect();
+    spec["paths"].as_object_mut().unwrap().extend(v2);
+
+    // Pollers can ask for the catalogue only when it has changed since their last look
+    for item in spec["paths"].as_object_mut().unwrap().values_mut() {
+        let operation = match item.get_mut("get") {
+            Some(operation) if operation["x-filter"] == "list_sims" || operation["x-filter"] == "get_sim" => operation,
+            _ => continue,
+        };
+        let parameters = operation["parameters"].as_array_mut().unwrap();
+        parameters.push(header("If-None-Match", "`ETag` of the copy the client has; answered with `304 Not Modified` while it is current"));
+        parameters.push(header("If-Modified-Since", "`Last-Modified` of the copy the client has; ignored when `If-None-Match` is sent"));
+        let validators = json!({
+            "ETag": { "schema": { "type": "string" }, "description": "Weak validator; changes with every write to what is listed" },
+            "Last-Modified": { "schema": { "type": "string" }, "description": "When what is listed was last written" },
+        });
+        operation["responses"]["200"]["headers"] = validators.clone();
+        operation["responses"]["304"] = json!({ "description": "The client's copy is current", "headers": validators });
+    }
+
+    // Creates can be retried under an `Idempotency-Key`
+    for item in spec["paths"].as_object_mut().unwrap().values_mut() {
+        let operation = match item.get_mut("post") {
+            Some(operation) if IDEMPOTENT.contains(&operation["x-filter"].as_str().unwrap_or_default()) => operation,
+            _ => continue,
+        };
+        match operation["parameters"].as_array_mut() {
+            Some(parameters) => parameters.push(idempotency_key()),
+            None => operation["parameters"] = json!([idempotency_key()]),
+        }
+        let responses = &mut operation["responses"];
+        responses["409"] = match responses.get("409") {
+            Some(conflict) => also(conflict, "a request with the same `Idempotency-Key` is still running"),
+            None => error("A request with the same `Idempotency-Key` is still running"),
+        };
+        responses["422"] = match responses.get("422") {
+            Some(unprocessable) => also(unprocessable, "the `Idempotency-Key` was used for a different request"),
+            None => error("The `Idempotency-Key` was used for a different request"),
+        };
+    }
+
+    // The catalogue, and bookings from it, belong to the tenant named in `X-Holodeck-Tenant`
+    for (path, item) in spec["paths"].as_object_mut().unwrap() {
+        if !(path.starts_with("/holodeck") || path.starts_with("/v2/") || path == "/tags" || path == "/audit" || path == "/admin/backup" || path == "/admin/restore" || path == "/rooms/{id}/reservations" || path == "/graphql") {
+            continue;
+        }
+        for method in ["get", "post", "put", "delete"].iter() {
+            let operation = match item.get_mut(*method) {
+                Some(operation) if (*path != "/rooms/{id}/reservations" && *path != "/graphql") || *method == "post" => operation,
+                _ => continue,
+            };
+            match operation["parameters"].as_array_mut() {
+                Some(parameters) => parameters.push(tenant()),
+                None => operation["parameters"] = json!([tenant()]),
+            }
+            if operation["responses"].get("404").is_none() {
+                operation["responses"]["404"] = error("There is no such tenant");
+            }
+        }
+    }
+    spec
+}
+
+/// Swagger UI pointed at `/openapi.json`. The browser fetches Swagger UI itself from unpkg.com, so
+/// where that cannot be reached the page stays blank; `/openapi.json` is served all the same.
+pub const DOCS: &str = r##"<!DOCTYPE html>
+<html lang="en">
+<head>
+  <meta charset="utf-8">
+  <title>Holodeck API</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css">
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+  <script>
+    window.onload = () => { window.ui = SwaggerUIBundle({ url: "/openapi.json", dom_id: "#swagger-ui" }); };
+  </script>
+</body>
+</html>
+"##;
+
+// A catalogue path as `/v2` serves it
+fn strict(item: &Value) -> Value {
+    let mut item = item.clone();
+    for method in ["get", "post", "put", "delete"].iter() {
+        let operation = match item.get_mut(*method) {
+            Some(operation) => operation,
+            None => continue,
+        };
+        let id = format!("{}V2", operation["operationId"].as_str().unwrap_or_default());
+        operation["operationId"] = json!(id);
+        operation.as_object_mut().unwrap().remove("description");
+    }
+    if let Some(put) = item.get_mut("put") {
+        put["summary"] = json!("Rename a simulation and update its metadata; creating it takes `If-None-Match: *`");
+        for parameter in put["parameters"].as_array_mut().unwrap() {
+            if parameter["name"] == "If-None-Match" {
+                parameter["description"] = json!("`*` to create the simulation when it does not exist");
+            }
+        }
+        put["responses"]["201"] = stored("Simulation inserted, as `If-None-Match: *` was sent");
+        put["responses"]["404"] = error("There is no such simulation and `If-None-Match: *` was not sent");
+    }
+    if let Some(delete) = item.get_mut("delete") {
+        let responses = delete["responses"].as_object_mut().unwrap();
+        responses.remove("200");
+        responses.insert("204".to_string(), json!({ "description": "Simulation deleted" }));
+        responses.insert("404".to_string(), error("There is no such simulation"));
+    }
+    item
+}
+
+fn transition(action: &str, summary: &str) -> Value {
+    let mut item = json!({
+        "parameters": [id()],
+        "post": {
+            "operationId": format!("{}Sim", action),
+            "x-filter": "transition_sim",
+            "summary": summary,
+            "responses": {
+                "200": {
+                    "description": "The simulation in its new state",
+                    "content": { "application/json": { "schema": { "$ref": "#/components/schemas/Simulation" } } },
+                },
+                "404": error("There is no such simulation"),
+                "409": error("The simulation's current state does not allow this"),
+            },
+        },
+    });
+
+    // Setting a simulation going is checked against the safety rules
+    if action == "run" || action == "resume" {
+        item["post"]["parameters"] = actor();
+        item["post"]["responses"]["403"] = forbidden();
+    }
+    item
+}
+
+// Trusted as is; an authenticating proxy in front of the server is expected to set them
+fn actor() -> Value {
+    json!([
+        header("X-Holodeck-User", "Who is making the request"),
+        header("X-Holodeck-Roles", "Comma separated roles of the requester, e.g. `admin`"),
+        header("X-Holodeck-Approved-By", "Comma separated people who approved the request; the requester does not count"),
+    ])
+}
+
+// An optional header, as strings
+fn header(name: &str, description: &str) -> Value {
+    json!({
+        "name": name,
+        "in": "header",
+        "required": false,
+        "schema": { "type": "string" },
+        "description": description,
+    })
+}
+
+/// Split out of `spec` to keep `json!` under the recursion limit.
+fn tenant_paths() -> Value {
+    json!({
+    "/admin/tenants": {
+        "get": {
+            "operationId": "listTenants",
+            "x-filter": "list_tenants",
+            "summary": "List the tenants besides the default one",
+            "parameters": actor(),
+            "responses": {
+                "200": {
+                    "description": "Tenants, by name",
+                    "content": {
+                        "application/json": {
+                            "schema": { "type": "array", "items": { "$ref": "#/components/schemas/Tenant" } },
+                        },
+                    },
+                },
+                "403": error("The requester does not have the `admin` role"),
+            },
+        },
+        "post": {
+            "operationId": "createTenant",
+            "x-filter": "post_tenant",
+            "summary": "Create a tenant, with an empty catalogue of its own",
+            "parameters": actor(),
+            "requestBody": body("NewTenant"),
+            "responses": {
+                "201": text("Tenant created"),
+                "400": error("The body is not a valid `NewTenant`, or the name is already taken"),
+                "403": error("The requester does not have the `admin` role"),
+                "409": error("The server persists to `HOLODECK_DATA_FILE`, which only holds the default tenant"),
+                "422": invalid(),
+            },
+        },
+    },
+    "/admin/tenants/{tenant}": {
+        "parameters": [{
+            "name": "tenant",
+            "in": "path",
+            "required": true,
+            "schema": { "type": "string" },
+        }],
+        "delete": {
+            "operationId": "deleteTenant",
+            "x-filter": "delete_tenant",
+            "summary": "Delete a tenant and every simulation it holds",
+            "parameters": actor(),
+            "responses": {
+                "200": text("Tenant deleted"),
+                "403": error("The requester does not have the `admin` role"),
+                "404": error("There is no such tenant"),
+            },
+        },
+    },
+    })
+}
+
+/// Filters whose POSTs honour `Idempotency-Key`.
+const IDEMPOTENT: [&str; 5] = ["post_sim", "post_character", "post_room", "post_reservation", "post_tenant"];
+
+fn idempotency_key() -> Value {
+    json!({
+        "name": "Idempotency-Key",
+        "in": "header",
+        "required": false,
+        "schema": { "type": "string" },
+        "description": "Retries with the same key and body get the first response again, without the request running twice",
+    })
+}
+
+// Another reason for a response the operation already documents; it may answer in plain text then
+fn also(response: &Value, reason: &str) -> Value {
+    let mut response = response.clone();
+    let description = format!("{}, or {}", response["description"].as_str().unwrap_or_default(), reason);
+    response["description"] = json!(description);
+    response["content"]["text/plain"] = json!({ "schema": { "$ref": "#/components/schemas/Error" } });
+    response
+}
+
+fn audit_path() -> Value {
+    let query = |name: &str, schema: Value, description: &str| {
+        json!({ "name": name, "in": "query", "required": false, "schema": schema, "description": description })
+    };
+    let entries = json!({ "type": "array", "items": { "$ref": "#/components/schemas/AuditEntry" } });
+    let mut parameters = vec![
+        query("actor", json!({ "type": "string" }), "Only changes made by this `X-Holodeck-User`"),
+        query("since", json!({ "type": "string", "format": "date-time" }), "Only changes made at or after this instant"),
+        query("id", json!({ "type": "integer", "format": "int64", "minimum": 0 }), "Only changes to this simulation"),
+    ];
+    parameters.extend(actor().as_array().unwrap().iter().cloned());
+    json!({
+        "get": {
+            "operationId": "listAudit",
+            "x-filter": "list_audit",
+            "summary": "Every change made to the simulations, oldest first; the latest 10000 when they are not all kept in memory",
+            "parameters": parameters,
+            "responses": {
+                "200": {
+                    "description": "Matching audit entries",
+                    "content": {
+                        "application/json": { "schema": entries },
+                        "application/x-ndjson": {
+                            "schema": { "$ref": "#/components/schemas/AuditEntry" },
+                            "description": "One entry per line, when `Accept` asks for it",
+                        },
+                    },
+                },
+                "400": error("The query string is invalid"),
+                "403": error("The requester does not have the `admin` role"),
+            },
+        },
+    })
+}
+
+fn audit_entry() -> Value {
+    let simulation = |description: &str| json!({ "allOf": [{ "$ref": "#/components/schemas/Simulation" }], "nullable": true, "description": description });
+    json!({
+        "type": "object",
+        "required": ["at", "operation", "id"],
+        "properties": {
+            "at": { "type": "string", "format": "date-time" },
+            "actor": { "type": "string", "nullable": true, "description": "`X-Holodeck-User` of the request that made the change" },
+            "operation": {
+                "type": "string",
+                "enum": ["create", "update", "delete", "run", "pause", "resume", "end", "create-character", "update-character", "delete-character", "restore"],
+            },
+            "id": { "type": "integer", "format": "int64", "description": "The simulation changed" },
+            "before": simulation("The simulation before the change; null for creates"),
+            "after": simulation("The simulation after the change; null for deletes"),
+        },
+    })
+}
+
+fn backup_paths() -> Value {
+    json!({
+    "/admin/backup": {
+        "post": {
+            "operationId": "backup",
+            "x-filter": "backup",
+            "summary": "Archive the tenant's catalogue as it is at this instant",
+            "parameters": actor(),
+            "responses": {
+                "200": {
+                    "description": "The archive, to be kept and handed back to `/admin/restore`",
+                    "content": { "application/json": { "schema": { "$ref": "#/components/schemas/Archive" } } },
+                },
+                "403": error("The requester does not have the `admin` role"),
+            },
+        },
+    },
+    "/admin/restore": {
+        "post": {
+            "operationId": "restore",
+            "x-filter": "restore",
+            "summary": "Replace the tenant's catalogue with an archived one, all at once",
+            "parameters": actor(),
+            "requestBody": body("Archive"),
+            "responses": {
+                "200": text("Catalogue restored"),
+                "400": error("The body is not an `Archive`"),
+                "403": {
+                    "description": "A simulation in the archive breaks the server's safety rules, with every unmet requirement listed; or the requester does not have the `admin` role, or the archive holds more simulations than the tenant's quota",
+                    "content": {
+                        "application/json": { "schema": { "$ref": "#/components/schemas/Violations" } },
+                        "text/plain": { "schema": { "$ref": "#/components/schemas/Error" } },
+                    },
+                },
+                "422": error("The archive is of an unsupported format or schema, does not match its checksum, or holds invalid simulations"),
+            },
+        },
+    },
+    })
+}
+
+fn replication_path() -> Value {
+    json!({
+        "get": {
+            "operationId": "replicate",
+            "x-filter": "replication",
+            "summary": "Stream the default tenant's catalogue to a follower: a snapshot, then every write after it",
+            "parameters": [header("Authorization", "`Bearer` and the leader's `HOLODECK_REPLICATION_SECRET`")],
+            "responses": {
+                "200": {
+                    "description": "Never ends. One JSON message per line, each of `type` `snapshot` (always first, with every simulation), `change` (one per revision, in order) or `heartbeat` (every second), and each with the leader's `revision` and when it was sent (`at`)",
+                    "content": { "application/x-ndjson": { "schema": { "type": "object" } } },
+                },
+                "403": error("The secret is missing or wrong, or the server has none and does not serve followers"),
+            },
+        },
+    })
+}
+
+fn archive() -> Value {
+    json!({
+        "type": "object",
+        "required": ["format_version", "schema_version", "created_at", "count", "checksum", "simulations"],
+        "properties": {
+            "format_version": { "type": "integer", "format": "int32", "description": "Layout of the archive" },
+            "schema_version": { "type": "integer", "format": "int32", "description": "Schema version of the server that made it" },
+            "created_at": { "type": "string", "format": "date-time" },
+            "count": { "type": "integer", "format": "int64", "minimum": 0 },
+            "checksum": { "type": "string", "description": "Hex SHA-256 of `simulations` as compact JSON" },
+            "simulations": { "type": "array", "items": { "$ref": "#/components/schemas/Simulation" } },
+        },
+    })
+}
+
+fn tenant() -> Value {
+    json!({
+        "name": "X-Holodeck-Tenant",
+        "in": "header",
+        "required": false,
+        "schema": { "type": "string" },
+        "description": "Tenant whose catalogue the request is for; the default tenant when left out",
+    })
+}
+
+fn id() -> Value {
+    json!({
+        "name": "id",
+        "in": "path",
+        "required": true,
+        "schema": { "type": "integer", "format": "int64", "minimum": 0 },
+    })
+}
+
+fn expand() -> Value {
+    json!({
+        "name": "expand",
+        "in": "query",
+        "required": false,
+        "schema": { "type": "string", "enum": ["characters"] },
+        "description": "Include the simulations' characters",
+    })
+}
+
+fn tag() -> Value {
+    json!({
+        "name": "tag",
+        "in": "query",
+        "required": false,
+        "style": "form",
+        "explode": true,
+        "schema": { "type": "array", "items": { "type": "string" } },
+        "description": "Only simulations carrying this tag; `!tag` for those without it. Repeat to combine, e.g. `?tag=noir&tag=!combat`",
+    })
+}
+
+fn name() -> Value {
+    json!({
+        "type": "string",
+        "minLength": 1,
+        "maxLength": NAME_MAX_LEN,
+        "description": "Letters, digits, spaces and ASCII punctuation, without leading or trailing whitespace",
+    })
+}
+
+fn description() -> Value {
+    json!({
+        "type": "string",
+        "maxLength": DESCRIPTION_MAX_LEN,
+        "description": "Free text; line breaks are allowed, other control characters are not",
+    })
+}
+
+fn tags() -> Value {
+    json!({
+        "type": "array",
+        "uniqueItems": true,
+        "maxItems": TAGS_MAX,
+        "items": { "type": "string", "pattern": format!("^[a-z0-9-]{{1,{}}}$", TAG_MAX_LEN) },
+    })
+}
+
+fn timestamp(description: &str) -> Value {
+    json!({
+        "type": "string",
+        "format": "date-time",
+        "readOnly": true,
+        "description": description,
+    })
+}
+
+fn body(schema: &str) -> Value {
+    json!({
+        "required": true,
+        "content": { "application/json": { "schema": { "$ref": format!("#/components/schemas/{}", schema) } } },
+    })
+}
+
+// Sent with a matching `Content-Type`; bodies without one are read as JSON
+fn encoded_body(schema: &str) -> Value {
+    let schema = json!({ "schema": { "$ref": format!("#/components/schemas/{}", schema) } });
+    json!({
+        "required": true,
+        "content": {
+            "application/json": schema,
+            "application/msgpack": schema,
+            "application/cbor": schema,
+        },
+    })
+}
+
+// Picked with `Accept`; JSON unless asked otherwise
+fn simulation() -> Value {
+    let schema = json!({ "schema": { "$ref": "#/components/schemas/Simulation" } });
+    json!({
+        "description": "The simulation",
+        "content": {
+            "application/json": schema,
+            "application/msgpack": schema,
+            "application/cbor": schema,
+        },
+    })
+}
+
+fn simulations() -> Value {
+    let schema = json!({ "schema": { "type": "array", "items": { "$ref": "#/components/schemas/Simulation" } } });
+    json!({
+        "description": "Simulations",
+        "content": {
+            "application/json": schema,
+            "application/msgpack": schema,
+            "application/cbor": schema,
+        },
+    })
+}
+
+fn character(description: &str) -> Value {
+    json!({
+        "description": description,
+        "content": { "application/json": { "schema": { "$ref": "#/components/schemas/Character" } } },
+    })
+}
+
+fn text(description: &str) -> Value {
+    json!({
+        "description": description,
+        "content": { "text/plain": { "schema": { "type": "string" } } },
+    })
+}
+
+// What a write answers: the simulation as stored with `Accept: application/json`, a line of text otherwise
+fn stored(description: &str) -> Value {
+    json!({
+        "description": format!("{}; the simulation as stored with `Accept: application/json`, a line of text otherwise", description),
+        "content": {
+            "text/plain": { "schema": { "type": "string" } },
+            "application/json": { "schema": { "$ref": "#/components/schemas/Simulation" } },
+        },
+    })
+}
+
+fn invalid() -> Value {
+    json!({
+        "description": "The payload failed validation; every problem is listed",
+        "content": { "application/json": { "schema": { "$ref": "#/components/schemas/ValidationErrors" } } },
+    })
+}
+
+fn forbidden() -> Value {
+    json!({
+        "description": "The request breaks the server's safety rules; every unmet requirement is listed",
+        "content": { "application/json": { "schema": { "$ref": "#/components/schemas/Violations" } } },
+    })
+}
+
+fn forbidden_or_quota() -> Value {
+    json!({
+        "description": "The request breaks the server's safety rules, with every unmet requirement listed, or the tenant's quota is reached",
+        "content": {
+            "application/json": { "schema": { "$ref": "#/components/schemas/Violations" } },
+            "text/plain": { "schema": { "$ref": "#/components/schemas/Error" } },
+        },
+    })
+}
+
+fn error(description: &str) -> Value {
+    json!({
+        "description": description,
+        "content": { "text/plain": { "schema": { "$ref": "#/components/schemas/Error" } } },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::spec;
+    use crate::libs::characters::NewCharacter;
+    use crate::libs::{filters, models, rooms, safety};
+    use std::sync::Arc;
+    use std::collections::BTreeSet;
+    use warp::http::StatusCode;
+    use warp::test::request;
+
+    const METHODS: [&str; 4] = ["get", "post", "put", "delete"];
+
+    // The filters `filters::routes` is built from, and all it is built from
+    fn filter_names() -> BTreeSet<String> {
+        filters::FILTERS.iter().map(|(name, _, _)| name.to_string()).collect()
+    }
+
+    #[test]
+    fn try_every_filter_documented() {
+        let spec = spec();
+        let documented: BTreeSet<String> = spec["paths"]
+            .as_object()
+            .unwrap()
+            .values()
+            .flat_map(|path| METHODS.iter().filter_map(move |method| path.get(*method)))
+            .map(|operation| operation["x-filter"].as_str().unwrap().to_string())
+            .collect();
+
+        let filters = filter_names();
+        assert!(filters.contains("list_sims"));
+        for name in &filters {
+            assert!(documented.contains(name), "filter `{}` is missing from the OpenAPI spec", name);
+        }
+        for name in &documented {
+            assert!(filters.contains(name), "the OpenAPI spec documents `{}`, which is not a filter", name);
+        }
+    }
+
+    #[tokio::test]
+    async fn try_every_path_routed() {
+        for (path, item) in spec()["paths"].as_object().unwrap() {
+            let path = path
+                .replace("{id}", "1")
+                .replace("{character}", "1")
+                .replace("{reservation}", "1")
+                .replace("{tenant}", "1");
+            for method in METHODS.iter().filter(|method| item.get(**method).is_some()) {
+                // Handlers answer 404 for unknown simulations, characters, rooms and reservations,
+                // so there has to be one of each to tell them from missing routes
+                let db = models::new_db();
+                let mut sim = models::Simulation::new(1, "The Big Goodbye");
+                sim.add_character(NewCharacter::new("Dixon Hill", "Detective"));
+                db.lock().await.insert(sim);
+                let rooms = rooms::new_db();
+                {
+                    let mut rooms = rooms.lock().await;
+                    rooms.insert(rooms::Room { id: 1, name: "Holodeck 1".to_string() }).unwrap();
+                    let start = chrono::Utc::now() + chrono::Duration::hours(1);
+                    let booking = rooms::NewReservation { simulation: 1, start, end: start + chrono::Duration::hours(1) };
+                    rooms.book(1, None, booking).unwrap();
+                }
+                let api = filters::routes(db.into(), rooms, Arc::new(safety::Policy::default()), Default::default(), Default::default());
+
+                // The replication stream never ends, so its reply cannot be read to the end
+                if path == "/replication" {
+                    assert!(request().path(&path).filter(&api).await.is_ok(), "{} {}", method, path);
+                    continue;
+                }
+                let response = request()
+                    .method(&method.to_uppercase())
+                    .path(&path)
+                    .reply(&api)
+                    .await;
+
+                assert_ne!(response.status(), StatusCode::NOT_FOUND, "{} {}", method, path);
+                assert_ne!(response.status(), StatusCode::METHOD_NOT_ALLOWED, "{} {}", method, path);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn try_serve_spec() {
+        let api = filters::routes(models::new_db().into(), rooms::new_db(), Arc::new(safety::Policy::default()), Default::default(), Default::default());
+
+        let response = request().method("GET").path("/openapi.json").reply(&api).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let served: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(served, spec());
+
+        let response = request().method("GET").path("/docs").reply(&api).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,393 @@
+use chrono::{DateTime, Utc};
+use futures_util::stream;
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::time::Interval;
+use warp::http::header::{HeaderValue, AUTHORIZATION, CONTENT_TYPE, LOCATION};
+use warp::http::{Method, StatusCode, Uri};
+use warp::hyper::body::{Body, Bytes, HttpBody};
+use warp::hyper::{Client, Request};
+use warp::path::FullPath;
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+use super::audit::NDJSON;
+use super::changes::Change;
+use super::models::{Db, Simulation, Store};
+
+/// How often the leader tells an idle follower it is still there.
+pub const HEARTBEAT: Duration = Duration::from_secs(1);
+
+/// How long a follower waits before reconnecting to its leader.
+pub const RETRY: Duration = Duration::from_secs(1);
+
+/// One line of the stream served at `GET /replication`. Revisions are the leader's `Store::revision`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Message {
+    /// Always first: every simulation as of `revision`.
+    Snapshot { revision: u64, at: DateTime<Utc>, simulations: Vec<Simulation> },
+    /// The write that made `revision`; they come one revision at a time.
+    Change { revision: u64, at: DateTime<Utc>, change: Box<Change> },
+    Heartbeat { revision: u64, at: DateTime<Utc> },
+}
+
+/// The leader's side: a snapshot of `store` followed by every write after it. Must be called
+/// with the store locked for writing, so no write can fall between the snapshot and the subscription.
+pub fn feed(store: &Store) -> Response {
+    let feed = Feed {
+        pending: Some(Message::Snapshot {
+            revision: store.revision(),
+            at: Utc::now(),
+            simulations: store.iter().cloned().collect(),
+        }),
+        changes: store.subscribe(),
+        revision: store.revision(),
+        heartbeat: tokio::time::interval(HEARTBEAT),
+    };
+    let lines = stream::unfold(feed, |mut feed| async move {
+        let message = feed.next().await?;
+        Some((Ok::<_, Infallible>(line(&message)), feed))
+    });
+    let mut response = Response::new(Body::wrap_stream(lines));
+    response.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static(NDJSON));
+    response
+}
+
+struct Feed {
+    pending: Option<Message>,
+    changes: broadcast::Receiver<Change>,
+    revision: u64,
+    heartbeat: Interval,
+}
+
+impl Feed {
+    async fn next(&mut self) -> Option<Message> {
+        if let Some(message) = self.pending.take() {
+            return Some(message);
+        }
+        tokio::select! {
+            received = self.changes.recv() => {
+                // A follower that fell too far behind has missed changes, so it has to start over from a snapshot
+                let change = received.ok()?;
+                self.revision += 1;
+                Some(Message::Change { revision: self.revision, at: Utc::now(), change: Box::new(change) })
+            }
+            _ = self.heartbeat.tick() => Some(Message::Heartbeat { revision: self.revision, at: Utc::now() }),
+        }
+    }
+}
+
+/// Whether an `Authorization` header carries `Bearer <secret>`. Nothing is authorized when there is no secret.
+pub fn authorized(secret: Option<&str>, authorization: Option<&str>) -> bool {
+    match (secret, authorization.and_then(|value| value.strip_prefix("Bearer "))) {
+        (Some(secret), Some(presented)) => same(secret.as_bytes(), presented.as_bytes()),
+        _ => false,
+    }
+}
+
+// Compares every byte whatever the first difference, so the time taken says nothing about the secret
+fn same(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+fn line(message: &Message) -> Bytes {
+    // Messages only hold strings, numbers and timestamps, which always serialize
+    let mut line = serde_json::to_vec(message).unwrap_or_default();
+    line.push(b'\n');
+    line.into()
+}
+
+/// How far a follower is behind its leader, as served at `GET /replication/status`.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Status {
+    pub leader: String,
+    /// Whether the replication stream is open right now.
+    pub connected: bool,
+    /// Last leader revision applied here.
+    pub revision: u64,
+    /// Latest revision the leader has reported.
+    pub leader_revision: u64,
+    /// `leader_revision - revision`.
+    pub behind: u64,
+    /// How long after the leader sent it the last change was applied, in milliseconds.
+    pub lag_ms: Option<i64>,
+    /// When the leader was last heard from, heartbeats included.
+    pub last_heard: Option<DateTime<Utc>>,
+}
+
+/// Keeps a read-only copy of a leader's default catalogue, by tailing its `GET /replication`.
+#[derive(Clone, Debug)]
+pub struct Follower {
+    leader: String,
+    secret: String,
+    status: Arc<Mutex<Status>>,
+}
+
+impl Follower {
+    /// `leader` is the base URL of the leader, e.g. `http://holodeck-1:3030`, and `secret` its
+    /// `HOLODECK_REPLICATION_SECRET`.
+    pub fn new(leader: &Uri, secret: String) -> Follower {
+        let leader = leader.to_string().trim_end_matches('/').to_string();
+        Follower {
+            status: Arc::new(Mutex::new(Status {
+                leader: leader.clone(),
+                ..Status::default()
+            })),
+            leader,
+            secret,
+        }
+    }
+
+    /// The leader's base URL, without a trailing slash.
+    pub fn leader(&self) -> &str {
+        &self.leader
+    }
+
+    pub fn status(&self) -> Status {
+        self.status.lock().unwrap_or_else(PoisonError::into_inner).clone()
+    }
+
+    fn update(&self, change: impl FnOnce(&mut Status)) {
+        let mut status = self.status.lock().unwrap_or_else(PoisonError::into_inner);
+        change(&mut status);
+        status.behind = status.leader_revision.saturating_sub(status.revision);
+    }
+
+    /// Follows the leader into `db` for as long as the server runs, reconnecting whenever the stream
+    /// breaks and starting over from a fresh snapshot each time.
+    pub async fn run(self, db: Db) {
+        loop {
+            match self.follow(&db).await {
+                Ok(()) => eprintln!("The leader at {} closed the replication stream", self.leader),
+                Err(e) => eprintln!("Replication from {} failed: {}", self.leader, e),
+            }
+            self.update(|status| status.connected = false);
+            tokio::time::sleep(RETRY).await;
+        }
+    }
+
+    async fn follow(&self, db: &Db) -> Result<(), String> {
+        let request = Request::get(format!("{}/replication", self.leader))
+            .header(AUTHORIZATION, format!("Bearer {}", self.secret))
+            .body(Body::empty())
+            .map_err(|e| e.to_string())?;
+        let response = Client::new().request(request).await.map_err(|e| e.to_string())?;
+        if !response.status().is_success() {
+            return Err(format!("the leader answered {}", response.status()));
+        }
+        self.update(|status| status.connected = true);
+
+        let mut body = response.into_body();
+        let mut buffer = Vec::new();
+        while let Some(chunk) = body.data().await {
+            buffer.extend_from_slice(&chunk.map_err(|e| e.to_string())?);
+            while let Some(end) = buffer.iter().position(|b| *b == b'\n') {
+                let line: Vec<u8> = buffer.drain(..=end).collect();
+                let message = serde_json::from_slice(&line).map_err(|e| format!("unreadable message: {}", e))?;
+                self.apply(db, message).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn apply(&self, db: &Db, message: Message) -> Result<(), String> {
+        let now = Utc::now();
+        match message {
+            Message::Snapshot { revision, simulations, .. } => {
+                db.lock().await.restore(simulations);
+                self.update(|status| {
+                    status.revision = revision;
+                    status.leader_revision = revision;
+                });
+            }
+            Message::Change { revision, at, change } => {
+                let expected = self.status().revision + 1;
+                if revision != expected {
+                    return Err(format!("expected revision {} but got {}", expected, revision));
+                }
+                {
+                    let mut map = db.lock().await;
+                    match change.simulation {
+                        Some(sim) => {
+                            map.replace(sim);
+                        }
+                        None => {
+                            if let Some(old) = map.get(change.id).cloned() {
+                                map.remove(&old);
+                            }
+                        }
+                    }
+                }
+                self.update(|status| {
+                    status.revision = revision;
+                    status.leader_revision = status.leader_revision.max(revision);
+                    status.lag_ms = Some((now - at).num_milliseconds().max(0));
+                });
+            }
+            Message::Heartbeat { revision, .. } => self.update(|status| status.leader_revision = revision),
+        }
+        self.update(|status| status.last_heard = Some(now));
+        Ok(())
+    }
+
+    /// `GET /replication/status`, and a `307 Temporary Redirect` to the leader for every request that
+    /// could write. `POST /graphql` is left to `handlers::handle_graphql`, which only sends mutations
+    /// on, since most GraphQL requests are queries. Everything else falls through to the routes this is put in front of.
+    pub fn routes(&self) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+        let follower = self.clone();
+        let status = warp::path!("replication" / "status")
+            .and(warp::get())
+            .map(move || warp::reply::json(&follower.status()).into_response());
+
+        let leader = self.leader.clone();
+        let writes = warp::method()
+            .and(warp::path::full())
+            .and(warp::query::raw().or(warp::any().map(String::new)).unify())
+            .and_then(move |method: Method, path: FullPath, query: String| {
+                let leader = leader.clone();
+                async move {
+                    let read = method == Method::GET || method == Method::HEAD || method == Method::OPTIONS;
+                    if read || path.as_str() == "/graphql" {
+                        return Err(warp::reject());
+                    }
+                    let location = match query.as_str() {
+                        "" => format!("{}{}", leader, path.as_str()),
+                        query => format!("{}{}?{}", leader, path.as_str(), query),
+                    };
+                    let reply = warp::reply::with_status(
+                        format!("This server is a read-only follower; send writes to {}.\n", leader),
+                        StatusCode::TEMPORARY_REDIRECT,
+                    );
+                    Ok::<_, Rejection>(warp::reply::with_header(reply, LOCATION, location).into_response())
+                }
+            });
+
+        status.or(writes).unify()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libs::{filters, models, rooms, safety};
+    use std::net::SocketAddr;
+    use warp::hyper::{body, Request};
+
+    async fn serve<F>(routes: F) -> SocketAddr
+    where
+        F: Filter + Clone + Send + Sync + 'static,
+        F::Extract: Reply,
+    {
+        let (addr, server) = warp::serve(routes).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        addr
+    }
+
+    async fn send(method: &str, addr: SocketAddr, path: &str, body: String) -> Response {
+        let request = Request::builder()
+            .method(method)
+            .uri(format!("http://{}{}", addr, path))
+            .header("content-type", "application/json")
+            .body(Body::from(body))
+            .unwrap();
+        Client::new().request(request).await.unwrap()
+    }
+
+    async fn listed(addr: SocketAddr) -> Vec<String> {
+        let response = send("GET", addr, "/holodeck", String::new()).await;
+        let sims: models::Simulations = serde_json::from_slice(&body::to_bytes(response.into_body()).await.unwrap()).unwrap();
+        let mut names: Vec<String> = sims.iter().map(|sim| sim.name.clone()).collect();
+        names.sort();
+        names
+    }
+
+    // Replication is asynchronous, so the follower gets a moment to catch up
+    async fn eventually(addr: SocketAddr, names: &[&str]) {
+        for _ in 0..50 {
+            if listed(addr).await == names {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        assert_eq!(listed(addr).await, names);
+    }
+
+    fn routes(db: Db, secret: Option<&str>, leader: Option<String>) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        filters::routes_for(filters::Context {
+            tenants: db.into(),
+            rooms: rooms::new_db(),
+            policy: Arc::new(safety::Policy::default()),
+            idempotency: Default::default(),
+            compat: Default::default(),
+            replication_secret: secret.map(str::to_string),
+            leader,
+        })
+    }
+
+    #[test]
+    fn try_authorized() {
+        assert!(authorized(Some("warp core"), Some("Bearer warp core")));
+        assert!(!authorized(Some("warp core"), Some("Bearer warp")));
+        assert!(!authorized(Some("warp core"), Some("warp core")));
+        assert!(!authorized(Some("warp core"), None));
+        // Without a secret nobody gets the stream
+        assert!(!authorized(None, Some("Bearer ")));
+    }
+
+    #[tokio::test]
+    async fn try_follow() {
+        let leader_db: Db = vec![Simulation::new(1, "The Big Goodbye")].into_iter().collect::<Store>().into();
+        let leader = serve(routes(leader_db, Some("warp core"), None)).await;
+        // Role headers are set by clients themselves, so they do not open the stream
+        let request = Request::get(format!("http://{}/replication", leader))
+            .header("x-holodeck-user", "q")
+            .header("x-holodeck-roles", "admin")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(Client::new().request(request).await.unwrap().status(), StatusCode::FORBIDDEN);
+        let closed = serve(routes(models::new_db(), None, None)).await;
+        assert_eq!(send("GET", closed, "/replication", String::new()).await.status(), StatusCode::FORBIDDEN);
+
+        let follower_db = models::new_db();
+        let follower = Follower::new(&format!("http://{}/", leader).parse().unwrap(), "warp core".to_string());
+        tokio::spawn(follower.clone().run(follower_db.clone()));
+        let replica = serve(follower.routes().or(routes(follower_db, None, Some(follower.leader().to_string())))).await;
+
+        eventually(replica, &["The Big Goodbye"]).await;
+        let sim = serde_json::to_string(&Simulation::new(2, "Fair Haven")).unwrap();
+        assert_eq!(send("POST", leader, "/holodeck", sim.clone()).await.status(), StatusCode::CREATED);
+        send("PUT", leader, "/holodeck/1", r#"{"name": "The Long Hello"}"#.to_string()).await;
+        eventually(replica, &["Fair Haven", "The Long Hello"]).await;
+        send("DELETE", leader, "/holodeck/2", String::new()).await;
+        eventually(replica, &["The Long Hello"]).await;
+
+        // Writes are sent on to the leader, method, body and all
+        let response = send("POST", replica, "/holodeck?tag=noir", sim).await;
+        assert_eq!(response.status(), StatusCode::TEMPORARY_REDIRECT);
+        assert_eq!(response.headers()[LOCATION], format!("http://{}/holodeck?tag=noir", leader));
+        assert_eq!(listed(replica).await, ["The Long Hello"]);
+
+        // GraphQL queries are answered here, and only mutations go to the leader
+        let query = r#"{"query": "{ simulations { items { name } } }"}"#.to_string();
+        let response = send("POST", replica, "/graphql", query).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = body::to_bytes(response.into_body()).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("The Long Hello"));
+        let mutation = r#"{"query": "query Q { simulations { total } } mutation M { deleteSimulation(id: 1) { id } }", "operationName": "M"}"#;
+        let response = send("POST", replica, "/graphql", mutation.to_string()).await;
+        assert_eq!(response.status(), StatusCode::TEMPORARY_REDIRECT);
+        assert_eq!(response.headers()[LOCATION], format!("http://{}/graphql", leader));
+        assert_eq!(listed(replica).await, ["The Long Hello"]);
+
+        let response = send("GET", replica, "/replication/status", String::new()).await;
+        let status: Status = serde_json::from_slice(&body::to_bytes(response.into_body()).await.unwrap()).unwrap();
+        assert!(status.connected);
+        assert_eq!(status.leader, format!("http://{}", leader));
+        assert_eq!((status.revision, status.behind), (4, 0));
+        assert!(status.lag_ms.is_some() && status.last_heard.is_some());
+    }
+}
@@ -0,0 +1,210 @@
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+pub use holodeck_models::rooms::{NewReservation, Reservation, Room};
+
+#[derive(Debug, PartialEq)]
+pub enum BookingError {
+    NoSuchRoom,
+    /// The window overlaps this reservation.
+    Conflict(Reservation),
+}
+
+#[derive(Debug, Default)]
+pub struct Rooms {
+    rooms: BTreeMap<u64, Room>,
+    reservations: BTreeMap<u64, Reservation>,
+    last_reservation: u64,
+}
+
+pub type Db = Arc<Mutex<Rooms>>;
+
+pub fn new_db() -> Db {
+    Arc::new(Mutex::new(Rooms::default()))
+}
+
+impl Rooms {
+    pub fn rooms(&self) -> Vec<Room> {
+        self.rooms.values().cloned().collect()
+    }
+
+    pub fn get(&self, id: u64) -> Option<&Room> {
+        self.rooms.get(&id)
+    }
+
+    /// Hands the room back when the id is already taken.
+    pub fn insert(&mut self, room: Room) -> Result<(), Room> {
+        if let Some(existing) = self.rooms.get(&room.id) {
+            return Err(existing.clone());
+        }
+        self.rooms.insert(room.id, room);
+        Ok(())
+    }
+
+    /// Removes the room along with its reservations.
+    pub fn remove(&mut self, id: u64) -> Option<Room> {
+        let room = self.rooms.remove(&id)?;
+        self.reservations.retain(|_, reservation| reservation.room != id);
+        Some(room)
+    }
+
+    /// Books `new.simulation` from `tenant`'s catalogue, the default one for `None`.
+    pub fn book(&mut self, room: u64, tenant: Option<&str>, new: NewReservation) -> Result<Reservation, BookingError> {
+        if !self.rooms.contains_key(&room) {
+            return Err(BookingError::NoSuchRoom);
+        }
+        if let Some(taken) = self
+            .reservations
+            .values()
+            .find(|r| r.room == room && r.overlaps(new.start, new.end))
+        {
+            return Err(BookingError::Conflict(taken.clone()));
+        }
+
+        self.last_reservation += 1;
+        let reservation = Reservation {
+            id: self.last_reservation,
+            room,
+            simulation: new.simulation,
+            tenant: tenant.map(str::to_string),
+            start: new.start,
+            end: new.end,
+        };
+        self.reservations.insert(reservation.id, reservation.clone());
+        Ok(reservation)
+    }
+
+    /// The room's reservations that have not ended by `now`, earliest first.
+    pub fn schedule(&self, room: u64, now: DateTime<Utc>) -> Vec<Reservation> {
+        let mut schedule: Vec<Reservation> = self
+            .reservations
+            .values()
+            .filter(|r| r.room == room && r.end > now)
+            .cloned()
+            .collect();
+        schedule.sort_by_key(|r| r.start);
+        schedule
+    }
+
+    /// How many reservations are held, in every room, ended or not.
+    pub fn reservation_count(&self) -> usize {
+        self.reservations.len()
+    }
+
+    pub fn cancel(&mut self, room: u64, id: u64) -> Option<Reservation> {
+        match self.reservations.get(&id) {
+            Some(reservation) if reservation.room == room => self.reservations.remove(&id),
+            _ => None,
+        }
+    }
+
+    /// Cancels the reservations of `tenant`'s simulations that are `gone`, returning how many.
+    pub fn cancel_simulations(&mut self, tenant: Option<&str>, gone: impl Fn(u64) -> bool) -> usize {
+        let before = self.reservations.len();
+        self.reservations
+            .retain(|_, reservation| reservation.tenant.as_deref() != tenant || !gone(reservation.simulation));
+        before - self.reservations.len()
+    }
+
+    /// Drops every reservation that has ended by `now`, returning how many.
+    pub fn release_expired(&mut self, now: DateTime<Utc>) -> usize {
+        let before = self.reservations.len();
+        self.reservations.retain(|_, reservation| reservation.end > now);
+        before - self.reservations.len()
+    }
+}
+
+/// Releases expired reservations every `period`, for as long as the server runs.
+pub async fn release_expired_every(db: Db, period: Duration) {
+    let mut interval = tokio::time::interval(period);
+    loop {
+        interval.tick().await;
+        let released = db.lock().await.release_expired(Utc::now());
+        if released > 0 {
+            println!("Released {} expired reservation(s).", released);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(hour: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2375, 3, 1, hour, 0, 0).unwrap()
+    }
+
+    fn booking(simulation: u64, start: u32, end: u32) -> NewReservation {
+        NewReservation {
+            simulation,
+            start: at(start),
+            end: at(end),
+        }
+    }
+
+    fn rooms() -> Rooms {
+        let mut rooms = Rooms::default();
+        for id in 1..=2 {
+            let name = format!("Holodeck {}", id);
+            rooms.insert(Room { id, name }).unwrap();
+        }
+        rooms
+    }
+
+    #[test]
+    fn try_book() {
+        let mut rooms = rooms();
+
+        let first = rooms.book(1, None, booking(1, 10, 12)).unwrap();
+        assert_eq!(first.id, 1);
+        // Back to back is fine, as is the same window in another room
+        rooms.book(1, None, booking(2, 12, 13)).unwrap();
+        rooms.book(2, None, booking(2, 10, 12)).unwrap();
+
+        assert_eq!(rooms.book(1, None, booking(3, 11, 14)), Err(BookingError::Conflict(first.clone())));
+        assert_eq!(rooms.book(1, None, booking(3, 9, 11)), Err(BookingError::Conflict(first)));
+        assert_eq!(rooms.book(3, None, booking(3, 9, 11)), Err(BookingError::NoSuchRoom));
+
+        let schedule: Vec<u64> = rooms.schedule(1, at(0)).iter().map(|r| r.simulation).collect();
+        assert_eq!(schedule, [1, 2]);
+    }
+
+    #[test]
+    fn try_release() {
+        let mut rooms = rooms();
+        rooms.book(1, None, booking(1, 10, 12)).unwrap();
+        let later = rooms.book(1, None, booking(2, 13, 14)).unwrap();
+
+        assert_eq!(rooms.schedule(1, at(12)), std::slice::from_ref(&later));
+        assert_eq!(rooms.release_expired(at(12)), 1);
+        assert_eq!(rooms.release_expired(at(12)), 0);
+
+        assert_eq!(rooms.cancel(2, later.id), None);
+        assert_eq!(rooms.cancel(1, later.id), Some(later));
+        assert!(rooms.schedule(1, at(0)).is_empty());
+
+        rooms.book(2, None, booking(1, 10, 12)).unwrap();
+        assert!(rooms.remove(2).is_some());
+        assert!(rooms.schedule(2, at(0)).is_empty());
+    }
+
+    #[test]
+    fn try_cancel_simulations() {
+        let mut rooms = rooms();
+        rooms.book(1, None, booking(1, 10, 12)).unwrap();
+        let enterprise = rooms.book(1, Some("enterprise"), booking(1, 12, 14)).unwrap();
+        let other = rooms.book(2, None, booking(2, 10, 12)).unwrap();
+
+        // Same id, another tenant's simulation: its booking stays
+        assert_eq!(rooms.cancel_simulations(None, |id| id == 1), 1);
+        assert_eq!(rooms.schedule(1, at(0)), [enterprise]);
+        assert_eq!(rooms.schedule(2, at(0)), [other]);
+
+        assert_eq!(rooms.cancel_simulations(Some("enterprise"), |_| true), 1);
+        assert!(rooms.schedule(1, at(0)).is_empty());
+    }
+}
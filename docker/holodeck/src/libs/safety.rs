@@ -0,0 +1,264 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use super::lifecycle::Action;
+use super::models::Simulation;
+
+pub use holodeck_models::safety::{Safety, Violation, Violations};
+
+/// Role of those who may manage the server itself, and by default turn the safety protocols off.
+pub const ADMIN_ROLE: &str = "admin";
+
+/// Writes the rules are checked on.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Event {
+    Create,
+    Update,
+    /// Both `run` and `resume`: anything that sets the simulation going.
+    Run,
+}
+
+pub const EVENTS: [Event; 3] = [Event::Create, Event::Update, Event::Run];
+
+impl Event {
+    /// The event a lifecycle action triggers, if any.
+    pub fn for_action(action: Action) -> Option<Event> {
+        match action {
+            Action::Run | Action::Resume => Some(Event::Run),
+            Action::Pause | Action::End => None,
+        }
+    }
+}
+
+/// Who is making the request, as told by the `X-Holodeck-User`, `X-Holodeck-Roles` and
+/// `X-Holodeck-Approved-By` headers. The server trusts them, so they must be set by a proxy that authenticates users.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Actor {
+    pub user: Option<String>,
+    pub roles: BTreeSet<String>,
+    pub approved_by: BTreeSet<String>,
+}
+
+impl Actor {
+    /// `roles` and `approved_by` are comma separated lists.
+    pub fn from_headers(user: Option<String>, roles: Option<String>, approved_by: Option<String>) -> Actor {
+        Actor {
+            user: user.map(|user| user.trim().to_string()).filter(|user| !user.is_empty()),
+            roles: list(roles),
+            approved_by: list(approved_by),
+        }
+    }
+
+    /// Approvals from people other than the requester; approving your own request does not count.
+    pub fn approvals(&self) -> usize {
+        self.approved_by
+            .iter()
+            .filter(|approver| Some(*approver) != self.user.as_ref())
+            .count()
+    }
+}
+
+fn list(header: Option<String>) -> BTreeSet<String> {
+    header
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Which simulations a rule applies to; conditions left out match everything.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Condition {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub protocols: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+}
+
+impl Condition {
+    fn matches(&self, sim: &Simulation) -> bool {
+        self.protocols.is_none_or(|protocols| sim.safety.protocols == protocols)
+            && self.tag.as_ref().is_none_or(|tag| sim.tags.contains(tag))
+    }
+}
+
+/// What the request must bring when a rule applies; each unmet part is reported on its own.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Requirement {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role: Option<String>,
+    #[serde(default)]
+    pub approvals: usize,
+    #[serde(default)]
+    pub reason: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Rule {
+    pub name: String,
+    #[serde(default = "every_event")]
+    pub on: BTreeSet<Event>,
+    #[serde(default)]
+    pub when: Condition,
+    #[serde(default)]
+    pub require: Requirement,
+}
+
+fn every_event() -> BTreeSet<Event> {
+    EVENTS.iter().copied().collect()
+}
+
+impl Rule {
+    fn check(&self, event: Event, sim: &Simulation, actor: &Actor, violations: &mut Vec<Violation>) {
+        if !self.on.contains(&event) || !self.when.matches(sim) {
+            return;
+        }
+
+        let require = &self.require;
+        if let Some(role) = &require.role {
+            if !actor.roles.contains(role) {
+                violations.push(self.violation(format!("requires the {} role", role)));
+            }
+        }
+        if actor.approvals() < require.approvals {
+            violations.push(self.violation(format!(
+                "requires {} approvals from people other than the requester, got {}",
+                require.approvals,
+                actor.approvals()
+            )));
+        }
+        if require.reason && sim.safety.reason.is_none() {
+            violations.push(self.violation("requires a reason in the safety settings".to_string()));
+        }
+    }
+
+    fn violation(&self, message: String) -> Violation {
+        Violation {
+            rule: self.name.clone(),
+            message,
+        }
+    }
+}
+
+/// The rules every write is checked against, as a JSON array of `Rule`s.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(transparent)]
+pub struct Policy {
+    pub rules: Vec<Rule>,
+}
+
+// Turning the protocols off takes an admin and two other people signing off
+impl Default for Policy {
+    fn default() -> Self {
+        Policy {
+            rules: vec![Rule {
+                name: "safety-off".to_string(),
+                on: every_event(),
+                when: Condition {
+                    protocols: Some(false),
+                    tag: None,
+                },
+                require: Requirement {
+                    role: Some(ADMIN_ROLE.to_string()),
+                    approvals: 2,
+                    reason: false,
+                },
+            }],
+        }
+    }
+}
+
+impl Policy {
+    pub fn load(path: &Path) -> io::Result<Policy> {
+        Ok(serde_json::from_slice(&fs::read(path)?)?)
+    }
+
+    /// Every rule is checked, so the answer lists all that was missing at once.
+    pub fn check(&self, event: Event, sim: &Simulation, actor: &Actor) -> Result<(), Violations> {
+        let mut violations = Vec::new();
+        for rule in &self.rules {
+            rule.check(event, sim, actor, &mut violations);
+        }
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(Violations { violations })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unsafe_sim() -> Simulation {
+        let mut sim = Simulation::new(1, "The Big Goodbye");
+        sim.safety.protocols = false;
+        sim
+    }
+
+    fn actor(user: &str, roles: &str, approved_by: &str) -> Actor {
+        Actor::from_headers(Some(user.to_string()), Some(roles.to_string()), Some(approved_by.to_string()))
+    }
+
+    fn messages(result: Result<(), Violations>) -> Vec<String> {
+        match result {
+            Ok(()) => Vec::new(),
+            Err(v) => v.violations.into_iter().map(|v| format!("{}: {}", v.rule, v.message)).collect(),
+        }
+    }
+
+    #[test]
+    fn try_default_policy() {
+        let policy = Policy::default();
+        let sim = unsafe_sim();
+
+        assert!(policy.check(Event::Create, &Simulation::new(1, "Safe"), &Actor::default()).is_ok());
+        assert!(policy.check(Event::Run, &sim, &actor("picard", "admin", "riker, troi")).is_ok());
+
+        // Approving your own request does not count
+        assert_eq!(
+            messages(policy.check(Event::Update, &sim, &actor("riker", "officer", "riker,troi"))),
+            [
+                "safety-off: requires the admin role",
+                "safety-off: requires 2 approvals from people other than the requester, got 1",
+            ]
+        );
+        assert_eq!(Event::for_action(Action::Resume), Some(Event::Run));
+        assert_eq!(Event::for_action(Action::Pause), None);
+    }
+
+    #[test]
+    fn try_configured_policy() {
+        let policy: Policy = serde_json::from_str(
+            r#"[
+                {"name": "combat-reviewed", "on": ["run"], "when": {"tag": "combat"}, "require": {"reason": true}},
+                {"name": "everything-needs-an-operator", "require": {"role": "operator"}}
+            ]"#,
+        )
+        .unwrap();
+
+        let mut sim = Simulation::new(1, "Klingon Calisthenics");
+        sim.tags.insert("combat".to_string());
+        let operator = actor("worf", "operator", "");
+
+        assert!(policy.check(Event::Create, &sim, &operator).is_ok());
+        assert_eq!(
+            messages(policy.check(Event::Run, &sim, &operator)),
+            ["combat-reviewed: requires a reason in the safety settings"]
+        );
+        sim.safety.reason = Some("Lieutenant Worf's morning routine".to_string());
+        assert!(policy.check(Event::Run, &sim, &operator).is_ok());
+
+        assert_eq!(
+            messages(policy.check(Event::Update, &sim, &Actor::default())),
+            ["everything-needs-an-operator: requires the operator role"]
+        );
+    }
+}
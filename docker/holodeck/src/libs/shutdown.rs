@@ -0,0 +1,202 @@
+use std::convert::Infallible;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use warp::reply::Response;
+use warp::Filter;
+
+/// Counts the requests currently being handled, so shutdown knows what it is waiting for.
+#[derive(Clone, Default)]
+pub struct Tracker {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    active: AtomicUsize,
+    completed: AtomicUsize,
+}
+
+/// Held for as long as a request is in flight.
+pub struct Guard(Tracker);
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        let inner = &self.0.inner;
+        inner.completed.fetch_add(1, Ordering::SeqCst);
+        inner.active.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Tracker {
+    pub fn new() -> Tracker {
+        Tracker::default()
+    }
+
+    pub fn enter(&self) -> Guard {
+        self.inner.active.fetch_add(1, Ordering::SeqCst);
+        Guard(self.clone())
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.inner.active.load(Ordering::SeqCst)
+    }
+
+    pub fn completed(&self) -> usize {
+        self.inner.completed.load(Ordering::SeqCst)
+    }
+}
+
+/// Extracts a `Guard` for the request; hand it to `hold` with the reply so it lives until the body is sent.
+pub fn track(tracker: Tracker) -> impl Filter<Extract = (Guard,), Error = Infallible> + Clone {
+    warp::any().map(move || tracker.enter())
+}
+
+/// `response` with `guard` in its extensions, so the request counts as in flight until the server has
+/// taken the reply to write out, or dropped it because the client went away. The body is left alone;
+/// the server's own graceful shutdown waits for it to be sent.
+pub fn hold(guard: Guard, mut response: Response) -> Response {
+    response.extensions_mut().insert(guard);
+    response
+}
+
+/// Resolves on SIGTERM (what `docker stop` sends) or Ctrl-C.
+pub async fn signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut term = signal(SignalKind::terminate()).expect("failed to install SIGTERM handler");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {},
+            _ = term.recv() => {},
+        }
+    }
+
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await.expect("failed to install Ctrl-C handler");
+}
+
+/// Waits for a server that was told to stop to finish its in-flight requests, giving up after `deadline`.
+/// A server still running then is aborted, and gone by the time this returns, so nothing it would
+/// still accept can slip in behind a flush. Returns `true` when everything was drained in time.
+pub async fn drain<T>(mut server: JoinHandle<T>, tracker: &Tracker, deadline: Duration) -> bool {
+    let in_flight = tracker.in_flight();
+    let completed = tracker.completed();
+    println!("Draining {} request(s) in flight, waiting up to {:?}", in_flight, deadline);
+
+    let drained = tokio::time::timeout(deadline, &mut server).await.is_ok();
+    if !drained {
+        server.abort();
+        let _ = server.await;
+    }
+    let finished = tracker.completed() - completed;
+    if drained {
+        println!("Drained {} request(s).", finished);
+    } else {
+        println!(
+            "Deadline reached: {} request(s) drained, {} abandoned.",
+            finished,
+            tracker.in_flight()
+        );
+    }
+    drained
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+    use tokio::sync::oneshot;
+
+    #[test]
+    fn try_tracker() {
+        let tracker = Tracker::new();
+        let first = tracker.enter();
+        let second = tracker.enter();
+        assert_eq!(tracker.in_flight(), 2);
+
+        drop(first);
+        assert_eq!(tracker.in_flight(), 1);
+        assert_eq!(tracker.completed(), 1);
+
+        drop(second);
+        assert_eq!(tracker.in_flight(), 0);
+        assert_eq!(tracker.completed(), 2);
+    }
+
+    #[tokio::test]
+    async fn try_graceful_shutdown() {
+        let tracker = Tracker::new();
+        let slow = warp::path("slow")
+            .and(track(tracker.clone()))
+            .and_then(|guard: Guard| async move {
+                let _guard = guard;
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                Ok::<_, Infallible>("done")
+            });
+
+        let (tx, rx) = oneshot::channel::<()>();
+        let (addr, server) = warp::serve(slow)
+            .bind_with_graceful_shutdown(([127, 0, 0, 1], 0), async { rx.await.ok(); });
+        let server = tokio::spawn(server);
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET /slow HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        while tracker.in_flight() == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        tx.send(()).unwrap();
+        assert!(drain(server, &tracker, Duration::from_secs(5)).await);
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with("done"));
+
+        assert!(TcpStream::connect(addr).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn try_hold() {
+        let tracker = Tracker::new();
+        let response = hold(tracker.enter(), Response::new("done".into()));
+        assert_eq!(tracker.in_flight(), 1);
+        drop(response);
+        assert_eq!(tracker.in_flight(), 0);
+
+        // Over a real connection, the request is done by the time the reply has arrived
+        let routes = track(tracker.clone()).and(warp::any().map(|| Response::new("done".into()))).map(hold);
+        let (addr, server) = warp::serve(routes).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.ends_with("done"));
+        assert_eq!((tracker.in_flight(), tracker.completed()), (0, 2));
+    }
+
+    #[tokio::test]
+    async fn try_drain_deadline() {
+        let tracker = Tracker::new();
+        let stuck = tracker.enter();
+        // Stands in for a server that never finishes, and releases its request only when aborted
+        let server = tokio::spawn(async move {
+            let _stuck = stuck;
+            std::future::pending::<()>().await
+        });
+
+        assert!(!drain(server, &tracker, Duration::from_millis(50)).await);
+        assert_eq!(tracker.in_flight(), 0);
+    }
+}
@@ -0,0 +1,119 @@
+use im::{OrdMap, OrdSet};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Inverted index from each tag to the ids of the simulations carrying it. Persistent maps, like
+/// `models::Simulations`, so copying it for a write is cheap.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TagIndex {
+    ids: OrdMap<String, OrdSet<u64>>,
+}
+
+impl TagIndex {
+    pub fn add<'a>(&mut self, id: u64, tags: impl IntoIterator<Item = &'a String>) {
+        for tag in tags {
+            self.ids.entry(tag.clone()).or_default().insert(id);
+        }
+    }
+
+    // Tags nobody carries any more are dropped, so they stop showing up in `counts`
+    pub fn remove<'a>(&mut self, id: u64, tags: impl IntoIterator<Item = &'a String>) {
+        for tag in tags {
+            if let Some(ids) = self.ids.get_mut(tag) {
+                ids.remove(&id);
+                if ids.is_empty() {
+                    self.ids.remove(tag);
+                }
+            }
+        }
+    }
+
+    /// How many simulations carry each tag.
+    pub fn counts(&self) -> BTreeMap<String, usize> {
+        self.ids.iter().map(|(tag, ids)| (tag.clone(), ids.len())).collect()
+    }
+
+    /// Ids matching `filter`, or `None` when it has no tags to include and so matches everything it does not exclude.
+    pub fn matching(&self, filter: &TagFilter) -> Option<BTreeSet<u64>> {
+        let empty = OrdSet::new();
+        let ids = |tag: &String| self.ids.get(tag).unwrap_or(&empty);
+
+        // Start from the rarest tag so the intersection stays small
+        let mut include: Vec<&OrdSet<u64>> = filter.include.iter().map(ids).collect();
+        include.sort_by_key(|ids| ids.len());
+        let (first, rest) = include.split_first()?;
+
+        let mut matching: BTreeSet<u64> = first.iter().copied().collect();
+        for ids in rest {
+            matching.retain(|id| ids.contains(id));
+        }
+        for tag in &filter.exclude {
+            for id in ids(tag) {
+                matching.remove(id);
+            }
+        }
+        Some(matching)
+    }
+
+    /// Ids carrying any of `tags`.
+    pub fn any(&self, tags: &BTreeSet<String>) -> BTreeSet<u64> {
+        tags.iter().filter_map(|tag| self.ids.get(tag)).flatten().copied().collect()
+    }
+}
+
+/// `?tag=noir&tag=!combat`: simulations carrying every tag in `include` and none in `exclude`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TagFilter {
+    pub include: BTreeSet<String>,
+    pub exclude: BTreeSet<String>,
+}
+
+impl TagFilter {
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    /// Adds one `tag` parameter; a leading `!` excludes the tag.
+    pub fn push(&mut self, value: &str) {
+        match value.strip_prefix('!') {
+            Some(tag) => self.exclude.insert(tag.to_string()),
+            None => self.include.insert(value.to_string()),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(tags: &[&str]) -> BTreeSet<String> {
+        tags.iter().map(|tag| tag.to_string()).collect()
+    }
+
+    fn filter(values: &[&str]) -> TagFilter {
+        let mut filter = TagFilter::default();
+        for value in values {
+            filter.push(value);
+        }
+        filter
+    }
+
+    #[test]
+    fn try_index() {
+        let mut index = TagIndex::default();
+        index.add(1, &tags(&["noir", "detective"]));
+        index.add(2, &tags(&["noir", "combat"]));
+        index.add(3, &tags(&["combat"]));
+
+        let matching = |values: &[&str]| index.matching(&filter(values)).map(|ids| ids.into_iter().collect::<Vec<_>>());
+        assert_eq!(matching(&["noir"]), Some(vec![1, 2]));
+        assert_eq!(matching(&["noir", "!combat"]), Some(vec![1]));
+        assert_eq!(matching(&["noir", "combat"]), Some(vec![2]));
+        assert_eq!(matching(&["western"]), Some(vec![]));
+        assert_eq!(matching(&["!combat"]), None);
+        assert_eq!(index.any(&tags(&["combat", "western"])), [2, 3].iter().copied().collect());
+
+        index.remove(2, &tags(&["noir", "combat"]));
+        index.remove(3, &tags(&["combat"]));
+        assert_eq!(index.counts().into_iter().collect::<Vec<_>>(), [("detective".to_string(), 1), ("noir".to_string(), 1)]);
+    }
+}
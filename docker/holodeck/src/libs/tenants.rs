@@ -0,0 +1,124 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+use super::models::{self, Db};
+
+pub use holodeck_models::tenants::{NewTenant, Tenant};
+
+/// Header naming the tenant a request is for; requests without it go to the default tenant.
+pub const HEADER: &str = "x-holodeck-tenant";
+
+/// Every tenant's catalogue, each with its own id space. The default tenant is the one the server
+/// has always had; it cannot be deleted and is the only one persisted to `HOLODECK_DATA_FILE`.
+#[derive(Clone, Debug)]
+pub struct Tenants {
+    default: Db,
+    named: Arc<RwLock<BTreeMap<String, Db>>>,
+    single: bool,
+}
+
+impl From<Db> for Tenants {
+    fn from(default: Db) -> Self {
+        Tenants {
+            default,
+            named: Arc::default(),
+            single: false,
+        }
+    }
+}
+
+impl Tenants {
+    /// Only the default tenant, for a server that flushes it to `HOLODECK_DATA_FILE`: other tenants
+    /// would be lost on exit, so none can be created.
+    pub fn single(default: Db) -> Tenants {
+        Tenants {
+            single: true,
+            ..Tenants::from(default)
+        }
+    }
+
+    pub fn is_single(&self) -> bool {
+        self.single
+    }
+
+    pub fn default_db(&self) -> &Db {
+        &self.default
+    }
+
+    /// The catalogue of tenant `name`, or the default one for `None`.
+    pub async fn get(&self, name: Option<&str>) -> Option<Db> {
+        match name {
+            Some(name) => self.named.read().await.get(name).cloned(),
+            None => Some(self.default.clone()),
+        }
+    }
+
+    /// Answers `false`, leaving the tenant alone, when the name is taken.
+    pub async fn create(&self, new: NewTenant) -> bool {
+        let mut named = self.named.write().await;
+        if named.contains_key(&new.name) {
+            return false;
+        }
+        let db = models::new_db();
+        db.lock().await.set_quota(new.quota);
+        named.insert(new.name, db);
+        true
+    }
+
+    /// Takes the tenant's simulations with it.
+    pub async fn remove(&self, name: &str) -> Option<Db> {
+        self.named.write().await.remove(name)
+    }
+
+    /// The named tenants, by name.
+    pub async fn list(&self) -> Vec<Tenant> {
+        let named = self.named.read().await;
+        let mut tenants = Vec::with_capacity(named.len());
+        for (name, db) in named.iter() {
+            let store = db.snapshot();
+            tenants.push(Tenant {
+                name: name.clone(),
+                quota: store.quota(),
+                simulations: store.len(),
+            });
+        }
+        tenants
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn try_tenants() {
+        let tenants = Tenants::from(models::new_db());
+        let enterprise = NewTenant {
+            name: "enterprise".to_string(),
+            quota: Some(2),
+        };
+        assert!(tenants.create(enterprise.clone()).await);
+        assert!(!tenants.create(enterprise).await);
+
+        // Same id, different catalogues
+        let db = tenants.get(Some("enterprise")).await.unwrap();
+        db.lock().await.insert(models::Simulation::new(1, "Fair Haven"));
+        tenants.get(None).await.unwrap().lock().await.insert(models::Simulation::new(1, "The Big Goodbye"));
+        assert_eq!(models::get_simulation(&*db.lock().await, 1).unwrap().name, "Fair Haven");
+        assert_eq!(
+            tenants.list().await,
+            [Tenant {
+                name: "enterprise".to_string(),
+                quota: Some(2),
+                simulations: 1,
+            }]
+        );
+
+        assert!(tenants.get(Some("voyager")).await.is_none());
+        assert!(Tenants::single(models::new_db()).is_single());
+        assert!(tenants.remove("enterprise").await.is_some());
+        assert!(tenants.get(Some("enterprise")).await.is_none());
+        assert_eq!(tenants.default_db().lock().await.len(), 1);
+    }
+}
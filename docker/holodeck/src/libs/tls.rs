@@ -0,0 +1,383 @@
+use futures_util::stream::{self, Stream};
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_rustls::rustls::server::AllowAnyAuthenticatedClient;
+use tokio_rustls::rustls::{Certificate, PrivateKey, RootCertStore, ServerConfig};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+use tonic::transport::{self, Identity, Server, ServerTlsConfig};
+
+use super::config::TlsFiles;
+
+/// How long a client gets to finish its TLS handshake before the connection is dropped.
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Builds the rustls configuration from the PEM files, requiring client certificates when a CA is given.
+pub fn server_config(files: &TlsFiles) -> io::Result<Arc<ServerConfig>> {
+    let certs = read_certs(&files.cert)?;
+    let key = read_key(&files.key)?;
+    let builder = ServerConfig::builder().with_safe_defaults();
+
+    let builder = match &files.client_ca {
+        Some(path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in read_certs(path)? {
+                roots.add(&cert).map_err(invalid)?;
+            }
+            builder.with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots).boxed())
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    let config = builder.with_single_cert(certs, key).map_err(invalid)?;
+    Ok(Arc::new(config))
+}
+
+/// A gRPC server presenting the same certificate, and asking for clients certificates from the same CA, as
+/// HTTPS. Tonic reads the files itself, so `reload_on_hangup` does not reach it.
+pub fn grpc_server(files: &TlsFiles) -> io::Result<Server> {
+    let identity = Identity::from_pem(fs::read(&files.cert)?, fs::read(&files.key)?);
+    let mut config = ServerTlsConfig::new().identity(identity);
+    if let Some(path) = &files.client_ca {
+        config = config.client_ca_root(transport::Certificate::from_pem(fs::read(path)?));
+    }
+    Server::builder().tls_config(config).map_err(invalid)
+}
+
+fn read_certs(path: &Path) -> io::Result<Vec<Certificate>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let certs = rustls_pemfile::certs(&mut reader)?;
+    if certs.is_empty() {
+        return Err(invalid(format!("no certificate found in {}", path.display())));
+    }
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+fn read_key(path: &Path) -> io::Result<PrivateKey> {
+    use rustls_pemfile::Item;
+
+    let mut reader = BufReader::new(File::open(path)?);
+    while let Some(item) = rustls_pemfile::read_one(&mut reader)? {
+        if let Item::PKCS8Key(key) | Item::RSAKey(key) | Item::ECKey(key) = item {
+            return Ok(PrivateKey(key));
+        }
+    }
+    Err(invalid(format!("no private key found in {}", path.display())))
+}
+
+fn invalid<E>(error: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+/// Hands new connections the most recently loaded certificates.
+/// Connections that are already up keep the configuration they were accepted with, so reloading never drops them.
+#[derive(Clone)]
+pub struct Acceptor {
+    files: TlsFiles,
+    current: Arc<RwLock<TlsAcceptor>>,
+}
+
+impl Acceptor {
+    pub fn new(files: TlsFiles) -> io::Result<Acceptor> {
+        let acceptor = TlsAcceptor::from(server_config(&files)?);
+        Ok(Acceptor {
+            files,
+            current: Arc::new(RwLock::new(acceptor)),
+        })
+    }
+
+    // On error the previous certificates stay in use
+    pub fn reload(&self) -> io::Result<()> {
+        let acceptor = TlsAcceptor::from(server_config(&self.files)?);
+        *self.current.write().unwrap() = acceptor;
+        Ok(())
+    }
+
+    fn current(&self) -> TlsAcceptor {
+        self.current.read().unwrap().clone()
+    }
+}
+
+/// Turns a TCP listener into a stream of established TLS connections for `warp::serve(..).serve_incoming*`.
+/// Handshakes run in their own tasks so a slow client cannot hold up the others; failed ones, and those
+/// not done within `handshake_timeout`, are logged and dropped. The listener is closed once the stream is
+/// dropped, which is what graceful shutdown does.
+pub fn incoming(
+    listener: TcpListener,
+    acceptor: Acceptor,
+    handshake_timeout: Duration,
+) -> impl Stream<Item = io::Result<TlsStream<TcpStream>>> {
+    let (tx, rx) = mpsc::channel(64);
+
+    tokio::spawn(async move {
+        loop {
+            let (tcp, peer) = tokio::select! {
+                _ = tx.closed() => break,
+                accepted = listener.accept() => match accepted {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        eprintln!("Failed to accept connection: {}", e);
+                        continue;
+                    }
+                },
+            };
+
+            let tls = acceptor.current();
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                match tokio::time::timeout(handshake_timeout, tls.accept(tcp)).await {
+                    Ok(Ok(stream)) => {
+                        let _ = tx.send(stream).await;
+                    }
+                    Ok(Err(e)) => eprintln!("TLS handshake with {} failed: {}", peer, e),
+                    Err(_) => eprintln!("TLS handshake with {} timed out after {:?}", peer, handshake_timeout),
+                }
+            });
+        }
+    });
+
+    stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|stream| (Ok(stream), rx))
+    })
+}
+
+/// Reloads the certificates every time the process receives SIGHUP.
+pub async fn reload_on_hangup(acceptor: Acceptor) {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut hangup = signal(SignalKind::hangup()).expect("failed to install SIGHUP handler");
+        while hangup.recv().await.is_some() {
+            match acceptor.reload() {
+                Ok(()) => println!("Reloaded TLS certificates from {}", acceptor.files.cert.display()),
+                Err(e) => eprintln!("Keeping the current TLS certificates, reload failed: {}", e),
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    let _ = acceptor;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+    use std::fs;
+    use std::net::SocketAddr;
+    use std::path::PathBuf;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_rustls::client::TlsStream as ClientStream;
+    use tokio_rustls::rustls::{ClientConfig, ServerName};
+    use tokio_rustls::TlsConnector;
+    use warp::Filter;
+
+    struct Pem {
+        cert: String,
+        key: String,
+    }
+
+    fn self_signed() -> (rcgen::Certificate, Pem) {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let pem = Pem {
+            cert: cert.serialize_pem().unwrap(),
+            key: cert.serialize_private_key_pem(),
+        };
+        (cert, pem)
+    }
+
+    fn authority() -> (rcgen::Certificate, Pem) {
+        let mut params = rcgen::CertificateParams::new(vec![]);
+        params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        let cert = rcgen::Certificate::from_params(params).unwrap();
+        let pem = Pem {
+            cert: cert.serialize_pem().unwrap(),
+            key: cert.serialize_private_key_pem(),
+        };
+        (cert, pem)
+    }
+
+    fn signed_by(ca: &rcgen::Certificate) -> Pem {
+        let cert = rcgen::generate_simple_self_signed(vec!["client".to_string()]).unwrap();
+        Pem {
+            cert: cert.serialize_pem_with_signer(ca).unwrap(),
+            key: cert.serialize_private_key_pem(),
+        }
+    }
+
+    fn temp_path(test: &str, name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("holodeck-{}-{}-{}", std::process::id(), test, name))
+    }
+
+    fn write_files(test: &str, pem: &Pem, client_ca: Option<&Pem>) -> TlsFiles {
+        let files = TlsFiles {
+            cert: temp_path(test, "cert.pem"),
+            key: temp_path(test, "key.pem"),
+            client_ca: client_ca.map(|_| temp_path(test, "ca.pem")),
+        };
+        fs::write(&files.cert, &pem.cert).unwrap();
+        fs::write(&files.key, &pem.key).unwrap();
+        if let (Some(path), Some(ca)) = (&files.client_ca, client_ca) {
+            fs::write(path, &ca.cert).unwrap();
+        }
+        files
+    }
+
+    async fn serve(acceptor: Acceptor) -> SocketAddr {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let routes = warp::path("bridge").map(|| "Engaged");
+        tokio::spawn(warp::serve(routes).serve_incoming(incoming(listener, acceptor, Duration::from_millis(500))));
+        addr
+    }
+
+    async fn connect(addr: SocketAddr, server: &Pem, client: Option<&Pem>) -> io::Result<ClientStream<TcpStream>> {
+        let mut roots = RootCertStore::empty();
+        for cert in rustls_pemfile::certs(&mut server.cert.as_bytes()).unwrap() {
+            roots.add(&Certificate(cert)).unwrap();
+        }
+        let builder = ClientConfig::builder().with_safe_defaults().with_root_certificates(roots);
+        let config = match client {
+            Some(pem) => {
+                let certs = rustls_pemfile::certs(&mut pem.cert.as_bytes()).unwrap();
+                let key = rustls_pemfile::pkcs8_private_keys(&mut pem.key.as_bytes()).unwrap().remove(0);
+                builder
+                    .with_client_auth_cert(certs.into_iter().map(Certificate).collect(), PrivateKey(key))
+                    .unwrap()
+            }
+            None => builder.with_no_client_auth(),
+        };
+
+        let tcp = TcpStream::connect(addr).await?;
+        let domain = ServerName::try_from("localhost").unwrap();
+        let mut stream = TlsConnector::from(Arc::new(config)).connect(domain, tcp).await?;
+        // TLS 1.3 reports a rejected client certificate only once the server answers
+        ask(&mut stream).await?;
+        Ok(stream)
+    }
+
+    async fn ask(stream: &mut ClientStream<TcpStream>) -> io::Result<String> {
+        stream.write_all(b"GET /bridge HTTP/1.1\r\nhost: localhost\r\n\r\n").await?;
+        let mut response = Vec::new();
+        let mut buf = [0; 1024];
+        while !response.ends_with(b"Engaged") {
+            let n = stream.read(&mut buf).await?;
+            if n == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            response.extend_from_slice(&buf[..n]);
+        }
+        Ok(String::from_utf8(response).unwrap())
+    }
+
+    #[tokio::test]
+    async fn try_https() {
+        let (_, pem) = self_signed();
+        let acceptor = Acceptor::new(write_files("https", &pem, None)).unwrap();
+        let addr = serve(acceptor).await;
+
+        let mut stream = connect(addr, &pem, None).await.unwrap();
+        assert!(ask(&mut stream).await.unwrap().starts_with("HTTP/1.1 200 OK"));
+
+        // A client that never starts its handshake is hung up on
+        let mut silent = TcpStream::connect(addr).await.unwrap();
+        let read = tokio::time::timeout(Duration::from_secs(5), silent.read(&mut [0; 1])).await;
+        assert_eq!(read.unwrap().unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn try_reload() {
+        let (_, old) = self_signed();
+        let files = write_files("reload", &old, None);
+        let acceptor = Acceptor::new(files.clone()).unwrap();
+        let addr = serve(acceptor.clone()).await;
+
+        let mut before = connect(addr, &old, None).await.unwrap();
+
+        let (_, new) = self_signed();
+        write_files("reload", &new, None);
+        acceptor.reload().unwrap();
+
+        // The connection accepted before the reload keeps working
+        assert!(ask(&mut before).await.unwrap().starts_with("HTTP/1.1 200 OK"));
+        // New connections get the new certificate
+        assert!(connect(addr, &new, None).await.is_ok());
+        assert!(connect(addr, &old, None).await.is_err());
+
+        // A broken file leaves the current certificate in place
+        fs::write(&files.key, "not a key").unwrap();
+        assert!(acceptor.reload().is_err());
+        assert!(connect(addr, &new, None).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn try_client_auth() {
+        let (ca, ca_pem) = authority();
+        let (_, pem) = self_signed();
+        let acceptor = Acceptor::new(write_files("mtls", &pem, Some(&ca_pem))).unwrap();
+        let addr = serve(acceptor).await;
+
+        assert!(connect(addr, &pem, None).await.is_err());
+        assert!(connect(addr, &pem, Some(&signed_by(&ca))).await.is_ok());
+
+        let (stranger, _) = authority();
+        assert!(connect(addr, &pem, Some(&signed_by(&stranger))).await.is_err());
+    }
+
+    // Plaintext without `tls`, else trusting `server` and presenting `client`
+    async fn grpc_list(addr: SocketAddr, tls: Option<(&Pem, Option<&Pem>)>) -> Result<(), Box<dyn std::error::Error>> {
+        use crate::libs::grpc::pb::{holodeck_client::HolodeckClient, ListRequest};
+        use tonic::transport::{Channel, ClientTlsConfig};
+
+        let channel = match tls {
+            Some((server, client)) => {
+                let mut config = ClientTlsConfig::new()
+                    .ca_certificate(transport::Certificate::from_pem(&server.cert))
+                    .domain_name("localhost");
+                if let Some(pem) = client {
+                    config = config.identity(Identity::from_pem(&pem.cert, &pem.key));
+                }
+                Channel::from_shared(format!("https://{}", addr))?.tls_config(config)?
+            }
+            None => Channel::from_shared(format!("http://{}", addr))?,
+        };
+        HolodeckClient::new(channel.connect().await?).list(ListRequest::default()).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn try_grpc() {
+        use crate::libs::safety::Policy;
+        use crate::libs::{grpc, models, rooms};
+
+        let (ca, ca_pem) = authority();
+        let (_, pem) = self_signed();
+        let server = grpc_server(&write_files("grpc", &pem, Some(&ca_pem))).unwrap();
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let policy = Arc::new(Policy::default());
+        let incoming = transport::server::TcpIncoming::from_listener(listener, true, None).unwrap();
+        tokio::spawn(grpc::serve(server, incoming, models::new_db(), rooms::new_db(), policy, futures_util::future::pending()));
+
+        // Same certificate and client CA as HTTPS, and no plaintext
+        assert!(grpc_list(addr, Some((&pem, Some(&signed_by(&ca))))).await.is_ok());
+        assert!(grpc_list(addr, Some((&pem, None))).await.is_err());
+        let (stranger, _) = authority();
+        assert!(grpc_list(addr, Some((&pem, Some(&signed_by(&stranger))))).await.is_err());
+        assert!(grpc_list(addr, None).await.is_err());
+
+        // A key that cannot be read fails startup instead of falling back to plaintext
+        let files = write_files("grpc-broken", &pem, None);
+        fs::write(&files.key, "not a key").unwrap();
+        assert!(grpc_server(&files).is_err());
+    }
+}
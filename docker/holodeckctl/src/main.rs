@@ -0,0 +1,315 @@
+use clap::{Parser, Subcommand, ValueEnum};
+use holodeck_client::backup::Archive;
+use holodeck_client::models::{NewName, Simulation};
+use holodeck_client::validation::Validate;
+use holodeck_client::Client;
+use std::error::Error;
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process;
+
+/// Operate a holodeck server.
+#[derive(Debug, Parser)]
+#[command(name = "holodeckctl", version)]
+struct Cli {
+    /// Server to talk to
+    #[arg(long, env = "HOLODECK_URL", default_value = "http://localhost:3030", global = true)]
+    url: String,
+
+    /// Who is running the command, for the server's safety rules
+    #[arg(long, env = "HOLODECK_USER", global = true)]
+    user: Option<String>,
+
+    /// Roles of the user, comma separated
+    #[arg(long, env = "HOLODECK_ROLES", value_delimiter = ',', global = true)]
+    roles: Vec<String>,
+
+    /// People who approved the command, comma separated
+    #[arg(long, value_delimiter = ',', global = true)]
+    approved_by: Vec<String>,
+
+    /// How simulations are printed
+    #[arg(short, long, value_enum, default_value_t = Output::Table, global = true)]
+    output: Output,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum Output {
+    Table,
+    Json,
+}
+
+#[derive(Debug, PartialEq, Subcommand)]
+enum Command {
+    /// List every simulation, or those matching every --tag
+    List {
+        /// Only simulations with this tag; `!tag` for those without it. Repeatable
+        #[arg(long = "tag")]
+        tags: Vec<String>,
+    },
+    /// Count how many simulations carry each tag
+    Tags,
+    /// Show one simulation
+    Get { id: u64 },
+    /// Create a simulation
+    Create { id: u64, name: String },
+    /// Rename a simulation, creating it when missing
+    Rename { id: u64, name: String },
+    /// Delete a simulation
+    Delete { id: u64 },
+    /// Load the simulations in a JSON file written by `export`, overwriting the ones that already exist
+    Import {
+        file: PathBuf,
+        /// Replace the whole catalogue with the file instead, deleting every simulation it does not
+        /// hold and bringing back characters and lifecycle state as exported; takes the admin role
+        #[arg(long)]
+        replace: bool,
+    },
+    /// Print every simulation, with its characters, as JSON ready for `import`
+    Export,
+}
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+    if let Err(e) = run(cli, &mut io::stdout()).await {
+        eprintln!("holodeckctl: {}", e);
+        process::exit(1);
+    }
+}
+
+async fn run(cli: Cli, out: &mut impl Write) -> Result<(), Box<dyn Error>> {
+    let mut builder = Client::builder(cli.url);
+    if let Some(user) = &cli.user {
+        builder = builder.user(user);
+    }
+    if !cli.roles.is_empty() {
+        builder = builder.roles(&cli.roles.iter().map(String::as_str).collect::<Vec<_>>());
+    }
+    if !cli.approved_by.is_empty() {
+        builder = builder.approved_by(&cli.approved_by.iter().map(String::as_str).collect::<Vec<_>>());
+    }
+    let client = builder.build()?;
+
+    match cli.command {
+        Command::List { tags } => {
+            let sims = if tags.is_empty() {
+                client.list().await?
+            } else {
+                client.list_tagged(&tags.iter().map(String::as_str).collect::<Vec<_>>()).await?
+            };
+            print(out, cli.output, &sorted(sims))?;
+        }
+        Command::Tags => {
+            let counts = client.tags().await?;
+            match cli.output {
+                Output::Table => {
+                    let width = counts.keys().map(|tag| tag.len()).max().unwrap_or(0).max("TAG".len());
+                    writeln!(out, "{:<width$}  COUNT", "TAG", width = width)?;
+                    for (tag, count) in &counts {
+                        writeln!(out, "{:<width$}  {}", tag, count, width = width)?;
+                    }
+                }
+                Output::Json => writeln!(out, "{}", serde_json::to_string_pretty(&counts)?)?,
+            }
+        }
+        Command::Get { id } => {
+            let sim = client.get(id).await?;
+            print(out, cli.output, &[sim])?;
+        }
+        Command::Create { id, name } => {
+            let sim = client.create(&Simulation::new(id, name)).await?;
+            print(out, cli.output, &[sim])?;
+        }
+        Command::Rename { id, name } => {
+            let sim = client.update(id, &name).await?;
+            print(out, cli.output, &[sim])?;
+        }
+        Command::Delete { id } => {
+            client.delete(id).await?;
+            if cli.output == Output::Table {
+                writeln!(out, "Simulation #{} deleted.", id)?;
+            }
+        }
+        Command::Import { file, replace } => {
+            let sims: Vec<Simulation> = serde_json::from_slice(&fs::read(&file)?)?;
+
+            // Check the whole file first so a bad entry does not leave the import half done
+            let mut problems = Vec::new();
+            for sim in &sims {
+                if let Err(invalid) = sim.validate() {
+                    for error in invalid.errors {
+                        problems.push(format!("#{} {}: {}", sim.id, error.field, error.message));
+                    }
+                }
+            }
+            if !problems.is_empty() {
+                return Err(format!("{} is not valid: {}", file.display(), problems.join("; ")).into());
+            }
+
+            if replace {
+                // One restore, so the catalogue is swapped whole and nothing the file holds is lost on the way
+                client.restore(&Archive::of(&sims)).await?;
+                eprintln!("Replaced the catalogue with {} simulation(s) from {}.", sims.len(), file.display());
+            } else {
+                for sim in &sims {
+                    client.update_with(sim.id, &NewName::from(sim)).await?;
+                }
+                eprintln!("Imported {} simulation(s) from {}.", sims.len(), file.display());
+            }
+        }
+        Command::Export => {
+            let sims = sorted(client.list_with_characters().await?);
+            print(out, Output::Json, &sims)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn sorted(mut sims: Vec<Simulation>) -> Vec<Simulation> {
+    sims.sort_by_key(|sim| sim.id);
+    sims
+}
+
+fn print(out: &mut impl Write, output: Output, sims: &[Simulation]) -> io::Result<()> {
+    match output {
+        Output::Table => write!(out, "{}", table(sims)),
+        Output::Json => writeln!(out, "{}", serde_json::to_string_pretty(sims)?),
+    }
+}
+
+fn table(sims: &[Simulation]) -> String {
+    let width = sims
+        .iter()
+        .map(|sim| sim.id.to_string().len())
+        .max()
+        .unwrap_or(0)
+        .max("ID".len());
+
+    let mut table = format!("{:<width$}  NAME\n", "ID", width = width);
+    for sim in sims {
+        table.push_str(&format!("{:>width$}  {}\n", sim.id, sim.name, width = width));
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+    use holodeck_client::lifecycle::State;
+
+    fn cli(args: &[&str]) -> Cli {
+        Cli::try_parse_from(std::iter::once("holodeckctl").chain(args.iter().copied())).unwrap()
+    }
+
+    #[test]
+    fn try_parse() {
+        Cli::command().debug_assert();
+
+        let parsed = cli(&["--url", "http://holodeck:3030", "rename", "3", "The Short Hello", "-o", "json"]);
+        assert_eq!(parsed.url, "http://holodeck:3030");
+        assert_eq!(parsed.output, Output::Json);
+        assert_eq!(parsed.command, Command::Rename { id: 3, name: "The Short Hello".to_string() });
+
+        assert!(Cli::try_parse_from(["holodeckctl", "get", "three"]).is_err());
+
+        let parsed = cli(&["list", "--tag", "noir", "--tag", "!combat"]);
+        assert_eq!(parsed.command, Command::List { tags: vec!["noir".to_string(), "!combat".to_string()] });
+
+        let parsed = cli(&["get", "3", "--url", "http://holodeck:3030"]);
+        assert_eq!(parsed.url, "http://holodeck:3030");
+
+        let parsed = cli(&["list", "--user", "picard", "--roles", "admin,captain", "--approved-by", "riker"]);
+        assert_eq!(parsed.user.as_deref(), Some("picard"));
+        assert_eq!(parsed.roles, ["admin", "captain"]);
+        assert_eq!(parsed.approved_by, ["riker"]);
+    }
+
+    #[test]
+    fn try_table() {
+        let sims = [
+            Simulation::new(1, "The Big Goodbye"),
+            Simulation::new(112, "Bride Of Chaotica!"),
+        ];
+        assert_eq!(
+            table(&sims),
+            "ID   NAME\n  1  The Big Goodbye\n112  Bride Of Chaotica!\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn try_import_export() {
+        let (addr, server) = warp::serve(holodeck::api()).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        let url = format!("http://{}", addr);
+
+        let exported = r#"[
+  {"id": 1, "name": "The Big Goodbye", "genre": "Noir", "tags": ["noir"], "state": "running",
+   "characters": [{"id": 1, "name": "Dixon Hill", "role": "detective"}]},
+  {"id": 2, "name": "Bride Of Chaotica!"}
+]"#;
+        let file = std::env::temp_dir().join(format!("holodeckctl-{}.json", process::id()));
+        let path = file.to_str().unwrap();
+        fs::write(&file, exported).unwrap();
+
+        // A plain import adds to what is there, and leaves characters and state to the server
+        run(cli(&["--url", &url, "create", "3", "Fair Haven"]), &mut Vec::new()).await.unwrap();
+        run(cli(&["--url", &url, "import", path]), &mut Vec::new()).await.unwrap();
+        let mut out = Vec::new();
+        run(cli(&["--url", &url, "export"]), &mut out).await.unwrap();
+        let sims: Vec<Simulation> = serde_json::from_slice(&out).unwrap();
+        assert_eq!(sims.iter().map(|sim| sim.id).collect::<Vec<_>>(), [1, 2, 3]);
+        assert_eq!(sims[0].genre.as_deref(), Some("Noir"));
+        assert_eq!(sims[0].state, State::Loaded);
+        assert!(sims[0].characters.is_empty());
+
+        // Replacing takes the admin role
+        let error = run(cli(&["--url", &url, "import", "--replace", path]), &mut Vec::new()).await.unwrap_err();
+        assert!(error.to_string().contains("admin"), "{}", error);
+        run(cli(&["--url", &url, "--roles", "admin", "import", "--replace", path]), &mut Vec::new()).await.unwrap();
+
+        let mut out = Vec::new();
+        run(cli(&["--url", &url, "export"]), &mut out).await.unwrap();
+        let sims: Vec<Simulation> = serde_json::from_slice(&out).unwrap();
+        assert_eq!(sims.len(), 2);
+        assert_eq!((sims[0].id, sims[0].name.as_str()), (1, "The Big Goodbye"));
+        assert_eq!(sims[0].genre.as_deref(), Some("Noir"));
+        assert!(sims[0].tags.contains("noir"));
+        assert_eq!(sims[0].state, State::Running);
+        assert_eq!(sims[0].characters[0].name, "Dixon Hill");
+
+        // What is exported imports back unchanged
+        fs::write(&file, &out).unwrap();
+        run(cli(&["import", "--replace", path, "--url", &url, "--roles", "admin"]), &mut Vec::new()).await.unwrap();
+        let mut again = Vec::new();
+        run(cli(&["export", "--url", &url]), &mut again).await.unwrap();
+        assert_eq!(String::from_utf8(again).unwrap(), String::from_utf8(out).unwrap());
+
+        let mut out = Vec::new();
+        run(cli(&["--url", &url, "tags"]), &mut out).await.unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "TAG   COUNT\nnoir  1\n");
+
+        let mut out = Vec::new();
+        run(cli(&["--url", &url, "list", "--tag", "!noir"]), &mut out).await.unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "ID  NAME\n 2  Bride Of Chaotica!\n");
+
+        let mut out = Vec::new();
+        run(cli(&["--url", &url, "get", "2"]), &mut out).await.unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "ID  NAME\n 2  Bride Of Chaotica!\n");
+
+        assert!(run(cli(&["--url", &url, "create", "1", "Again"]), &mut Vec::new()).await.is_err());
+
+        fs::write(&file, r#"[{"id": 3, "name": "Fair Haven"}, {"id": 0, "name": " "}]"#).unwrap();
+        let error = run(cli(&["--url", &url, "import", path]), &mut Vec::new())
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("#0 id: 0 is reserved; #0 name: must not be blank"));
+        assert!(run(cli(&["--url", &url, "get", "3"]), &mut Vec::new()).await.is_err());
+    }
+}